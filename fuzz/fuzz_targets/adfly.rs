@@ -0,0 +1,19 @@
+//! adf.ly pages and `ysmm` values from untrusted input must never panic.
+//!
+//! Run with `cargo fuzz run adfly fuzz/corpus/adfly` (nightly toolchain).
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use urlexpand::{decode_ysmm, expand_from_response, find_ysmm};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    // a whole page, then the input as a bare value
+    if let Ok(ysmm) = find_ysmm(input) {
+        let _ = decode_ysmm(ysmm);
+    }
+    let _ = decode_ysmm(input);
+    let _ = expand_from_response("adf.ly", "https://adf.ly/fuzz", 200, &[], input);
+});
@@ -0,0 +1,272 @@
+//! Affiliate and referral tags.
+//!
+//! Affiliate links (`amzn.to`, `prf.hn`, `s.click.aliexpress.com`, ...) lead
+//! to destinations carrying the affiliate's tag: Amazon's `tag=`,
+//! AliExpress' `aff_*`, Partnerize's `camref`, ... [`find_affiliates`] reads
+//! them, for [`Expansion::affiliates`](crate::Expansion::affiliates), and
+//! [`strip_affiliates`] removes them, for
+//! [`Options::strip_affiliates`](crate::Options::strip_affiliates).
+//!
+//! The programs are listed in [`PROGRAMS`].
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::{amazon::MARKETPLACES, idn::host_matches};
+
+/// An affiliate program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AffiliateProgram {
+    /// Amazon Associates
+    Amazon,
+    /// AliExpress Portals
+    AliExpress,
+    /// Awin
+    Awin,
+    /// B&H Photo Video
+    BhPhotoVideo,
+    /// Booking.com Affiliate Partner Programme
+    Booking,
+    /// CJ (Commission Junction)
+    CommissionJunction,
+    /// eBay Partner Network
+    Ebay,
+    /// Impact
+    Impact,
+    /// Partnerize
+    Partnerize,
+    /// Rakuten Advertising (LinkShare)
+    Rakuten,
+    /// ShareASale
+    ShareASale,
+    /// Skimlinks
+    Skimlinks,
+}
+
+/// An affiliate tag found on a link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Affiliate {
+    /// The program
+    pub program: AffiliateProgram,
+    /// The parameter holding the tag
+    pub parameter: String,
+    /// The tag: the affiliate's ID, or the click's for the programs that
+    /// only pass that on
+    pub tag: String,
+}
+
+/// Where an affiliate program puts its tags.
+struct Program {
+    program: AffiliateProgram,
+    /// Domains, with their subdomains; empty for the parameters passed on
+    /// to any merchant
+    domains: &'static [&'static str],
+    /// Parameters holding the tag, by preference, lowercase; they are read
+    /// in the query, and in `name:value` path segments
+    tags: &'static [&'static str],
+    /// Every affiliate parameter, lowercase; a trailing `*` matches any suffix
+    parameters: &'static [&'static str],
+}
+
+static PROGRAMS: &[Program] = &[
+    Program {
+        program: AffiliateProgram::Amazon,
+        domains: MARKETPLACES,
+        tags: &["tag"],
+        parameters: &["tag", "ascsubtag", "linkcode", "linkid", "camp", "creative", "creativeasin"],
+    },
+    Program {
+        program: AffiliateProgram::AliExpress,
+        domains: &["aliexpress.com", "aliexpress.us", "aliexpress.ru"],
+        tags: &["aff_fsk", "aff_short_key", "aff_trace_key", "aff_fcid"],
+        parameters: &["aff_*"],
+    },
+    Program {
+        program: AffiliateProgram::Awin,
+        domains: &["awin1.com"],
+        tags: &["awinaffid"],
+        parameters: &["awinaffid", "awinmid", "clickref*"],
+    },
+    Program {
+        program: AffiliateProgram::Awin,
+        domains: &[],
+        tags: &["awc"],
+        parameters: &["awc"],
+    },
+    Program {
+        program: AffiliateProgram::BhPhotoVideo,
+        domains: &["bhphotovideo.com"],
+        tags: &["kbid", "bi"],
+        parameters: &["bi", "kbid"],
+    },
+    Program {
+        program: AffiliateProgram::Booking,
+        domains: &["booking.com"],
+        tags: &["aid"],
+        parameters: &["aid"],
+    },
+    Program {
+        program: AffiliateProgram::CommissionJunction,
+        domains: &[],
+        tags: &["cjevent"],
+        parameters: &["cjevent", "cjdata"],
+    },
+    Program {
+        program: AffiliateProgram::Ebay,
+        domains: &[
+            "ebay.com", "ebay.ca", "ebay.co.uk", "ebay.ie", "ebay.de", "ebay.at", "ebay.ch", "ebay.fr", "ebay.be",
+            "ebay.nl", "ebay.it", "ebay.es", "ebay.pl", "ebay.com.au", "ebay.com.hk", "ebay.com.sg", "ebay.com.my",
+            "ebay.ph",
+        ],
+        tags: &["campid"],
+        parameters: &["campid", "customid", "mkcid", "mkevt", "mkrid", "toolid"],
+    },
+    Program {
+        program: AffiliateProgram::Impact,
+        domains: &[],
+        tags: &["irclickid", "irgwc"],
+        parameters: &["irclickid", "irgwc", "irpid"],
+    },
+    Program {
+        program: AffiliateProgram::Partnerize,
+        domains: &["prf.hn"],
+        tags: &["camref"],
+        parameters: &["camref", "pubref", "clickref"],
+    },
+    Program {
+        program: AffiliateProgram::Rakuten,
+        domains: &["linksynergy.com"],
+        tags: &["id"],
+        parameters: &["id", "mid", "offerid", "type", "subid"],
+    },
+    Program {
+        program: AffiliateProgram::Rakuten,
+        domains: &[],
+        tags: &["raneaid", "ransiteid"],
+        parameters: &["raneaid", "ranlinkid", "ranmid", "ransiteid"],
+    },
+    Program {
+        program: AffiliateProgram::ShareASale,
+        domains: &["shareasale.com"],
+        tags: &["u"],
+        parameters: &["u", "m", "b", "afftrack"],
+    },
+    Program {
+        program: AffiliateProgram::ShareASale,
+        domains: &[],
+        tags: &["sscid"],
+        parameters: &["sscid"],
+    },
+    Program {
+        program: AffiliateProgram::Skimlinks,
+        domains: &["skimresources.com", "redirectingat.com"],
+        tags: &["id"],
+        parameters: &["id", "xs", "xcust", "sref"],
+    },
+];
+
+impl Program {
+    fn serves(&self, host: &str) -> bool {
+        //! Whether the program puts its tags on links to a host.
+        self.domains.is_empty() || self.domains.iter().any(|d| host_matches(host, d))
+    }
+
+    fn is_parameter(&self, name: &str) -> bool {
+        //! Whether a parameter (lowercase) is one of the program's.
+        self.parameters.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == *p,
+        })
+    }
+}
+
+pub fn find_affiliates(url: &str) -> Vec<Affiliate> {
+    //! Reads the affiliate tags of a link.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - An absolute URL
+    //!
+    //! # Returns
+    //!
+    //! Returns the tag of every program found on the link, usually one.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{find_affiliates, AffiliateProgram};
+    //!
+    //! let found = find_affiliates("https://www.amazon.co.uk/dp/B08N5WRWNW?tag=deals-21&linkCode=ll1");
+    //! assert_eq!((found[0].program, found[0].tag.as_str()), (AffiliateProgram::Amazon, "deals-21"));
+    //! let found = find_affiliates("https://prf.hn/click/camref:1011lkMn/pubref:spring");
+    //! assert_eq!((found[0].program, found[0].tag.as_str()), (AffiliateProgram::Partnerize, "1011lkMn"));
+    //! ```
+    let Ok(parsed) = Url::parse(url.trim()) else {
+        return vec![];
+    };
+    let Some(host) = parsed.host_str().map(str::to_ascii_lowercase) else {
+        return vec![];
+    };
+    let mut values: Vec<(String, String)> =
+        parsed.query_pairs().map(|(k, v)| (k.to_ascii_lowercase(), v.into_owned())).collect();
+    values.extend(parsed.path_segments().into_iter().flatten().filter_map(|segment| {
+        let (name, value) = segment.split_once(':')?;
+        Some((name.to_ascii_lowercase(), percent_decode_str(value).decode_utf8_lossy().into_owned()))
+    }));
+
+    let mut found: Vec<Affiliate> = vec![];
+    for program in PROGRAMS.iter().filter(|p| p.serves(&host)) {
+        let tag = program.tags.iter().find_map(|name| {
+            values.iter().find(|(k, v)| k == name && !v.trim().is_empty()).map(|(k, v)| (k, v.trim()))
+        });
+        if let Some((parameter, tag)) = tag
+            && !found.iter().any(|a| a.program == program.program)
+        {
+            found.push(Affiliate { program: program.program, parameter: parameter.clone(), tag: tag.into() });
+        }
+    }
+    found
+}
+
+pub fn strip_affiliates(url: &str) -> String {
+    //! Removes the affiliate parameters of a link.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - An absolute URL
+    //!
+    //! # Returns
+    //!
+    //! Returns the URL without the query parameters of the affiliate programs
+    //! serving its host; the other parameters are kept as written. What isn't
+    //! a URL is returned as is.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::strip_affiliates;
+    //!
+    //! let url = "https://www.amazon.com/dp/B08N5WRWNW?tag=deals-20&th=1&linkCode=ll1";
+    //! assert_eq!(strip_affiliates(url), "https://www.amazon.com/dp/B08N5WRWNW?th=1");
+    //! ```
+    let Ok(mut parsed) = Url::parse(url.trim()) else {
+        return url.into();
+    };
+    let Some(host) = parsed.host_str().map(str::to_ascii_lowercase) else {
+        return url.into();
+    };
+    let Some(query) = parsed.query() else {
+        return url.into();
+    };
+    let programs: Vec<&Program> = PROGRAMS.iter().filter(|p| p.serves(&host)).collect();
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|p| {
+            let name = p.split('=').next().unwrap_or_default().replace('+', " ");
+            let name = percent_decode_str(&name).decode_utf8_lossy().to_ascii_lowercase();
+            !p.is_empty() && !programs.iter().any(|program| program.is_parameter(&name))
+        })
+        .collect();
+    let kept = kept.join("&");
+    parsed.set_query(Some(&kept).filter(|q| !q.is_empty()).map(String::as_str));
+    parsed.into()
+}
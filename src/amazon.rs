@@ -0,0 +1,103 @@
+//! Amazon product pages.
+//!
+//! `amzn.to` and `amzn.id` lead to product pages in many shapes: `/dp/`,
+//! `/gp/product/`, `/exec/obidos/ASIN/`, the mobile `/gp/aw/d/`, with or
+//! without a slug before them, and with session and tracking parameters.
+//! [`parse_amazon_product`] reads the marketplace and ASIN of any of them, for
+//! [`Expansion::amazon`](crate::Expansion::amazon); their canonical form (see
+//! [`canonicalize`](crate::canonicalize)) is `https://www.amazon.<tld>/dp/<ASIN>`.
+use regex::Regex;
+use std::sync::LazyLock;
+use url::Url;
+
+/// The product paths, the ASIN following the marker.
+static RE_PRODUCT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)/(?:dp(?:/product)?|gp/product|gp/aw/d|gp/offer-listing|exec/obidos/asin|exec/obidos/tg/detail/-|o/asin)/([a-z0-9]{10})(?:[/;]|$)",
+    )
+    .expect("valid product regex")
+});
+
+/// The Amazon marketplaces, by domain.
+pub(crate) static MARKETPLACES: &[&str] = &[
+    "amazon.com",
+    "amazon.ca",
+    "amazon.com.mx",
+    "amazon.com.br",
+    "amazon.co.uk",
+    "amazon.ie",
+    "amazon.de",
+    "amazon.fr",
+    "amazon.it",
+    "amazon.es",
+    "amazon.nl",
+    "amazon.com.be",
+    "amazon.se",
+    "amazon.pl",
+    "amazon.com.tr",
+    "amazon.ae",
+    "amazon.sa",
+    "amazon.eg",
+    "amazon.in",
+    "amazon.co.jp",
+    "amazon.sg",
+    "amazon.com.au",
+    "amazon.cn",
+];
+
+/// An Amazon product page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AmazonProduct {
+    /// The marketplace, as its domain (`amazon.com`, `amazon.co.uk`, ...)
+    pub marketplace: String,
+    /// The product's ASIN, uppercase
+    pub asin: String,
+}
+
+impl AmazonProduct {
+    pub fn url(&self) -> String {
+        //! The canonical URL of the product page,
+        //! `https://www.amazon.<tld>/dp/<ASIN>`.
+        format!("https://www.{}/dp/{}", self.marketplace, self.asin)
+    }
+}
+
+pub fn parse_amazon_product(url: &str) -> Option<AmazonProduct> {
+    //! Reads the marketplace and ASIN of an Amazon product page.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - An absolute URL
+    //!
+    //! # Returns
+    //!
+    //! Returns the product, or `None` when the URL isn't a product page of an
+    //! Amazon marketplace.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::parse_amazon_product;
+    //!
+    //! let url = "https://www.amazon.co.uk/Some-Product-Name/dp/B08N5WRWNW/ref=sr_1_3?crid=2X&keywords=x&sr=8-3";
+    //! let product = parse_amazon_product(url).unwrap();
+    //! assert_eq!((product.marketplace.as_str(), product.asin.as_str()), ("amazon.co.uk", "B08N5WRWNW"));
+    //! assert_eq!(product.url(), "https://www.amazon.co.uk/dp/B08N5WRWNW");
+    //! ```
+    let parsed = Url::parse(url.trim()).ok()?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return None;
+    }
+    let marketplace = marketplace(parsed.host_str()?)?;
+    let asin = RE_PRODUCT.captures(parsed.path())?[1].to_ascii_uppercase();
+    Some(AmazonProduct { marketplace: marketplace.to_string(), asin })
+}
+
+pub(crate) fn marketplace(host: &str) -> Option<&'static str> {
+    //! The marketplace of an Amazon host: one of [`MARKETPLACES`], bare or
+    //! under `www.` or `smile.`.
+    let host = host.to_ascii_lowercase();
+    let host = host.trim_end_matches('.');
+    let host = ["www.", "smile."].iter().find_map(|p| host.strip_prefix(p)).unwrap_or(host);
+    MARKETPLACES.iter().copied().find(|&domain| domain == host)
+}
@@ -0,0 +1,150 @@
+//! Canonical form of destination URLs.
+//!
+//! The same destination is reached with many spellings: tracking parameters
+//! (`utm_*`, `fbclid`, `gclid`, ...) and per-click tokens, upper case hosts,
+//! default ports, trailing slashes and parameters in any order.
+//! [`canonicalize`] removes these differences, so that destinations can be
+//! compared and deduplicated; [`Expansion::canonical`](crate::Expansion::canonical)
+//! is the canonical form of every destination.
+//!
+//! Amazon product pages are reduced to `https://www.amazon.<tld>/dp/<ASIN>`
+//! (see [`parse_amazon_product`](crate::parse_amazon_product)).
+//!
+//! The tracking parameters are listed in [`TRACKING`]; more can be added at
+//! runtime with [`add_tracking_parameter`].
+use percent_encoding::percent_decode_str;
+use std::sync::{LazyLock, RwLock};
+use url::Url;
+
+use crate::parse_amazon_product;
+
+/// Tracking parameters, lowercase; a trailing `*` matches any suffix.
+static TRACKING: &[&str] = &[
+    // campaign tags: Google Analytics, Matomo / Piwik, HubSpot ads
+    "utm_*",
+    "mtm_*",
+    "pk_*",
+    "hsa_*",
+    "_ga",
+    "_gl",
+    // click identifiers of the ad networks
+    "fbclid",
+    "gclid",
+    "gclsrc",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "yclid",
+    "twclid",
+    "ttclid",
+    "li_fat_id",
+    "epik",
+    "rb_clickid",
+    "srsltid",
+    // email marketing: Mailchimp, HubSpot, Marketo, Vero, Klaviyo
+    "mc_eid",
+    "mc_cid",
+    "_hsenc",
+    "_hsmi",
+    "__hssc",
+    "__hstc",
+    "__hsfp",
+    "mkt_tok",
+    "vero_id",
+    "vero_conv",
+    "_kx",
+    // share tokens
+    "igshid",
+    "igsh",
+    "ref_src",
+    "ref_url",
+    "s_cid",
+    "sc_cid",
+];
+
+/// Tracking parameters added at runtime, lowercase.
+static ADDED: LazyLock<RwLock<Vec<String>>> = LazyLock::new(Default::default);
+
+pub fn add_tracking_parameter(name: &str) {
+    //! Adds a tracking parameter removed by [`canonicalize`], compared
+    //! case-insensitively; a trailing `*` matches any suffix (`"xtor*"`).
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{add_tracking_parameter, canonicalize};
+    //!
+    //! add_tracking_parameter("cmpid");
+    //! assert_eq!(canonicalize("https://example.com/a?cmpid=nl&id=1"), "https://example.com/a?id=1");
+    //! ```
+    ADDED.write().unwrap_or_else(|e| e.into_inner()).push(name.trim().to_ascii_lowercase());
+}
+
+pub(crate) fn is_tracking(name: &str) -> bool {
+    //! Whether a query parameter is a tracking parameter.
+    let name = name.to_ascii_lowercase();
+    let matches = |pattern: &str| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    };
+    TRACKING.iter().any(|p| matches(p)) || ADDED.read().unwrap_or_else(|e| e.into_inner()).iter().any(|p| matches(p))
+}
+
+pub fn canonicalize(url: &str) -> String {
+    //! The canonical form of a URL.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - An absolute URL
+    //!
+    //! # Returns
+    //!
+    //! Returns the URL without tracking parameters, with a lowercase scheme
+    //! and host, without default port, empty query or fragment, trailing dot
+    //! on the host, or trailing slash (but for the root path), and with its
+    //! parameters sorted by name (the order of repeated parameters is kept).
+    //! Amazon product pages become `https://www.amazon.<tld>/dp/<ASIN>`.
+    //! Other than `http(s)` URLs are only parsed; what isn't a URL is returned
+    //! trimmed.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::canonicalize;
+    //!
+    //! let url = "HTTPS://WWW.Example.COM:443/shop/?utm_source=x&b=2&fbclid=abc&a=1#";
+    //! assert_eq!(canonicalize(url), "https://www.example.com/shop?a=1&b=2");
+    //! ```
+    let Ok(mut parsed) = Url::parse(url.trim()) else {
+        return url.trim().into();
+    };
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return parsed.into();
+    }
+    if let Some(product) = parse_amazon_product(parsed.as_str()) {
+        return product.url();
+    }
+    if let Some(host) = parsed.host_str().and_then(|h| h.strip_suffix('.')).map(String::from) {
+        let _ = parsed.set_host(Some(&host));
+    }
+    let path = parsed.path().trim_end_matches('/').to_string();
+    parsed.set_path(if path.is_empty() { "/" } else { &path });
+
+    // the parameters are kept as written, only their order changes
+    let mut params: Vec<&str> = parsed
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter(|p| {
+            let name = p.split('=').next().unwrap_or_default().replace('+', " ");
+            !is_tracking(&percent_decode_str(&name).decode_utf8_lossy())
+        })
+        .collect();
+    params.sort_by_key(|p| p.split('=').next().unwrap_or_default());
+    let query = params.join("&");
+    parsed.set_query(Some(&query).filter(|q| !q.is_empty()).map(String::as_str));
+    if parsed.fragment() == Some("") {
+        parsed.set_fragment(None);
+    }
+    parsed.into()
+}
@@ -0,0 +1,183 @@
+//! Offline decoders for destinations hidden in redirect pages.
+//!
+//! Redirect pages rarely write the destination in clear. The common tricks are
+//! undone here:
+//!
+//! - base64 (standard or URL-safe, with or without padding), also in a query
+//!   parameter of an intermediate URL (`/go?u=aHR0cHM6Ly8...`)
+//! - hex strings (`68747470...`, `\x68\x74...`)
+//! - percent-encoding, applied once or several times (`https%253A%252F%252F`)
+//! - reversed strings (`moc.elpmaxe//:sptth`)
+//! - the `eval(function(p,a,c,k,e,d){...})` packer
+//!
+//! [`decode_url`] combines them to reveal a URL from a candidate string; the
+//! page-parsing stages of the resolution pipeline apply it to every candidate.
+use base64::{engine::general_purpose, Engine as _};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use std::sync::LazyLock;
+use url::Url;
+
+use crate::js::string_literal;
+
+static RE_PACKED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"eval\s*\(\s*function\s*\(\s*p\s*,\s*a\s*,\s*c\s*,\s*k\s*,\s*e\s*,\s*[dr]\s*\)").expect("valid packer regex")
+});
+static RE_PACKED_ARGS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\}\s*\(\s*['"]"#).expect("valid packer regex"));
+static RE_WORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b\w+\b").expect("valid word regex"));
+
+/// How many decoders [`decode_url`] chains at most.
+const MAX_DEPTH: usize = 3;
+
+pub fn decode_base64(s: &str) -> Option<String> {
+    //! Decodes standard or URL-safe base64, padded or not, into UTF-8 text.
+    let s = s.trim().trim_end_matches('=');
+    general_purpose::STANDARD_NO_PAD
+        .decode(s)
+        .or_else(|_| general_purpose::URL_SAFE_NO_PAD.decode(s))
+        .ok()
+        .and_then(|buf| String::from_utf8(buf).ok())
+}
+
+pub fn decode_hex(s: &str) -> Option<String> {
+    //! Decodes a hex string (`68747470`, `\x68\x74\x74\x70`, `0x68...`) into
+    //! UTF-8 text.
+    let s = s.trim();
+    let s = s.strip_prefix("0x").unwrap_or(s).replace("\\x", "");
+    if s.is_empty() || !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let buf = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    String::from_utf8(buf).ok()
+}
+
+pub fn decode_percent(s: &str) -> String {
+    //! Decodes `%XX` escapes until none is left, undoing double (or deeper)
+    //! percent-encoding.
+    let mut s = s.to_string();
+    for _ in 0..4 {
+        let decoded = percent_decode_str(&s).decode_utf8_lossy().into_owned();
+        if decoded == s {
+            break;
+        }
+        s = decoded;
+    }
+    s
+}
+
+pub fn unpack(source: &str) -> Option<String> {
+    //! Unpacks the first `eval(function(p,a,c,k,e,d){...}(...))` block of a
+    //! script, returning the code it would evaluate.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::unpack;
+    //!
+    //! let packed = r#"eval(function(p,a,c,k,e,d){e=function(c){return c};if(!''.replace(/^/,String)){while(c--){d[c]=k[c]||c}k=[function(e){return d[e]}];e=function(){return'\\w+'};c=1};while(c--){if(k[c]){p=p.replace(new RegExp('\\b'+e(c)+'\\b','g'),k[c])}}return p}('0.1="2://3.4/"',10,5,'location|href|https|example|com'.split('|'),0,{}))"#;
+    //! assert_eq!(unpack(packed).unwrap(), r#"location.href="https://example.com/""#);
+    //! ```
+    let start = RE_PACKED.find(source)?.end();
+    let args = RE_PACKED_ARGS.find(&source[start..])?;
+    // the payload starts at the quote
+    let (payload, rest) = string_literal(&source[start + args.end() - 1..])?;
+    let (radix, rest) = number(rest.trim_start().strip_prefix(',')?)?;
+    let (count, rest) = number(rest.trim_start().strip_prefix(',')?)?;
+    let (keywords, _) = string_literal(rest.trim_start().strip_prefix(',')?.trim_start())?;
+    let keywords = keywords.split('|').collect::<Vec<_>>();
+    if !(2..=62).contains(&radix) {
+        return None;
+    }
+
+    Some(
+        RE_WORD
+            .replace_all(&payload, |caps: &regex::Captures| {
+                let word = &caps[0];
+                unbase(word, radix)
+                    .filter(|&i| i < count)
+                    .and_then(|i| keywords.get(i))
+                    .filter(|k| !k.is_empty())
+                    .map_or_else(|| word.to_string(), |k| k.to_string())
+            })
+            .into_owned(),
+    )
+}
+
+pub fn decode_url(candidate: &str) -> Option<String> {
+    //! Reveals an absolute `http(s)` URL from a candidate string by chaining
+    //! the decoders (e.g. reversed, then base64). A candidate that already is
+    //! a URL is returned as is.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::decode_url;
+    //!
+    //! assert_eq!(decode_url("aHR0cHM6Ly9leGFtcGxlLmNvbS8").as_deref(), Some("https://example.com/"));
+    //! assert_eq!(decode_url("https%253A%252F%252Fexample.com%252F").as_deref(), Some("https://example.com/"));
+    //! assert_eq!(decode_url("/moc.elpmaxe//:sptth").as_deref(), Some("https://example.com/"));
+    //! ```
+    let mut layer = vec![candidate.trim().to_string()];
+    for depth in 0..=MAX_DEPTH {
+        if let Some(found) = layer.iter().find(|s| is_url(s)) {
+            return Some(found.clone());
+        }
+        if depth == MAX_DEPTH {
+            break;
+        }
+        layer = layer
+            .iter()
+            .flat_map(|s| {
+                [
+                    Some(decode_percent(s)).filter(|d| d != s),
+                    decode_base64(s),
+                    decode_hex(s),
+                    Some(s.chars().rev().collect::<String>()),
+                ]
+            })
+            .flatten()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+    }
+    None
+}
+
+pub fn find_url_in_query(url: &str) -> Option<String> {
+    //! The first query parameter of a URL that decodes (see [`decode_url`]) to
+    //! another URL, e.g. the destination of `https://gate.example/go?u=aHR0cHM6Ly8...`.
+    Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find_map(|(_, value)| decode_url(&value))
+}
+
+fn is_url(s: &str) -> bool {
+    //! Whether a string is an absolute `http(s)` URL with a host.
+    let lower = s.get(..8).unwrap_or(s).to_ascii_lowercase();
+    (lower.starts_with("http://") || lower.starts_with("https://"))
+        && !s.contains(char::is_whitespace)
+        && Url::parse(s).is_ok_and(|u| u.host_str().is_some_and(|h| h.contains('.') || h == "localhost"))
+}
+
+fn number(s: &str) -> Option<(usize, &str)> {
+    //! Parses a decimal number; returns it and the rest of the input.
+    let s = s.trim_start();
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+fn unbase(word: &str, radix: usize) -> Option<usize> {
+    //! Decodes a packer keyword index: base 36 digits, then `A-Z` for 36..61.
+    word.chars().try_fold(0usize, |acc, c| {
+        let digit = match c {
+            '0'..='9' => c as usize - '0' as usize,
+            'a'..='z' => c as usize - 'a' as usize + 10,
+            'A'..='Z' => c as usize - 'A' as usize + 36,
+            _ => return None,
+        };
+        (digit < radix).then(|| acc.checked_mul(radix)?.checked_add(digit)).flatten()
+    })
+}
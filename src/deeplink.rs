@@ -0,0 +1,168 @@
+//! Offline parsing of app deep links.
+//!
+//! Deep linking platforms send a click to the app when it is installed, and
+//! to a fallback page otherwise. The targets are carried in the link itself:
+//!
+//! - Firebase Dynamic Links (`*.page.link/?link=...&apn=...&ibi=...&ofl=...`)
+//! - Branch (`*.app.link/...?$fallback_url=...&$ios_url=...&$android_url=...`)
+//! - Android intents (`intent://...#Intent;scheme=...;package=...;S.browser_fallback_url=...;end`),
+//!   as returned by their redirects
+//!
+//! [`expand`](crate::expand) reports them as [`Expansion::deep_link`](crate::Expansion::deep_link),
+//! and goes on with the web fallback.
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::{idn::host_matches, unwrap::web_url};
+
+/// Domains of the Firebase Dynamic Links, with their subdomains.
+static FIREBASE: &[&str] = &["page.link", "app.goo.gl"];
+
+/// Domains of the Branch links, with their subdomains.
+static BRANCH: &[&str] = &["app.link", "test-app.link", "bnc.lt"];
+
+/// Branch parameters holding the web fallback, by preference.
+static BRANCH_WEB: &[&str] = &["$fallback_url", "$desktop_url", "$canonical_url", "$original_url"];
+
+/// The platform a deep link was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeepLinkPlatform {
+    /// Firebase Dynamic Links
+    Firebase,
+    /// Branch
+    Branch,
+    /// A bare Android `intent://` URL
+    Intent,
+}
+
+/// Where a deep link leads on each platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeepLink {
+    /// The platform that built the link
+    pub platform: DeepLinkPlatform,
+    /// The Android app target
+    pub android: Option<AndroidIntent>,
+    /// The iOS app target
+    pub ios: Option<IosLink>,
+    /// The web page opened without the app
+    pub web: Option<String>,
+}
+
+/// The Android app a deep link opens.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AndroidIntent {
+    /// Package name of the app
+    pub package: Option<String>,
+    /// The URI handed to the app
+    pub uri: Option<String>,
+    /// The page opened when the app isn't installed
+    pub fallback: Option<String>,
+}
+
+/// The iOS app a deep link opens.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IosLink {
+    /// Bundle ID of the app
+    pub bundle_id: Option<String>,
+    /// App Store ID of the app
+    pub app_store_id: Option<String>,
+    /// The universal link handed to the app
+    pub url: Option<String>,
+    /// The page opened when the app isn't installed
+    pub fallback: Option<String>,
+}
+
+pub fn parse_deep_link(url: &str) -> Option<DeepLink> {
+    //! Reads the targets of a deep link.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - A Firebase Dynamic Link, a Branch link or an `intent://` URL
+    //!
+    //! # Returns
+    //!
+    //! Returns the targets found, or `None` when the link isn't a deep link or
+    //! carries none (short links need expanding first).
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{parse_deep_link, DeepLinkPlatform};
+    //!
+    //! let url = "https://example.page.link/?link=https://example.com/item&apn=com.example.app";
+    //! let link = parse_deep_link(url).unwrap();
+    //! assert_eq!(link.platform, DeepLinkPlatform::Firebase);
+    //! assert_eq!(link.web.as_deref(), Some("https://example.com/item"));
+    //! assert_eq!(link.android.unwrap().package.as_deref(), Some("com.example.app"));
+    //! ```
+    let parsed = Url::parse(url).ok()?;
+    if parsed.scheme().eq_ignore_ascii_case("intent") {
+        return intent(url);
+    }
+    let host = parsed.host_str()?.to_ascii_lowercase();
+    let param = |name: &str| parsed.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+    let web = |name: &str| param(name).and_then(|v| web_url(&v));
+    let link = if FIREBASE.iter().any(|d| host_matches(&host, d)) {
+        let deep = web("link")?;
+        DeepLink {
+            platform: DeepLinkPlatform::Firebase,
+            android: param("apn").map(|package| AndroidIntent {
+                package: Some(package),
+                uri: Some(deep.clone()),
+                fallback: web("afl"),
+            }),
+            ios: param("ibi").map(|bundle_id| IosLink {
+                bundle_id: Some(bundle_id),
+                app_store_id: param("isi"),
+                url: Some(deep.clone()),
+                fallback: web("ifl"),
+            }),
+            web: web("ofl").or(Some(deep)),
+        }
+    } else if BRANCH.iter().any(|d| host_matches(&host, d)) {
+        let android = web("$android_url");
+        let ios = web("$ios_url");
+        let fallback = BRANCH_WEB.iter().find_map(|name| web(name));
+        if android.is_none() && ios.is_none() && fallback.is_none() {
+            return None;
+        }
+        DeepLink {
+            platform: DeepLinkPlatform::Branch,
+            android: android.map(|fallback| AndroidIntent { fallback: Some(fallback), ..Default::default() }),
+            ios: ios.map(|fallback| IosLink { fallback: Some(fallback), ..Default::default() }),
+            web: fallback,
+        }
+    } else {
+        return None;
+    };
+    Some(link)
+}
+
+fn intent(url: &str) -> Option<DeepLink> {
+    //! Reads an `intent://<rest>#Intent;key=value;...;end` URL; `S.` marks
+    //! string extras.
+    let (target, extras) = url.split_once("#Intent;")?;
+    let rest = &target[target.find(':')? + 1..];
+    let extras = extras.strip_suffix("end").unwrap_or(extras);
+    let extra = |name: &str| {
+        extras
+            .split(';')
+            .find_map(|pair| pair.split_once('=').filter(|(k, _)| *k == name))
+            .and_then(|(_, v)| percent_decode_str(v).decode_utf8().ok())
+            .map(|v| v.into_owned())
+    };
+    let scheme = extra("scheme");
+    let uri = scheme.as_ref().map(|scheme| format!("{scheme}:{rest}"));
+    let fallback = extra("S.browser_fallback_url").and_then(|v| web_url(&v));
+    // an intent for a web URL opens it in the browser without the app
+    let web = fallback.clone().or_else(|| uri.as_deref().and_then(web_url));
+    Some(DeepLink {
+        platform: DeepLinkPlatform::Intent,
+        android: Some(AndroidIntent { package: extra("package"), uri, fallback }),
+        ios: None,
+        web,
+    })
+}
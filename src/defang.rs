@@ -0,0 +1,75 @@
+//! Defanging and refanging of URLs.
+//!
+//! Threat intel feeds and incident tickets write URLs in a "defanged" form so
+//! that they can't be clicked by accident: `hxxps://bit[.]ly/abc`,
+//! `bit(.)ly/abc`, `https[:]//t[.]co/x`. [`refang`] turns the common styles
+//! back into a real URL, and [`defang`] produces the `hxxps://bit[.]ly/abc`
+//! style for results that are pasted back into tickets.
+use regex::Regex;
+use std::sync::LazyLock;
+use url::{Position, Url};
+
+/// (pattern, replacement) pairs applied in order by [`refang`].
+static RE_REFANG: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        // hxxp://, hXXps://, h**p://, meow://
+        (r"(?i)^h(?:xx|\*\*)p(s?)([:\[\(\{])", "http$1$2"),
+        (r"(?i)^meows?([:\[\(\{])", "https$1"),
+        // [://], [:]//, (:)//
+        (r"\s*[\[\(\{]\s*://\s*[\]\)\}]\s*", "://"),
+        (r"\s*[\[\(\{]\s*:\s*[\]\)\}]\s*", ":"),
+        // [.], (.), {.}, [dot], (dot), \.
+        (r"(?i)\s*[\[\(\{]\s*(?:\.|dot)\s*[\]\)\}]\s*", "."),
+        (r"\\\.", "."),
+        // [/]
+        (r"\s*[\[\(\{]\s*/\s*[\]\)\}]\s*", "/"),
+        // [at], [@]
+        (r"(?i)\s*[\[\(\{]\s*(?:@|at)\s*[\]\)\}]\s*", "@"),
+    ]
+    .into_iter()
+    .map(|(p, r)| (Regex::new(p).expect("valid refang regex"), r))
+    .collect()
+});
+
+pub fn refang(url: &str) -> String {
+    //! Turns a defanged URL back into a real one.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::refang;
+    //!
+    //! assert_eq!(refang("hxxps://bit[.]ly/abc"), "https://bit.ly/abc");
+    //! assert_eq!(refang("bit(.)ly/abc"), "bit.ly/abc");
+    //! assert_eq!(refang("https[:]//t[.]co/x"), "https://t.co/x");
+    //! ```
+    RE_REFANG
+        .iter()
+        .fold(url.trim().to_string(), |s, (re, with)| re.replace_all(&s, *with).into_owned())
+}
+
+pub fn defang(url: &str) -> String {
+    //! Defangs a URL: `http(s)` becomes `hxxp(s)` and the dots of the host
+    //! become `[.]`. Strings that aren't URLs get all their dots defanged.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::defang;
+    //!
+    //! assert_eq!(defang("https://www.google.com/a.html"), "hxxps://www[.]google[.]com/a.html");
+    //! ```
+    let Some(parsed) = Url::parse(url).ok().filter(|u| u.host_str().is_some()) else {
+        return url.replace('.', "[.]");
+    };
+    let scheme = match parsed.scheme() {
+        "http" => "hxxp",
+        "https" => "hxxps",
+        other => other,
+    };
+    format!(
+        "{}{}{}{}",
+        scheme,
+        &parsed[Position::AfterScheme..Position::BeforeHost],
+        parsed[Position::BeforeHost..Position::AfterHost].replace('.', "[.]"),
+        &parsed[Position::AfterHost..],
+    )
+}
@@ -0,0 +1,196 @@
+//! What kind of target a link leads to.
+//!
+//! Shorteners don't only lead to web pages: `tel:`, `mailto:` and `sms:`
+//! links, app store listings and app schemes are common destinations too.
+//! The redirects to them are not followed; [`parse_destination`] tells what
+//! they do, for [`Expansion::destination`](crate::Expansion::destination).
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::parse_deep_link;
+
+/// Schemes that don't open an app.
+static NOT_APPS: &[&str] = &[
+    "about",
+    "blob",
+    "chrome",
+    "data",
+    "file",
+    "ftp",
+    "ftps",
+    "javascript",
+    "sftp",
+    "view-source",
+    "ws",
+    "wss",
+];
+
+/// Where a link leads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Destination {
+    /// An `http(s)` page
+    Web {
+        /// Host of the page
+        host: String,
+    },
+    /// A `mailto:` link
+    Email {
+        /// The recipients
+        to: Vec<String>,
+        /// The subject filled in
+        subject: Option<String>,
+        /// The message filled in
+        body: Option<String>,
+    },
+    /// A `tel:` link
+    Phone {
+        /// The number dialled, without visual separators
+        number: String,
+    },
+    /// An `sms:` / `smsto:` / `mms:` link
+    Sms {
+        /// The recipients, without visual separators
+        numbers: Vec<String>,
+        /// The message filled in
+        body: Option<String>,
+    },
+    /// An app store listing (`market://`, `itms-apps://`, Google Play and
+    /// App Store pages)
+    AppStore {
+        /// The store
+        store: Store,
+        /// Package name (Google Play) or numeric ID (App Store) of the app
+        app_id: String,
+    },
+    /// A link opened by an app: a custom scheme or an Android `intent://`
+    AppScheme {
+        /// The scheme
+        scheme: String,
+        /// What follows the scheme
+        target: String,
+        /// Package name of the app, for intents that name it
+        package: Option<String>,
+    },
+    /// Anything else (`javascript:`, `data:`, `ftp:`, ...)
+    Other {
+        /// The scheme, empty when the link has none
+        scheme: String,
+    },
+}
+
+/// An app store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Store {
+    /// Google Play
+    GooglePlay,
+    /// Apple's App Store
+    Apple,
+}
+
+impl Destination {
+    pub fn is_web(&self) -> bool {
+        //! Whether the destination is an `http(s)` page.
+        matches!(self, Self::Web { .. })
+    }
+}
+
+pub fn parse_destination(url: &str) -> Destination {
+    //! Tells what kind of target a link is.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - An absolute URL, of any scheme
+    //!
+    //! # Returns
+    //!
+    //! Returns the kind of target with its parsed components; store pages are
+    //! [`Destination::AppStore`] even over `https`.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{parse_destination, Destination};
+    //!
+    //! let number = "+442079460958".to_string();
+    //! assert_eq!(parse_destination("tel:+44-20-7946-0958"), Destination::Phone { number });
+    //! assert!(parse_destination("https://example.com/").is_web());
+    //! ```
+    let Ok(parsed) = Url::parse(url.trim()) else {
+        return Destination::Other { scheme: String::new() };
+    };
+    let scheme = parsed.scheme().to_string();
+    let param = |name: &str| parsed.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+    let decoded = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+    let host = parsed.host_str().unwrap_or_default().to_ascii_lowercase();
+    let apple_id = || {
+        parsed
+            .path_segments()?
+            .filter_map(|s| s.strip_prefix("id"))
+            .find(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+            .map(String::from)
+    };
+    match scheme.as_str() {
+        "http" | "https" => match host.as_str() {
+            "play.google.com" if parsed.path().starts_with("/store/apps/details") => match param("id") {
+                Some(app_id) => Destination::AppStore { store: Store::GooglePlay, app_id },
+                None => Destination::Web { host },
+            },
+            "apps.apple.com" | "itunes.apple.com" => match apple_id() {
+                Some(app_id) => Destination::AppStore { store: Store::Apple, app_id },
+                None => Destination::Web { host },
+            },
+            _ => Destination::Web { host },
+        },
+        "mailto" => {
+            let mut to: Vec<String> = parsed
+                .path()
+                .split(',')
+                .map(|a| decoded(a).trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect();
+            to.extend(param("to").into_iter().flat_map(|a| {
+                a.split(',').map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect::<Vec<_>>()
+            }));
+            Destination::Email { to, subject: param("subject"), body: param("body") }
+        }
+        "tel" => Destination::Phone { number: phone_number(&decoded(parsed.path())) },
+        "sms" | "smsto" | "mms" | "mmsto" => {
+            // `smsto:` puts the message after a second colon
+            let path = decoded(parsed.path());
+            let (numbers, text) = match path.split_once(':') {
+                Some((numbers, text)) => (numbers.to_string(), Some(text.to_string())),
+                None => (path, None),
+            };
+            Destination::Sms {
+                numbers: numbers.split(',').map(phone_number).filter(|n| !n.is_empty()).collect(),
+                body: param("body").or(text),
+            }
+        }
+        "market" => match param("id") {
+            Some(app_id) => Destination::AppStore { store: Store::GooglePlay, app_id },
+            None => app_scheme(url, scheme),
+        },
+        "itms-apps" | "itms-appss" => match apple_id() {
+            Some(app_id) => Destination::AppStore { store: Store::Apple, app_id },
+            None => app_scheme(url, scheme),
+        },
+        s if NOT_APPS.contains(&s) => Destination::Other { scheme },
+        _ => app_scheme(url, scheme),
+    }
+}
+
+fn app_scheme(url: &str, scheme: String) -> Destination {
+    //! A link opened by an app, with the package named by an intent.
+    let target = url.trim()[scheme.len() + 1..].trim_start_matches("//");
+    let target = target.split("#Intent;").next().unwrap_or(target).to_string();
+    let package = parse_deep_link(url).and_then(|link| link.android?.package);
+    Destination::AppScheme { scheme, target, package }
+}
+
+fn phone_number(number: &str) -> String {
+    //! A phone number without its visual separators and parameters
+    //! (`;ext=...`).
+    let number = number.split(';').next().unwrap_or_default();
+    number.chars().filter(|c| c.is_ascii_digit() || matches!(c, '+' | '*' | '#')).collect()
+}
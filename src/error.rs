@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
 pub enum Error {
     #[error("tokio runtime error")]
     StdIo(String),
@@ -6,8 +6,18 @@ pub enum Error {
     ReqwestHeader(String),
     #[error("reqwest error")]
     Reqwest(String),
+    #[error("service registry error")]
+    Registry(String),
+    #[error("extraction rule error")]
+    Rule(String),
+    #[error("invalid HTTP status")]
+    InvalidStatus(u16),
+    #[error("adf.ly ysmm error")]
+    Ysmm(crate::YsmmError),
     #[error("no string")]
     NoString,
+    #[error("no offline resolver")]
+    Offline,
     #[error("unknown error")]
     Unknown,
 }
@@ -24,6 +34,12 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<crate::YsmmError> for Error {
+    fn from(a: crate::YsmmError) -> Self {
+        Self::Ysmm(a)
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(a: std::io::Error) -> Self {
         Self::StdIo(a.to_string())
@@ -0,0 +1,132 @@
+//! Result of expanding a short URL.
+use std::time::Duration;
+
+use crate::{
+    affiliate::{find_affiliates, strip_affiliates},
+    canonical::canonicalize,
+    parse_amazon_product,
+    defang::defang,
+    idn::{url_to_ascii, url_to_unicode},
+    parse_destination, Affiliate, AmazonProduct, ApiProvider, DeepLink, Destination, Options, OutboundLink, Stage,
+};
+
+/// The outcome of a successful expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Expansion {
+    /// The URL as given by the caller
+    pub input: String,
+    /// Main domain of the shortener service that matched
+    pub service: String,
+    /// The final destination URL
+    pub url: String,
+    /// The final destination URL in canonical form: without tracking
+    /// parameters, with a lowercase host, parameters sorted, ... (see
+    /// [`canonicalize`](crate::canonicalize))
+    pub canonical: String,
+    /// The final destination URL with its host in ASCII (`xn--`) form
+    pub ascii: String,
+    /// The final destination URL with its host in Unicode form
+    pub unicode: String,
+    /// The final destination URL, defanged (with [`Options::defang`])
+    pub defanged: Option<String>,
+    /// What kind of target the final destination is: a web page, a phone
+    /// number, an app, ...
+    pub destination: Destination,
+    /// Every URL visited, from the short URL to the destination
+    pub hops: Vec<Hop>,
+    /// The affiliate tags found on the hops and the destination, once per
+    /// program
+    pub affiliates: Vec<Affiliate>,
+    /// Marketplace and ASIN of the destination, when it is an Amazon product
+    /// page
+    pub amazon: Option<AmazonProduct>,
+    /// The pipeline stage that found the destination
+    pub stage: Stage,
+    /// Delay of the `Refresh` header / meta refresh that led to the destination
+    pub refresh_delay: Option<Duration>,
+    /// What the shortener's official API reports about the link (with
+    /// [`Options::api_token`])
+    pub metadata: Option<LinkMetadata>,
+    /// The app targets of the deep link met on the way, whose web fallback
+    /// is the destination
+    pub deep_link: Option<DeepLink>,
+    /// Every outbound link of the destination, when it is a link-in-bio page
+    /// (with [`Options::link_pages`])
+    pub links: Vec<OutboundLink>,
+}
+
+/// One URL visited during an expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Hop {
+    /// The URL
+    pub url: String,
+    /// The URL with its host in ASCII (`xn--`) form
+    pub ascii: String,
+    /// The URL with its host in Unicode form
+    pub unicode: String,
+    /// The URL, defanged (with [`Options::defang`])
+    pub defanged: Option<String>,
+}
+
+/// What a shortener's official API reports about a link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LinkMetadata {
+    /// The API that reported it
+    pub provider: ApiProvider,
+    /// Title of the link
+    pub title: Option<String>,
+    /// Creation date of the link, as reported (usually RFC 3339)
+    pub created_at: Option<String>,
+}
+
+impl Expansion {
+    pub(crate) fn new(
+        input: &str,
+        service: &str,
+        url: String,
+        hops: Vec<String>,
+        stage: Stage,
+        options: &Options,
+    ) -> Self {
+        //! Builds an expansion with both host forms of every URL, defanging the
+        //! URLs when asked to, and reading the affiliate tags (which are
+        //! removed from the destination when asked to).
+        let mut affiliates: Vec<Affiliate> = vec![];
+        for found in hops.iter().chain([&url]).flat_map(|u| find_affiliates(u)) {
+            if !affiliates.iter().any(|a| a.program == found.program) {
+                affiliates.push(found);
+            }
+        }
+        let url = if options.strip_affiliates { strip_affiliates(&url) } else { url };
+        let defang_if = |u: &str| options.defang.then(|| defang(u));
+        Self {
+            input: input.into(),
+            service: service.into(),
+            defanged: defang_if(&url),
+            destination: parse_destination(&url),
+            canonical: canonicalize(&url),
+            amazon: parse_amazon_product(&url),
+            ascii: url_to_ascii(&url),
+            unicode: url_to_unicode(&url),
+            url,
+            hops: hops
+                .into_iter()
+                .map(|url| Hop {
+                    defanged: defang_if(&url),
+                    ascii: url_to_ascii(&url),
+                    unicode: url_to_unicode(&url),
+                    url,
+                })
+                .collect(),
+            affiliates,
+            stage,
+            refresh_delay: None,
+            metadata: None,
+            deep_link: None,
+            links: vec![],
+        }
+    }
+}
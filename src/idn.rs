@@ -0,0 +1,60 @@
+//! Internationalised domain name handling.
+//!
+//! Shortener domains may be written in Unicode (`例え.jp`, emoji domains) or in
+//! their ASCII `xn--` form, and both must be treated as the same host. Hosts are
+//! normalised with IDNA / UTS-46 before they are compared, so a lookalike host
+//! (e.g. a Cyrillic `і` in `bіt.ly`) never matches the real service.
+use url::{Position, Url};
+
+pub(crate) fn domain_to_ascii(domain: &str) -> Option<String> {
+    //! UTS-46 ASCII form of a domain (lowercase, `xn--` labels), without a
+    //! trailing dot.
+    idna::domain_to_ascii(domain.trim().trim_end_matches('.'))
+        .ok()
+        .filter(|d| !d.is_empty())
+}
+
+pub(crate) fn domain_to_unicode(domain: &str) -> String {
+    //! Unicode form of a domain; labels that can't be decoded are kept as is.
+    idna::domain_to_unicode(domain).0
+}
+
+pub(crate) fn host_of(url: &str) -> Option<String> {
+    //! The normalised (ASCII) host of a URL, or of a bare `host/path` string
+    //! without a scheme.
+    let parsed = Url::parse(url)
+        .ok()
+        .filter(|u| u.has_host())
+        .or_else(|| Url::parse(&format!("https://{}", url)).ok())?;
+    domain_to_ascii(parsed.host_str()?)
+}
+
+pub(crate) fn host_matches(host: &str, domain: &str) -> bool {
+    //! Whether an ASCII host is `domain` or one of its subdomains; `domain`
+    //! must be in ASCII form already (see [`domain_to_ascii`]), so that
+    //! lookups over a table don't convert every entry again.
+    host == domain || host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+fn with_host(url: &str, convert: impl Fn(&str) -> String) -> String {
+    //! Rewrites the host of a URL, keeping every other character as is.
+    match Url::parse(url) {
+        Ok(parsed) if parsed.host_str().is_some() => format!(
+            "{}{}{}",
+            &parsed[..Position::BeforeHost],
+            convert(&parsed[Position::BeforeHost..Position::AfterHost]),
+            &parsed[Position::AfterHost..]
+        ),
+        _ => url.into(),
+    }
+}
+
+pub(crate) fn url_to_ascii(url: &str) -> String {
+    //! The URL with its host in ASCII form.
+    with_host(url, |host| domain_to_ascii(host).unwrap_or_else(|| host.into()))
+}
+
+pub(crate) fn url_to_unicode(url: &str) -> String {
+    //! The URL with its host in Unicode form.
+    with_host(url, domain_to_unicode)
+}
@@ -0,0 +1,363 @@
+//! JavaScript redirect detection.
+//!
+//! Interstitial pages often send the browser on with a script rather than an
+//! HTTP redirect. This module finds the navigations in a page or script:
+//!
+//! - `location = ...`, `location.href = ...` on `window`, `top`, `self`,
+//!   `parent` or `document`
+//! - `location.replace(...)` and `location.assign(...)`
+//! - `window.open(...)`
+//! - any of these inside `setTimeout`, including the string form
+//!   `setTimeout("location.href = '...'", 0)`
+//!
+//! The destination expression is evaluated when it is built from string
+//! literals, `+` concatenation, variables assigned in the same source,
+//! `atob("...")` and `decodeURIComponent(...)` (each variable once per source,
+//! up to [`MAX_VALUE`] bytes), and code hidden with the
+//! `eval(function(p,a,c,k,e,d)...)` packer is unpacked (see
+//! [`unpack`](crate::unpack)) and scanned as well. Every match is scored, so that
+//! popups, analytics beacons and static assets rank below the real
+//! destination; see [`find_js_redirects`].
+use base64::{engine::general_purpose, Engine as _};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use std::{cell::RefCell, collections::HashMap, sync::LazyLock};
+use url::Url;
+
+use crate::{decode::unpack, idn::host_matches};
+
+/// Navigation sinks.
+static RE_SINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        (?P<open>\bwindow\s*\.\s*open\s*\(\s*)
+        | (?P<call>\blocation\s*\.\s*(?P<method>replace|assign)\s*\(\s*)
+        | (?P<set>\blocation(?:\s*\.\s*href)?\s*=\s*)
+        | (?P<timeout>\bsetTimeout\s*\(\s*)",
+    )
+    .expect("valid sink regex")
+});
+
+/// Domains of analytics, tag manager and ad scripts, never a destination.
+static NOISE_DOMAINS: &[&str] = &[
+    "google-analytics.com",
+    "googletagmanager.com",
+    "googlesyndication.com",
+    "doubleclick.net",
+    "facebook.net",
+    "scorecardresearch.com",
+    "hotjar.com",
+    "quantserve.com",
+];
+
+/// First labels of tracking hosts (`analytics.example.com`).
+static NOISE_LABELS: &[&str] = &["analytics", "stats", "pixel", "track", "beacon"];
+
+/// Extensions of static assets, never a destination.
+static ASSET_EXTENSIONS: &[&str] = &[
+    ".js", ".css", ".png", ".gif", ".jpg", ".jpeg", ".svg", ".ico", ".webp", ".woff", ".woff2",
+];
+
+/// How deep an expression may nest: parentheses, calls and variable lookups.
+const MAX_DEPTH: u8 = 16;
+
+/// Longest value an expression may evaluate to.
+const MAX_VALUE: usize = 16 * 1024;
+
+/// Most navigations [`find_js_redirects`] evaluates, over all the layers.
+const MAX_SINKS: usize = 256;
+
+/// The kind of navigation a redirect uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsSink {
+    /// `location = ...` / `location.href = ...`
+    Location,
+    /// `location.replace(...)`
+    Replace,
+    /// `location.assign(...)`
+    Assign,
+    /// `window.open(...)`
+    Open,
+}
+
+/// A JavaScript redirect found in a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsRedirect {
+    /// The destination, as evaluated (possibly relative)
+    pub url: String,
+    /// How the page navigates to it
+    pub sink: JsSink,
+    /// Confidence; a positive score means a likely destination
+    pub score: i32,
+    /// Byte offset of the navigation in the source
+    pub start: usize,
+}
+
+pub fn find_js_redirects(source: &str) -> Vec<JsRedirect> {
+    //! Finds the JavaScript redirects of a page or script, best first.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::find_js_redirects;
+    //!
+    //! let page = r#"<script>
+    //!     var host = "https://" + "example.com";
+    //!     window.open("https://ads.example.net/pop");
+    //!     setTimeout(function() { top.location.replace(host + atob("L2Rlc3Q=")); }, 500);
+    //! </script>"#;
+    //! let found = find_js_redirects(page);
+    //! assert_eq!(found[0].url, "https://example.com/dest");
+    //! ```
+    let mut sinks = MAX_SINKS;
+    let mut found = scan(source, &Scope::new(source), 0, &mut sinks);
+    // packed code, possibly packed several times
+    let mut packed = unpack(source);
+    for _ in 0..3 {
+        let Some(code) = packed else {
+            break;
+        };
+        found.extend(scan(&code, &Scope::new(&code), 0, &mut sinks));
+        packed = unpack(&code);
+    }
+    found.sort_by_key(|r| (std::cmp::Reverse(r.score), r.start));
+    found
+}
+
+pub(crate) fn js_location(source: &str) -> Option<String> {
+    //! The best JavaScript redirect of a page, when it is a likely destination.
+    find_js_redirects(source).into_iter().find(|r| r.score > 0).map(|r| r.url)
+}
+
+fn scan(code: &str, scope: &Scope, offset: usize, sinks: &mut usize) -> Vec<JsRedirect> {
+    //! Finds the redirects in `code`, evaluating at most `sinks` more
+    //! navigations; variables are looked up in `scope`.
+    let mut found = vec![];
+    for caps in RE_SINK.captures_iter(code) {
+        let Some(all) = caps.get(0) else {
+            continue;
+        };
+        if *sinks == 0 {
+            break;
+        }
+        *sinks -= 1;
+        let start = offset + all.start();
+        let rest = &code[all.end()..];
+        let sink = if caps.name("open").is_some() {
+            JsSink::Open
+        } else if let Some(method) = caps.name("method") {
+            match method.as_str() {
+                "replace" => JsSink::Replace,
+                _ => JsSink::Assign,
+            }
+        } else if caps.name("set").is_some() {
+            // a comparison, not an assignment
+            if rest.starts_with('=') {
+                continue;
+            }
+            JsSink::Location
+        } else {
+            // setTimeout("code", delay): scan the code string
+            if let Some((code, _)) = string_literal(rest) {
+                found.extend(scan(&code, scope, start, sinks));
+            }
+            continue;
+        };
+        let Some((url, _)) = expr(rest, scope, 0) else {
+            continue;
+        };
+        if let Some(score) = score(sink, url.trim()) {
+            found.push(JsRedirect {
+                url: url.trim().into(),
+                sink,
+                score,
+                start,
+            });
+        }
+    }
+    found
+}
+
+pub(crate) fn score(sink: JsSink, url: &str) -> Option<i32> {
+    //! Scores a destination; `None` when it can't be one at all.
+    let lower = url.to_ascii_lowercase();
+    if lower.is_empty()
+        || lower.starts_with('#')
+        || ["javascript:", "about:", "data:"].iter().any(|s| lower.starts_with(s))
+    {
+        return None;
+    }
+    let mut score = match sink {
+        JsSink::Replace => 100,
+        JsSink::Location | JsSink::Assign => 90,
+        // usually a popup, next to the real redirect
+        JsSink::Open => 50,
+    };
+    let host = match Url::parse(url) {
+        Ok(parsed) if !matches!(parsed.scheme(), "http" | "https") => {
+            score -= 20;
+            None
+        }
+        Ok(parsed) => parsed.host_str().map(str::to_ascii_lowercase),
+        // relative to the page
+        Err(_) => {
+            score -= 10;
+            None
+        }
+    };
+    let noise_host = host.is_some_and(|host| {
+        NOISE_DOMAINS.iter().any(|d| host_matches(&host, d))
+            || NOISE_LABELS.iter().any(|l| host.split('.').next() == Some(l))
+    });
+    let path = lower.split(['?', '#']).next().unwrap_or_default();
+    if noise_host || ASSET_EXTENSIONS.iter().any(|e| path.ends_with(e)) {
+        score -= 150;
+    }
+    Some(score)
+}
+
+/// The variables of a source: their assignments, parsed once, and their
+/// values, evaluated once.
+struct Scope<'s> {
+    /// The right-hand sides of each variable's assignments, in source order
+    assignments: HashMap<&'s str, Vec<&'s str>>,
+    /// The values evaluated so far; `None` for a variable without a value,
+    /// or being evaluated
+    values: RefCell<HashMap<&'s str, Option<String>>>,
+}
+
+impl<'s> Scope<'s> {
+    fn new(source: &'s str) -> Self {
+        //! Finds the assignments (`name = ...`, not `a.name = ...`, `==` or
+        //! `=>`) of a source.
+        let mut assignments: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut i = 0;
+        while let Some(c) = source[i..].chars().next() {
+            if !(c.is_alphanumeric() || matches!(c, '_' | '$')) {
+                i += c.len_utf8();
+                continue;
+            }
+            let end = source[i..]
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '$')))
+                .map_or(source.len(), |e| i + e);
+            let member = source[..i].ends_with('.');
+            if !c.is_numeric()
+                && !member
+                && let Some(rest) = source[end..].trim_start().strip_prefix('=')
+                && !rest.starts_with(['=', '>'])
+            {
+                assignments.entry(&source[i..end]).or_default().push(rest);
+            }
+            i = end;
+        }
+        Self {
+            assignments,
+            values: RefCell::default(),
+        }
+    }
+
+    fn variable(&self, name: &str, depth: u8) -> Option<String> {
+        //! The string value assigned to a variable, from its first assignment
+        //! that evaluates.
+        if depth >= MAX_DEPTH {
+            return None;
+        }
+        let (&name, assignments) = self.assignments.get_key_value(name)?;
+        if let Some(value) = self.values.borrow().get(name) {
+            return value.clone();
+        }
+        // a variable referring to itself has no value
+        self.values.borrow_mut().insert(name, None);
+        let value = assignments.iter().find_map(|rest| expr(rest, self, depth + 1).map(|(value, _)| value));
+        self.values.borrow_mut().insert(name, value.clone());
+        value
+    }
+}
+
+fn expr<'a>(s: &'a str, scope: &Scope, depth: u8) -> Option<(String, &'a str)> {
+    //! Evaluates a `+` concatenation of terms, up to [`MAX_VALUE`] bytes;
+    //! returns the value and the rest of the input.
+    let (mut value, mut rest) = term(s, scope, depth)?;
+    while let Some(r) = rest.trim_start().strip_prefix('+') {
+        let (t, r) = term(r, scope, depth)?;
+        if value.len() + t.len() > MAX_VALUE {
+            return None;
+        }
+        value.push_str(&t);
+        rest = r;
+    }
+    Some((value, rest))
+}
+
+fn term<'a>(s: &'a str, scope: &Scope, depth: u8) -> Option<(String, &'a str)> {
+    //! Evaluates a string literal, a parenthesised expression, a decoding call
+    //! or a variable.
+    let s = s.trim_start();
+    match s.chars().next()? {
+        '\'' | '"' | '`' => string_literal(s),
+        '(' if depth < MAX_DEPTH => {
+            let (value, rest) = expr(&s[1..], scope, depth + 1)?;
+            Some((value, rest.trim_start().strip_prefix(')')?))
+        }
+        c if c.is_alphabetic() || c == '_' || c == '$' => {
+            let end = s
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '$' | '.')))
+                .unwrap_or(s.len());
+            let (name, rest) = s.split_at(end);
+            match rest.trim_start().strip_prefix('(') {
+                Some(_) if depth >= MAX_DEPTH => None,
+                Some(args) => {
+                    let (arg, rest) = expr(args, scope, depth + 1)?;
+                    let rest = rest.trim_start().strip_prefix(')')?;
+                    let value = match name.trim_start_matches("window.") {
+                        "atob" => {
+                            let buf = general_purpose::STANDARD.decode(arg.trim()).ok()?;
+                            String::from_utf8(buf).ok()?
+                        }
+                        "decodeURIComponent" | "decodeURI" | "unescape" => {
+                            percent_decode_str(&arg).decode_utf8_lossy().into_owned()
+                        }
+                        _ => return None,
+                    };
+                    Some((value, rest))
+                }
+                None if name.contains('.') => None,
+                None => Some((scope.variable(name, depth)?, rest)),
+            }
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn string_literal(s: &str) -> Option<(String, &str)> {
+    //! Decodes a quoted JavaScript string (templates without substitutions
+    //! only); returns the value and the rest of the input.
+    let mut chars = s.char_indices();
+    let (_, quote) = chars.next()?;
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some((value, &s[i + 1..])),
+            '\n' if quote != '`' => return None,
+            '$' if quote == '`' && s[i + 1..].starts_with('{') => return None,
+            '\\' => {
+                let (_, e) = chars.next()?;
+                value.push(match e {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'x' => {
+                        let hex = (0..2).filter_map(|_| chars.next().map(|(_, c)| c)).collect::<String>();
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    'u' => {
+                        let hex = (0..4).filter_map(|_| chars.next().map(|(_, c)| c)).collect::<String>();
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    e => e,
+                });
+            }
+            c => value.push(c),
+        }
+    }
+    None
+}
@@ -20,9 +20,10 @@
 //! 2. The library picks a resolver (or tries several in order).
 //! 3. The resolver expands the URL using one of these strategies:
 //!    - **HTTP redirect following** (3xx chains)
-//!    - **HTML pattern extraction** (regex-based "click-through" / meta / JS hints)
+//!    - **HTML pattern extraction** (declarative selector / regex rules for "click-through" / meta / JS hints)
 //!    - **Service API lookup** (when the browser normally uses JS to fetch the destination)
-//! 4. The final URL is returned as a `String`.
+//! 4. The final URL is returned as a `String` (`unshorten()`), or as an [`Expansion`] carrying
+//!    the matched service as well (`expand()`).
 //!
 //! ## API Usage
 //!
@@ -52,6 +53,96 @@
 //! let final_url = unshorten_async("https://bit.ly/3alqLKi", Some(Duration::from_secs(10))).await?;
 //! ```
 //!
+//! ### With js feature
+//!
+//! The inline scripts of redirect pages that the static JavaScript scan can't follow run in a
+//! sandboxed pure-Rust engine, under the limits set with `Options::sandbox` (see `run_scripts`).
+//!
+//! ### Expansion details and free text
+//!
+//! `expand()` returns an [`Expansion`] instead of a bare `String`, and takes its settings from
+//! [`Options`]. `expand_text()` finds every short link in a chat message, ticket or email body
+//! (see [`find_short_urls`]) and expands them all:
+//!
+//! ```ignore
+//! use urlexpand::{expand_text, Options};
+//!
+//! for (found, expansion) in expand_text("ping me at bit.ly/3alqLKi.", &Options::new()).await {
+//!     println!("{}..{} {:?}", found.start, found.end, expansion);
+//! }
+//! ```
+//!
+//! ### Captured pages
+//!
+//! `expand_from_response()` finds the destination in a response captured earlier (status,
+//! headers and body), without any request.
+//!
+//! ### Preview pages
+//!
+//! With `Options::preview_first`, the destination is looked up on the service's preview page or
+//! lookup API (see [`Preview`]) before the link itself is followed, so that no click is counted.
+//!
+//! ### Canonical URLs
+//!
+//! `Expansion::canonical` is the destination without tracking parameters (`utm_*`, `fbclid`,
+//! `gclid`, ...), with a lowercase host, no default port or trailing slash, and its parameters
+//! sorted (see [`canonicalize`]); `Expansion::url` stays as reached. Amazon product pages are
+//! reduced to `https://www.amazon.<tld>/dp/<ASIN>`, their marketplace and ASIN reported as
+//! `Expansion::amazon` (see [`parse_amazon_product`]). More tracking parameters
+//! can be added with [`add_tracking_parameter`].
+//!
+//! ### Affiliate tags
+//!
+//! `Expansion::affiliates` lists the affiliate tags met on the hops and the destination
+//! (Amazon's `tag=`, AliExpress' `aff_*`, Partnerize's `camref`, ...; see [`find_affiliates`]).
+//! With `Options::strip_affiliates`, the destination is returned without its affiliate
+//! parameters (see [`strip_affiliates`]).
+//!
+//! ### Non-HTTP destinations
+//!
+//! Redirects to `tel:`, `mailto:`, `sms:`, app store or app scheme URLs end the expansion with
+//! that target, described by `Expansion::destination` (see [`Destination`]).
+//!
+//! ### Link-in-bio pages
+//!
+//! With `Options::link_pages`, a destination that is a Linktree-like page is fetched, and its
+//! outbound links are listed in `Expansion::links` (see [`find_outbound_links`]).
+//!
+//! ### App deep links
+//!
+//! Firebase Dynamic Links, Branch links and Android `intent://` URLs are parsed offline into a
+//! [`DeepLink`], reported as `Expansion::deep_link`; their web fallback is the destination.
+//!
+//! ### Offline resolution
+//!
+//! Links of youtu.be, y2u.be, redd.it, wa.me and amzn.com map to their destination without any
+//! request (see [`Rewrite`]); with `Options::offline`, no request is made at all.
+//!
+//! ### Official APIs
+//!
+//! With `Options::api_token`, links of Bitly, Rebrandly, Dub and TinyURL are expanded through
+//! the official API of the service (see [`ApiProvider`]), which also reports [`LinkMetadata`].
+//!
+//! ### Email security wrappers
+//!
+//! Links rewritten by Proofpoint, Safe Links, Barracuda or Cisco are unwrapped offline before
+//! looking for a shortener, each layer being a hop (see [`unwrap_link`]).
+//!
+//! ### Redirectors
+//!
+//! Redirectors carrying the destination in a query parameter (`google.com/url?q=`,
+//! `l.facebook.com/l.php?u=`, ..., and any `?redirect=` / `?to=` / `?dest=` link unless
+//! `Options::unwrap_parameters(false)`) are unwrapped offline too, before expanding and again
+//! on the destination; a short link found this way is expanded in turn. The parameter
+//! heuristic only takes links to another site, and leaves a destination that served its own
+//! page as it is.
+//!
+//! ### Self-hosted shorteners
+//!
+//! YOURLS, Shlink, Kutt and Polr instances are registered with their platform as [`Strategy`],
+//! and their API keys set with `Options::instance_key`; with `Options::detect_platforms`, the
+//! hosts of unknown domains are probed for these platforms, for that expansion only.
+//!
 //! ## Module layout
 //!
 //! A common structure looks like this:
@@ -71,7 +162,8 @@
 //! Many resolver modules share helpers such as:
 //!
 //! - `get_client_builder(timeout)` — returns a configured `reqwest::ClientBuilder`
-//! - `from_re(text, pattern)` — returns the first capture group match as `Option<String>`
+//! - `rules::extract(service, stage, html)` — runs the declarative extraction [`Rule`]s of a
+//!   service for a pipeline [`Stage`]
 //!
 //! These helpers keep each resolver tiny and consistent.
 //!
@@ -107,8 +199,13 @@
 //! }
 //! ```
 //!
-//! 3. Re-export it from `src/resolvers/mod.rs`
-//! 4. Add it to your dispatcher/registry if you have one (e.g., “try resolvers in order”).
+//! 3. Declare it in `src/resolvers/mod.rs` and add a [`Strategy`] variant dispatched by
+//!    the API stage of `resolvers::pipeline`
+//! 4. Map the service's domain to the new strategy in `services::STRATEGIES`
+//!
+//! Services that only need a different user agent, redirect policy or extraction [`Rule`]
+//! don't need code at all: describe them in a service definition file and load it with
+//! [`load_services`].
 //!
 //! ### Resolver style guideline
 //!
@@ -116,14 +213,14 @@
 //!
 //! - follow redirects first
 //! - if the service stops on a non-redirect “intermediate page”, use either:
-//!   - regex extraction (`from_re`) or
+//!   - an extraction [`Rule`] (CSS selector or regex) or
 //!   - a small API call if the browser normally uses JS
 //!
 //! ## Testing
 //!
 //! For deterministic tests, consider:
 //!
-//! - unit testing regex extraction helpers (`from_re`) with fixed strings
+//! - unit testing extraction rules (`rules::extract`) with fixed strings
 //! - using a mock HTTP server (or recorded fixtures) for network calls
 //! - keeping “live” integration tests behind a feature flag, since shortener behavior can change
 //!
@@ -136,22 +233,88 @@
 //! - blocking private IP ranges (SSRF protection) if this runs server-side
 //! - request method restrictions (typically GET only)
 //! - size limits for downloaded bodies when parsing HTML
-use std::time::Duration;
+use std::{borrow::Cow, time::Duration};
 use url::{ParseError, Url};
 
 mod error;
+mod affiliate;
+pub use affiliate::{find_affiliates, strip_affiliates, Affiliate, AffiliateProgram};
+
+mod amazon;
+pub use amazon::{parse_amazon_product, AmazonProduct};
+
+mod canonical;
+pub use canonical::{add_tracking_parameter, canonicalize};
+
+mod decode;
+pub use decode::{decode_base64, decode_hex, decode_percent, decode_url, find_url_in_query, unpack};
+
+mod deeplink;
+pub use deeplink::{parse_deep_link, AndroidIntent, DeepLink, DeepLinkPlatform, IosLink};
+
+mod destination;
+pub use destination::{parse_destination, Destination, Store};
+
+mod defang;
+pub use defang::{defang, refang};
+
+mod idn;
+
+mod js;
+pub use js::{find_js_redirects, JsRedirect, JsSink};
+
+mod expansion;
+pub use expansion::{Expansion, Hop, LinkMetadata};
+
+mod linkpage;
+pub use linkpage::{find_outbound_links, OutboundLink};
+
+mod options;
+pub use options::Options;
+
+#[cfg(feature = "js")]
+mod sandbox;
+#[cfg(feature = "js")]
+pub use sandbox::{run_scripts, Navigation, SandboxLimits};
+
+mod refresh;
+pub use refresh::{meta_refresh, parse_refresh, Refresh};
+
 mod resolvers;
 
+mod rules;
+pub use rules::{add_rule, PostStep, Rule};
+
 mod services;
-use services::{which_service, SERVICES};
+pub use services::{
+    load_services, register_service, ApiProvider, Preview, RedirectPolicy, Registry, Rewrite, Service, Stage,
+    Strategy, UaProfile,
+};
+use services::which_service;
+
+mod text;
+pub use text::{find_short_urls, Match};
+
+mod unwrap;
+pub use unwrap::{unwrap_link, Wrapper};
+
+mod ysmm;
+pub use ysmm::{decode_ysmm, find_ysmm, YsmmError};
 
 #[cfg(test)]
 mod tests;
 
+/// Most expansions, each followed by unwrapping, one call makes.
+const MAX_ROUNDS: usize = 5;
+
 pub type Error = error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
-use futures::future::{ready, TryFutureExt};
+use futures::future::{join_all, TryFutureExt};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
 
 pub fn is_shortened(url: &str) -> bool {
     //! Check to see if a given url is a shortened url
@@ -162,7 +325,7 @@ pub fn is_shortened(url: &str) -> bool {
     //! let url = "https://bit.ly/id";
     //! assert!(is_shortened(url));
     //! ```
-    SERVICES.iter().any(|x| url.contains(x))
+    which_service(url).is_some()
 }
 
 #[cfg(not(feature = "blocking"))]
@@ -212,71 +375,290 @@ pub async fn unshorten_async(url: &str, timeout: Option<Duration>) -> Result<Str
     unshorten_impl(url, timeout).await
 }
 
+#[cfg(not(feature = "blocking"))]
+pub async fn expand(url: &str, options: &Options) -> Result<Expansion> {
+    //! Expand a shortened URL into an [`Expansion`] (async version)
+    //! ## Example
+    //! ```ignore
+    //!  use std::time::Duration;
+    //!  use urlexpand::{expand, Options};
+    //!
+    //!  let options = Options::new().timeout(Duration::from_secs(10));
+    //!  let expansion = expand("https://bit.ly/3alqLKi", &options).await?;
+    //!  assert_eq!(expansion.service, "bit.ly");
+    //! ```
+    expand_impl(url, options).await
+}
+
+#[cfg(feature = "blocking")]
+pub fn expand(url: &str, options: &Options) -> Result<Expansion> {
+    //! Expand a shortened URL into an [`Expansion`] (blocking version)
+    //! ## Example
+    //! ```ignore
+    //!  use std::time::Duration;
+    //!  use urlexpand::{expand, Options};
+    //!
+    //!  let options = Options::new().timeout(Duration::from_secs(10));
+    //!  let expansion = expand("https://bit.ly/3alqLKi", &options)?;
+    //!  assert_eq!(expansion.service, "bit.ly");
+    //! ```
+    use tokio::runtime::Runtime;
+    let rt = Runtime::new()?;
+    rt.block_on(expand_async(url, options))
+}
+
+#[cfg(feature = "blocking")]
+pub async fn expand_async(url: &str, options: &Options) -> Result<Expansion> {
+    //! Expand a shortened URL into an [`Expansion`] (async version, available when blocking
+    //! feature is enabled)
+    expand_impl(url, options).await
+}
+
+#[cfg(not(feature = "blocking"))]
+pub async fn expand_text(text: &str, options: &Options) -> Vec<(Match, Result<Expansion>)> {
+    //! Find every short URL in a text and expand them concurrently (async version)
+    //! ## Example
+    //! ```ignore
+    //!  use urlexpand::{expand_text, Options};
+    //!
+    //!  let text = "Check <https://bit.ly/3alqLKi>, or surl.li/aap.";
+    //!  for (found, expansion) in expand_text(text, &Options::new()).await {
+    //!      println!("{} -> {:?}", &text[found.start..found.end], expansion.map(|e| e.url));
+    //!  }
+    //! ```
+    expand_text_impl(text, options).await
+}
+
+#[cfg(feature = "blocking")]
+pub fn expand_text(text: &str, options: &Options) -> Vec<(Match, Result<Expansion>)> {
+    //! Find every short URL in a text and expand them concurrently (blocking version)
+    //! ## Example
+    //! ```ignore
+    //!  use urlexpand::{expand_text, Options};
+    //!
+    //!  let text = "Check <https://bit.ly/3alqLKi>, or surl.li/aap.";
+    //!  for (found, expansion) in expand_text(text, &Options::new()) {
+    //!      println!("{} -> {:?}", &text[found.start..found.end], expansion.map(|e| e.url));
+    //!  }
+    //! ```
+    use tokio::runtime::Runtime;
+    match Runtime::new() {
+        Ok(rt) => rt.block_on(expand_text_async(text, options)),
+        Err(e) => {
+            let e = Error::from(e);
+            find_short_urls(text)
+                .into_iter()
+                .map(|m| (m, Err(e.clone())))
+                .collect()
+        }
+    }
+}
+
+#[cfg(feature = "blocking")]
+pub async fn expand_text_async(text: &str, options: &Options) -> Vec<(Match, Result<Expansion>)> {
+    //! Find every short URL in a text and expand them concurrently (async version, available
+    //! when blocking feature is enabled)
+    expand_text_impl(text, options).await
+}
+
+pub fn expand_from_response(
+    service: &str,
+    url: &str,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<Expansion> {
+    //! Find the destination of a shortener page captured earlier, without any request
+    //!
+    //! # Arguments
+    //!
+    //! * `service` - Domain (or alias) of the shortener, built-in or registered
+    //! * `url` - URL of the captured page
+    //! * `status` - HTTP status of the captured response
+    //! * `headers` - HTTP headers of the captured response; invalid ones are ignored
+    //! * `body` - Body of the captured response
+    //!
+    //! # Behavior
+    //!
+    //! - A redirection status leads to the `Location` header
+    //! - Otherwise the service's pipeline stages parse the page: the page parser of its
+    //!   dedicated resolver (adf.ly, adfoc.us, lnkd.in, surl.li, JS redirects), the
+    //!   `Refresh` header, meta refresh, JavaScript and canonical stages
+    //! - Resolvers that need requests of their own (APIs, curl) find nothing
+    //! - Everything runs on the calling thread, page scripts included (`js` feature),
+    //!   so it can be called from async code as well
+    //! - A status outside `100..=999` is an [`Error::InvalidStatus`]
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{expand_from_response, Stage};
+    //!
+    //! let body = r#"<meta http-equiv="refresh" content="0; url=https://example.com/">"#;
+    //! let expansion = expand_from_response("bit.ly", "https://bit.ly/abc", 200, &[], body).unwrap();
+    //! assert_eq!(expansion.url, "https://example.com/");
+    //! assert_eq!(expansion.stage, Stage::MetaRefresh);
+    //! ```
+    let service = which_service(service).ok_or(Error::NoString)?;
+    let status = StatusCode::from_u16(status).map_err(|_| Error::InvalidStatus(status))?;
+    let headers = headers
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((HeaderName::from_bytes(name.trim().as_bytes()).ok()?, HeaderValue::from_str(value.trim()).ok()?))
+        })
+        .collect::<HeaderMap>();
+
+    let options = Options::new();
+    let ctx = resolvers::Context::new(&service, &options);
+    let resolved = resolvers::resolve_captured(url, status, headers, body, &ctx)?;
+    let mut expansion = Expansion::new(url, &service.domain, resolved.url, ctx.hops.to_vec(), resolved.stage, &options);
+    expansion.refresh_delay = resolved.delay;
+    Ok(expansion)
+}
+
 async fn unshorten_impl(url: &str, timeout: Option<Duration>) -> Result<String> {
+    //! Shared implementation of `unshorten()`: an [`expand_impl`] that only keeps the
+    //! final URL.
+    let mut options = Options::new();
+    options.timeout = timeout;
+    expand_impl(url, &options).map_ok(|expansion| expansion.url).await
+}
+
+async fn expand_impl(url: &str, options: &Options) -> Result<Expansion> {
     //! Shared implementation for URL expansion.
     //!
     //! This function contains the core logic for expanding shortened URLs and is used
-    //! by both the async and blocking public APIs. It validates the input URL, determines
-    //! the appropriate resolver based on the service, and delegates to the specific
-    //! resolver implementation.
+    //! by both the async and blocking public APIs. It unwraps links rewritten by email
+    //! security gateways or redirectors (see [`unwrap_link`]), validates the input URL,
+    //! determines the service, and runs its resolution pipeline: the redirect / page
+    //! stages and the resolver of the service's [`Strategy`] (see [`Stage`]). The
+    //! destination is unwrapped in turn (deep links giving way to their web fallback, see
+    //! [`parse_deep_link`]), and expanded again when it is a short link.
     //!
     //! # Arguments
     //!
     //! * `url` - The shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `options` - Expansion options (timeout, refang / defang, ...)
     //!
     //! # Returns
     //!
-    //! Returns `Ok(String)` with the final expanded URL on success, or `Err(Error)`
+    //! Returns `Ok(Expansion)` with the final expanded URL on success, or `Err(Error)`
     //! if the URL cannot be expanded.
     //!
     //! # Note
     //!
     //! This is an internal function and should not be called directly. Use the public
-    //! `unshorten()` or `unshorten_async()` functions instead.
-    // Check to make sure url is valid
-    ready(validate(url).ok_or(Error::NoString))
-        .and_then(|validated_url| async move {
-            let service = which_service(&validated_url).ok_or(Error::NoString)?;
-
-            match service {
-                // Browser UA Resolvers (services that require browser user agent)
-                "2cm.es" | "rb.gy" => resolvers::browser::unshort(&validated_url, timeout).await,
-
-                // Curl UA Resolvers (services that work with curl user agent)
-                "bit.ly" | "b.link" | "cutt.ly" | "fb.me" | "git.io"
-                | "kutt.it" | "plu.sh" | "rlu.ru"
-                | "t.co" | "t.ly" | "t2m.io" | "tiny.cc" | "tinyurl.com" | "tny.sh" => {
-                    resolvers::curl::unshort(&validated_url, timeout).await
-                }
+    //! `unshorten()` / `expand()` functions (or their `_async` variants) instead.
+    let input = if options.refang {
+        Cow::Owned(refang(url))
+    } else {
+        Cow::Borrowed(url)
+    };
 
-                // Adfly Resolver
-                "adf.ly" | "atominik.com" | "fumacrom.com" | "intamema.com" | "j.gs" | "q.gs" => {
-                    resolvers::adfly::unshort(&validated_url, timeout).await
-                }
+    // Links rewritten by email security gateways or redirectors, and deep
+    // links, are unwrapped before and after expanding, each layer being a hop;
+    // a short link found by unwrapping an expanded one is expanded in turn
+    let hops = resolvers::Hops::default();
+    let mut current = input.to_string();
+    let mut unwrapped = false;
+    let mut deep_link = None;
+    let mut shortener: Option<String> = None;
+    let mut resolved: Option<resolvers::pipeline::Resolved> = None;
+    for _ in 0..MAX_ROUNDS {
+        // a destination that served its own page is no redirect, whatever its parameters
+        let served = resolved.as_ref().is_some_and(|r| r.stage == Stage::Redirect);
+        let parameters = !options.keep_parameters && !served;
+        let progressed = unwrap_offline(&mut current, &hops, parameters, &mut deep_link);
+        if !progressed && resolved.is_some() {
+            break;
+        }
+        unwrapped |= progressed;
 
-                // Redirect Resolvers
-                "gns.io" | "ity.im" | "ldn.im" | "nowlinks.net" | "tr.im" | "u.to" | "vzturl.com" => {
-                    resolvers::redirect::unshort(&validated_url, timeout).await
-                }
+        // Check to make sure url is valid, or served by a self-hosted platform
+        let validated = match validate(&current) {
+            None if options.detect_platforms && !options.offline => {
+                resolvers::detect::detect(&current, options).await.map(|(url, service)| (url, Some(service)))
+            }
+            validated => validated.map(|url| (url, None)),
+        };
+        let Some((validated_url, detected)) = validated else {
+            // an unwrapped link to anything but a short link is the destination itself
+            if !unwrapped {
+                return Err(Error::NoString);
+            }
+            hops.push(&current);
+            break;
+        };
+        let service = match detected {
+            Some(service) => service,
+            None => which_service(&validated_url).ok_or(Error::NoString)?,
+        };
 
-                // Meta Refresh Resolvers
-                "cutt.us" | "soo.gd" => resolvers::refresh::unshort(&validated_url, timeout).await,
+        let mut ctx = resolvers::Context::new(&service, options);
+        ctx.hops = hops.clone();
+        let round = resolvers::resolve(&validated_url, &ctx).await?;
+        shortener.get_or_insert(service.domain);
+        current = round.url.clone();
+        unwrapped = false;
+        resolved = Some(round);
+    }
 
-                // Specific Resolvers
-                "adfoc.us" => resolvers::adfocus::unshort(&validated_url, timeout).await,
-                "goo.gl" => resolvers::fallback::unshort(&validated_url, timeout).await,
-                "ow.ly" => resolvers::fallback::unshort(&validated_url, timeout).await,
-                "l1nq.com" | "sl1nk.com" => resolvers::urlshortdev::unshort(&validated_url, timeout).await,
-                "lnkd.in" => resolvers::linkedin::unshort(&validated_url, timeout).await,
-                "shorturl.at" => resolvers::shorturl::unshort(&validated_url, timeout).await,
-                "surl.li" => resolvers::surlli::unshort(&validated_url, timeout).await,
+    let service = shortener.or_else(|| idn::host_of(&input)).unwrap_or_default();
+    let stage = match &resolved {
+        Some(resolved) if !unwrapped => resolved.stage,
+        _ => Stage::Unwrap,
+    };
+    let mut expansion = Expansion::new(url, &service, current, hops.to_vec(), stage, options);
+    if let Some(resolved) = resolved {
+        expansion.refresh_delay = resolved.delay;
+        expansion.metadata = resolved.metadata;
+    }
+    expansion.deep_link = deep_link;
+    if options.link_pages && !options.offline && expansion.destination.is_web() {
+        expansion.links = linkpage::links(&expansion.url, options).await;
+    }
+    Ok(expansion)
+}
 
-                // Generic Resolvers (fallback for everything else)
-                _ => resolvers::generic::unshort(&validated_url, timeout).await,
-            }
-        })
-        .await
+fn unwrap_offline(
+    current: &mut String,
+    hops: &resolvers::Hops,
+    parameters: bool,
+    deep_link: &mut Option<DeepLink>,
+) -> bool {
+    //! Removes the wrappers and redirectors around a link, with or without the
+    //! parameter heuristic, and replaces deep links with their web fallback,
+    //! recording every layer but the last as a hop; whether anything was
+    //! removed.
+    let mut unwrapped = false;
+    for _ in 0..MAX_ROUNDS {
+        let layers = unwrap::unwrap_chain(current, parameters);
+        if let Some((target, wrappers)) = layers.split_last() {
+            hops.push(current);
+            wrappers.iter().for_each(|layer| hops.push(layer));
+            *current = target.clone();
+            unwrapped = true;
+        }
+        let Some(link) = deeplink::parse_deep_link(current) else {
+            break;
+        };
+        let web = link.web.clone().filter(|web| web != current);
+        *deep_link = Some(link);
+        let Some(web) = web else {
+            break;
+        };
+        hops.push(current);
+        *current = web;
+        unwrapped = true;
+    }
+    unwrapped
+}
+
+async fn expand_text_impl(text: &str, options: &Options) -> Vec<(Match, Result<Expansion>)> {
+    //! Shared implementation of `expand_text()`: every match of [`find_short_urls`] is
+    //! expanded concurrently, results are kept in text order.
+    let matches = find_short_urls(text);
+    let expansions = join_all(matches.iter().map(|m| expand_impl(&m.url, options))).await;
+    matches.into_iter().zip(expansions).collect()
 }
 
 fn validate(u: &str) -> Option<String> {
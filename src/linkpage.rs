@@ -0,0 +1,159 @@
+//! Link-in-bio pages.
+//!
+//! Linktree-like services (and `fig.page`, `hmm.rs`, ...) don't lead to one
+//! destination but to a landing page listing many outbound links. With
+//! [`Options::link_pages`](crate::Options::link_pages), the destination of an
+//! expansion is fetched, and when it is such a page, every outbound link is
+//! reported in [`Expansion::links`](crate::Expansion::links) with its label
+//! and position.
+//!
+//! A page is a link-in-bio page when it is served by one of the known
+//! platforms ([`PLATFORMS`]), or when its markup carries the marks of one
+//! (custom domains).
+use scraper::{ElementRef, Html, Selector};
+use std::sync::LazyLock;
+use url::Url;
+
+use crate::{
+    idn::host_matches, parse_destination, resolvers::from_url, unwrap::unwrap_chain, Destination, Options,
+};
+
+static ANCHOR: LazyLock<Selector> = LazyLock::new(|| Selector::parse("a[href]").expect("valid anchor selector"));
+static IMAGE: LazyLock<Selector> = LazyLock::new(|| Selector::parse("img[alt]").expect("valid image selector"));
+
+/// Link-in-bio platforms, with their subdomains.
+static PLATFORMS: &[&str] = &[
+    "allmylinks.com",
+    "beacons.ai",
+    "bio.link",
+    "campsite.bio",
+    "fig.page",
+    "hmm.rs",
+    "linkin.bio",
+    "linktr.ee",
+    "lnk.bio",
+    "msha.ke",
+    "solo.to",
+    "tap.bio",
+    "taplink.cc",
+];
+
+/// Marks of the platforms in pages on custom domains, lowercase.
+static MARKS: &[&str] = &[
+    r#"data-testid="linkbutton""#,
+    r#"property="og:site_name" content="linktree""#,
+    r#"content="beacons""#,
+    r#"powered by lnk.bio"#,
+];
+
+/// One link of a link-in-bio page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OutboundLink {
+    /// The link, with redirectors around it removed
+    pub url: String,
+    /// Text of the link, or its `aria-label` / `title` / image `alt`
+    pub label: Option<String>,
+    /// Position of the link on the page, from 0
+    pub position: usize,
+}
+
+pub fn find_outbound_links(html: &str, page_url: &str) -> Vec<OutboundLink> {
+    //! Lists the outbound links of a page, in page order.
+    //!
+    //! # Arguments
+    //!
+    //! * `html` - The page
+    //! * `page_url` - URL of the page, to resolve relative links
+    //!
+    //! # Returns
+    //!
+    //! Returns every `http(s)` link off the page's host (and off the
+    //! platform's, for the known platforms), and every `mailto:` / `tel:` /
+    //! app link; the first of several links to the same target is kept.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::find_outbound_links;
+    //!
+    //! let page = r#"<a href="/privacy">Privacy</a>
+    //!     <a href="https://shop.example.com/"> My <b>shop</b> </a>
+    //!     <a href="mailto:me@example.com" aria-label="Mail me"></a>"#;
+    //! let links = find_outbound_links(page, "https://linktr.ee/someone");
+    //! assert_eq!(links.len(), 2);
+    //! assert_eq!((links[0].url.as_str(), links[0].label.as_deref()), ("https://shop.example.com/", Some("My shop")));
+    //! assert_eq!((links[1].position, links[1].label.as_deref()), (1, Some("Mail me")));
+    //! ```
+    find_links(html, page_url, true)
+}
+
+fn find_links(html: &str, page_url: &str, parameters: bool) -> Vec<OutboundLink> {
+    //! [`find_outbound_links`], with or without the redirect parameter
+    //! heuristic when unwrapping the links.
+    let Ok(page) = Url::parse(page_url) else {
+        return vec![];
+    };
+    let host = page.host_str().unwrap_or_default().to_ascii_lowercase();
+    let platform = PLATFORMS.iter().find(|d| host_matches(&host, d));
+    let is_outbound = |url: &Url| match url.scheme() {
+        "http" | "https" => url.host_str().is_some_and(|h| {
+            let h = h.to_ascii_lowercase();
+            h != host && !platform.is_some_and(|d| host_matches(&h, d))
+        }),
+        _ => !matches!(parse_destination(url.as_str()), Destination::Web { .. } | Destination::Other { .. }),
+    };
+
+    let mut links: Vec<OutboundLink> = vec![];
+    for anchor in Html::parse_document(html).select(&ANCHOR) {
+        let Some(url) = anchor.value().attr("href").and_then(|href| page.join(href.trim()).ok()) else {
+            continue;
+        };
+        let url = match unwrap_chain(url.as_str(), parameters).pop().map(|u| Url::parse(&u)) {
+            Some(Ok(inner)) => inner,
+            _ => url,
+        };
+        if !is_outbound(&url) || links.iter().any(|l| l.url == url.as_str()) {
+            continue;
+        }
+        links.push(OutboundLink {
+            url: url.into(),
+            label: label(&anchor),
+            position: links.len(),
+        });
+    }
+    links
+}
+
+pub(crate) async fn links(url: &str, options: &Options) -> Vec<OutboundLink> {
+    //! The outbound links of a destination, when it is a link-in-bio page;
+    //! empty otherwise, or when the page can't be fetched.
+    let Some(host) = Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase)) else {
+        return vec![];
+    };
+    let Ok(html) = from_url(url, options.timeout).await else {
+        return vec![];
+    };
+    let known = PLATFORMS.iter().any(|d| host_matches(&host, d));
+    let lower = html.to_ascii_lowercase();
+    if !known && !MARKS.iter().any(|mark| lower.contains(mark)) {
+        return vec![];
+    }
+    find_links(&html, url, !options.keep_parameters)
+}
+
+fn label(anchor: &ElementRef) -> Option<String> {
+    //! The visible text of a link, or its accessible name.
+    let text = anchor.text().flat_map(str::split_whitespace).collect::<Vec<_>>().join(" ");
+    let attr = |name: &str| anchor.value().attr(name).map(str::trim).filter(|v| !v.is_empty());
+    Some(text)
+        .filter(|t| !t.is_empty())
+        .or_else(|| attr("aria-label").map(String::from))
+        .or_else(|| attr("title").map(String::from))
+        .or_else(|| {
+            anchor
+                .select(&IMAGE)
+                .filter_map(|img| img.value().attr("alt").map(str::trim))
+                .find(|alt| !alt.is_empty())
+                .map(String::from)
+        })
+}
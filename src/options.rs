@@ -0,0 +1,170 @@
+//! Options controlling an expansion.
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use crate::{idn, ApiProvider};
+#[cfg(feature = "js")]
+use crate::SandboxLimits;
+
+/// Expansion options, built with chained setters.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use urlexpand::Options;
+///
+/// let options = Options::new()
+///     .timeout(Duration::from_secs(10))
+///     .refang(true)
+///     .defang(true);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) refang: bool,
+    pub(crate) defang: bool,
+    pub(crate) preview_first: bool,
+    pub(crate) apis: BTreeMap<ApiProvider, ApiAccess>,
+    pub(crate) instance_keys: BTreeMap<String, Secret>,
+    pub(crate) detect_platforms: bool,
+    pub(crate) keep_parameters: bool,
+    pub(crate) offline: bool,
+    pub(crate) link_pages: bool,
+    pub(crate) strip_affiliates: bool,
+    #[cfg(feature = "js")]
+    pub(crate) sandbox: SandboxLimits,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        //! Default options: no timeout.
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        //! Sets the timeout applied to every HTTP request.
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn refang(mut self, refang: bool) -> Self {
+        //! Accepts defanged input such as `hxxps://bit[.]ly/abc` (see
+        //! [`refang`](crate::refang)).
+        self.refang = refang;
+        self
+    }
+
+    pub fn defang(mut self, defang: bool) -> Self {
+        //! Also reports the destination and every hop in defanged form (see
+        //! [`defang`](crate::defang)), safe to paste into tickets.
+        self.defang = defang;
+        self
+    }
+
+    pub fn preview_first(mut self, preview_first: bool) -> Self {
+        //! Looks the destination up on the service's preview page or lookup
+        //! API first (see [`Preview`](crate::Preview)), so that the link owner
+        //! doesn't see a click; the link is only followed when the preview
+        //! doesn't show a destination.
+        self.preview_first = preview_first;
+        self
+    }
+
+    pub fn api_token(mut self, provider: ApiProvider, token: &str) -> Self {
+        //! Expands the links of the provider's services through its official
+        //! API, with this account token; the API also reports the link's
+        //! metadata (see [`LinkMetadata`](crate::LinkMetadata)). Without a
+        //! token, or when the API fails, the links are expanded as usual.
+        self.apis.entry(provider).or_default().token = Some(Secret(token.into()));
+        self
+    }
+
+    pub fn api_base_url(mut self, provider: ApiProvider, base_url: &str) -> Self {
+        //! Sends the provider's API requests to another base URL than
+        //! [`ApiProvider::base_url`], e.g. a local stand-in.
+        self.apis.entry(provider).or_default().base_url = Some(base_url.trim_end_matches('/').into());
+        self
+    }
+
+    pub fn instance_key(mut self, domain: &str, key: &str) -> Self {
+        //! Sets the API key of a self-hosted YOURLS (signature token), Shlink,
+        //! Kutt or Polr instance, by its service domain (see
+        //! [`Strategy::Yourls`](crate::Strategy::Yourls) and the following).
+        //! Shlink, Kutt and Polr links are only expanded through their API
+        //! with a key.
+        self.instance_keys.insert(instance_domain(domain), Secret(key.into()));
+        self
+    }
+
+    pub fn detect_platforms(mut self, detect_platforms: bool) -> Self {
+        //! Probes the hosts of unknown domains for a self-hosted YOURLS,
+        //! Shlink, Kutt or Polr instance, and expands their links as such. The
+        //! instances found are not registered: each expansion probes again
+        //! (see [`register_service`](crate::register_service) to keep them).
+        //! Without it, only the known and registered domains are expanded.
+        self.detect_platforms = detect_platforms;
+        self
+    }
+
+    pub fn unwrap_parameters(mut self, unwrap_parameters: bool) -> Self {
+        //! Unwraps links carrying a URL in a `redirect`, `to`, `dest`, ...
+        //! query parameter (see [`unwrap_link`](crate::unwrap_link)); on by
+        //! default. The known redirectors are unwrapped either way.
+        self.keep_parameters = !unwrap_parameters;
+        self
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        //! Expands without any request: links are only unwrapped and mapped by
+        //! the offline resolvers (see [`Rewrite`](crate::Rewrite)), and the
+        //! other short links fail with [`Error::Offline`](crate::Error::Offline).
+        self.offline = offline;
+        self
+    }
+
+    pub fn link_pages(mut self, link_pages: bool) -> Self {
+        //! Fetches the destination, and when it is a link-in-bio page
+        //! (Linktree-like), lists its outbound links in
+        //! [`Expansion::links`](crate::Expansion::links).
+        self.link_pages = link_pages;
+        self
+    }
+
+    pub fn strip_affiliates(mut self, strip_affiliates: bool) -> Self {
+        //! Removes the affiliate parameters from the destination (see
+        //! [`strip_affiliates`](crate::strip_affiliates)); the tags are still
+        //! reported in [`Expansion::affiliates`](crate::Expansion::affiliates),
+        //! and the hops keep them.
+        self.strip_affiliates = strip_affiliates;
+        self
+    }
+
+    #[cfg(feature = "js")]
+    pub fn sandbox(mut self, limits: SandboxLimits) -> Self {
+        //! Sets the limits of the script sandbox (see
+        //! [`run_scripts`](crate::run_scripts)).
+        self.sandbox = limits;
+        self
+    }
+}
+
+/// The token and base URL of an official API.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ApiAccess {
+    pub(crate) token: Option<Secret>,
+    pub(crate) base_url: Option<String>,
+}
+
+/// A token or key, kept out of logs.
+#[derive(Clone)]
+pub(crate) struct Secret(pub(crate) String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+pub(crate) fn instance_domain(domain: &str) -> String {
+    //! The key of an instance domain in [`Options::instance_key`], the same
+    //! whichever case or form (Unicode or `xn--`) the domain is written in.
+    idn::domain_to_ascii(domain).unwrap_or_else(|| domain.trim().trim_end_matches('.').to_lowercase())
+}
@@ -0,0 +1,85 @@
+//! Meta refresh and `Refresh` header parsing.
+//!
+//! Some shorteners answer with a page that reloads itself to the destination,
+//! either with the HTTP `Refresh` header or with a
+//! `<meta http-equiv="refresh" content="0; url=...">` tag. The content value
+//! is parsed the way browsers do: the delay, then an optional `url=` (in any
+//! case, with whitespace around `=`, quoted or not). Meta tags are found with
+//! an HTML tokenizer, so `URL=` text elsewhere in the page is never picked up
+//! and attribute entities such as `&amp;` are decoded.
+use scraper::{Html, Selector};
+use std::{sync::LazyLock, time::Duration};
+
+static META: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("meta[http-equiv][content]").expect("valid meta selector"));
+
+/// A parsed refresh instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refresh {
+    /// Delay before the refresh
+    pub delay: Duration,
+    /// Target of the refresh, as written (possibly relative); `None` reloads
+    /// the page itself
+    pub url: Option<String>,
+}
+
+pub fn parse_refresh(content: &str) -> Option<Refresh> {
+    //! Parses the value of a `Refresh` header or of a meta refresh `content`
+    //! attribute.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use std::time::Duration;
+    //! use urlexpand::parse_refresh;
+    //!
+    //! let refresh = parse_refresh("5; url = 'https://example.com/?a=1'").unwrap();
+    //! assert_eq!(refresh.delay, Duration::from_secs(5));
+    //! assert_eq!(refresh.url.as_deref(), Some("https://example.com/?a=1"));
+    //! ```
+    let s = content.trim_start();
+    // delay: digits, then an ignored fractional part
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let rest = s[digits..].trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if digits == 0 && rest.len() == s.len() {
+        return None;
+    }
+    let delay = Duration::from_secs(s[..digits].parse().unwrap_or(u64::MAX));
+
+    let mut rest = rest.trim_start();
+    if let Some(r) = rest.strip_prefix([';', ',']) {
+        rest = r.trim_start();
+    }
+    // optional `url =`
+    if let Some(key) = rest.get(..3).filter(|k| k.eq_ignore_ascii_case("url")) {
+        let after = rest[key.len()..].trim_start();
+        if let Some(value) = after.strip_prefix('=') {
+            rest = value.trim_start();
+        }
+    }
+    let url = match rest.chars().next() {
+        Some(q @ ('"' | '\'')) => rest[1..].split(q).next().unwrap_or_default(),
+        _ => rest,
+    }
+    .trim();
+    Some(Refresh {
+        delay,
+        url: (!url.is_empty()).then(|| url.into()),
+    })
+}
+
+pub fn meta_refresh(html: &str) -> Option<Refresh> {
+    //! Finds the first `<meta http-equiv="refresh">` tag of a page and parses
+    //! its content.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::meta_refresh;
+    //!
+    //! let html = r#"<meta http-equiv="Refresh" content="0;url='/next?a=1&amp;b=2'">"#;
+    //! assert_eq!(meta_refresh(html).unwrap().url.as_deref(), Some("/next?a=1&b=2"));
+    //! ```
+    Html::parse_document(html)
+        .select(&META)
+        .filter(|m| m.value().attr("http-equiv").is_some_and(|h| h.trim().eq_ignore_ascii_case("refresh")))
+        .find_map(|m| parse_refresh(m.value().attr("content")?))
+}
@@ -1,13 +1,11 @@
 // adfoc.us shortening service
-use std::time::Duration;
-
-use super::from_url_not_200;
+use super::{from_url_not_200, Context};
 
 use futures::future::{ready, TryFutureExt};
 
-use crate::{Error, Result};
+use crate::{rules, services::Stage, Error, Result};
 
-pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<String> {
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Expands URLs shortened by Adfoc.us.
     //!
     //! This resolver handles Adfoc.us's JavaScript-based redirect mechanism
@@ -17,7 +15,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! # Arguments
     //!
     //! * `url` - The Adfoc.us shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -27,17 +25,10 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! # Behavior
     //!
     //! - Fetches the HTML content of the short URL (expecting non-200 status)
-    //! - Extracts the click_url parameter from JavaScript in the page
+    //! - Extracts the click_url parameter from JavaScript in the page (`adfoc.us`
+    //!   extraction rule)
     //! - Returns the extracted URL
-    from_url_not_200(url, timeout)
-        .and_then(|html| {
-            ready(
-                html.split("click_url = \"")
-                    .nth(1)
-                    .and_then(|r| r.split("\";").next())
-                    .map(Into::into)
-                    .ok_or(Error::NoString),
-            )
-        })
+    from_url_not_200(url, ctx.timeout())
+        .and_then(|html| ready(rules::extract(ctx.service, Stage::Api, &html).ok_or(Error::NoString)))
         .await
 }
@@ -0,0 +1,96 @@
+// Self-hosted shortener platforms, recognised from their endpoints and pages
+use core::time::Duration;
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use serde_json::Value;
+use url::{ParseError, Url};
+
+use super::{get_client_builder, pipeline::read_body};
+use crate::{Options, Service, Strategy};
+
+/// Marks of the platforms in the home page of an instance, lowercased.
+static FINGERPRINTS: &[(&str, Strategy)] = &[
+    ("github.com/thedevs-network/kutt", Strategy::Kutt),
+    ("powered by kutt", Strategy::Kutt),
+    ("github.com/cydrobolt/polr", Strategy::Polr),
+    ("powered by polr", Strategy::Polr),
+    ("github.com/yourls/yourls", Strategy::Yourls),
+    ("powered by yourls", Strategy::Yourls),
+    ("shlink.io", Strategy::Shlink),
+];
+
+pub(crate) async fn detect(url: &str, options: &Options) -> Option<(String, Service)> {
+    //! The service of a URL on an unknown domain, when its host runs a
+    //! self-hosted shortener platform. The service is only used for the
+    //! expansion at hand: probing doesn't change the registry.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The URL to expand (a missing scheme means `https`)
+    //! * `options` - The caller's options (timeout)
+    //!
+    //! # Returns
+    //!
+    //! Returns the normalised URL and its service, `None` when no platform was
+    //! recognised.
+    let parts = match Url::parse(url) {
+        Err(ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{url}")).ok()?,
+        parts => parts.ok()?,
+    };
+    let domain = parts.domain()?;
+    if parts.path().trim_matches('/').is_empty() {
+        return None;
+    }
+    let strategy = platform(&parts, options.timeout).await?;
+    let service = Service::new(domain, strategy);
+    Some((parts.into(), service))
+}
+
+pub(crate) async fn platform(url: &Url, timeout: Option<Duration>) -> Option<Strategy> {
+    //! The self-hosted platform serving a URL.
+    //!
+    //! # Behavior
+    //!
+    //! - Shlink answers `/rest/health` with an `application/health+json` status
+    //! - YOURLS answers its `yourls-api.php` next to the keyword with a version,
+    //!   or with a 403 JSON error on private instances
+    //! - Kutt, Polr (and the others) are recognised by the marks of their home page
+    let client = get_client_builder(timeout).build().ok()?;
+
+    let (status, content_type, body) = probe(&client, url.join("/rest/health").ok()?).await?;
+    let json = serde_json::from_str::<Value>(&body).ok();
+    if status.is_success()
+        && json.as_ref().is_some_and(|j| j.get("status").is_some())
+        && (content_type.contains("health+json") || body.contains("shlink"))
+    {
+        return Some(Strategy::Shlink);
+    }
+
+    let mut api = url.join("yourls-api.php").ok()?;
+    api.query_pairs_mut().append_pair("action", "version").append_pair("format", "json");
+    let (status, _, body) = probe(&client, api).await?;
+    let json = serde_json::from_str::<Value>(&body).ok();
+    if json.is_some_and(|j| {
+        (status.is_success() && j.get("version").is_some())
+            || (status == StatusCode::FORBIDDEN && j.get("errorCode").is_some())
+    }) {
+        return Some(Strategy::Yourls);
+    }
+
+    let (_, _, body) = probe(&client, url.join("/").ok()?).await?;
+    let body = body.to_lowercase();
+    FINGERPRINTS.iter().find(|(mark, _)| body.contains(mark)).map(|(_, strategy)| *strategy)
+}
+
+async fn probe(client: &Client, url: Url) -> Option<(StatusCode, String, String)> {
+    //! The status, content type and body (up to the pipeline's limit) of a
+    //! probe request.
+    let response = client.get(url).send().await.ok()?;
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    Some((status, content_type, read_body(response).await))
+}
@@ -1,21 +1,33 @@
-// FALLBACK Resolver - Hybrid approach using reqwest + curl fallback
-use std::process::Command;
-use std::time::Duration;
+// FALLBACK Resolver - reqwest, then a request shaped like curl's
+use std::time::Instant;
 
-use crate::Result;
-use crate::resolvers::generic;
+use futures::future::{ready, TryFutureExt};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, HOST, LOCATION, USER_AGENT},
+    redirect::Policy,
+    Client,
+};
+use url::Url;
 
-pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<String> {
-    //! Expands URLs using a hybrid approach with curl fallback.
+use crate::resolvers::{get_client_builder, generic, Context};
+use crate::{Error, Result};
+
+/// User agent of the curl command line tool.
+static CURL_CLI_UA: &str = "curl/8.5.0";
+
+/// Redirects followed by the curl-like client, curl's `--max-redirs` default.
+const MAX_REDIRECTS: usize = 50;
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
+    //! Expands URLs with reqwest, falling back to a request shaped like curl's.
     //!
-    //! This resolver first tries reqwest, and if that fails, falls back to using
-    //! the curl command to get the redirect location. It's designed for services
-    //! that work with curl but have issues with reqwest's HTTP client.
+    //! Some services (goo.gl, ow.ly) answer the curl command line tool with a
+    //! redirect but reqwest's default requests with a page.
     //!
     //! # Arguments
     //!
     //! * `url` - The shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -24,35 +36,116 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //!
     //! # Behavior
     //!
-    //! - First tries reqwest with custom user agent
-    //! - Falls back to curl command if reqwest fails
-    //! - Handles network errors and timeouts appropriately
-    
-    // Clone the URL to move it into the blocking task
-    let url = url.to_string();
-    
-    // First try reqwest
-    let reqwest_result = generic::unshort_with_curl_ua(&url, timeout).await;
-    
-    match reqwest_result {
-        Ok(expanded_url) => {
-            // Check if reqwest actually expanded the URL
-            if expanded_url != url {
-                Ok(expanded_url)
-            } else {
-                // reqwest didn't expand, try curl fallback
-                curl_fallback(&url, timeout).await
+    //! - First tries reqwest with the service's user agent profile
+    //! - Falls back to [`curl_like`] when reqwest fails or lands on the URL itself
+    //! - With the `curl-command` feature, the `curl` binary is tried before [`curl_like`]
+    match generic::unshort(url, ctx).await {
+        Ok(expanded_url) if expanded_url != url => Ok(expanded_url),
+        _ => curl(url, ctx).await,
+    }
+}
+
+async fn curl(url: &str, ctx: &Context<'_>) -> Result<String> {
+    //! The curl fallback: the native [`curl_like`] client, after the `curl`
+    //! binary with the `curl-command` feature.
+    #[cfg(feature = "curl-command")]
+    if let Ok(found) = curl_command(url, ctx.timeout()).await {
+        return Ok(found);
+    }
+    curl_like(url, ctx).await
+}
+
+async fn curl_like(url: &str, ctx: &Context<'_>) -> Result<String> {
+    //! Follows the redirects of a URL the way `curl -L` does.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Ok(String)` with the final URL (curl's `url_effective`) when it
+    //! differs from `url`, or `Err(Error)` otherwise.
+    //!
+    //! # Behavior
+    //!
+    //! - HTTP/1.1 only, with title case header names in curl's order:
+    //!   `Host`, `User-Agent: curl/...`, `Accept: */*`, and nothing else (no
+    //!   `Accept-Encoding`, as curl without `--compressed`)
+    //! - Redirects are followed one request at a time, so that every request
+    //!   has the same shape, up to curl's limit of 50; each one is recorded
+    //!   as a hop, and a non-HTTP target (`tel:`, `market://`, ...) ends the
+    //!   chain
+    //! - The timeout bounds the whole chain, like `--max-time`, with
+    //!   sub-second precision
+    let deadline = ctx.timeout().map(|t| Instant::now() + t);
+    let client = ready(
+        get_client_builder(None)
+            .http1_only()
+            .http1_title_case_headers()
+            .redirect(Policy::none())
+            .build(),
+    )
+    .err_into::<Error>()
+    .await?;
+
+    let mut current = Url::parse(url).map_err(|_| Error::NoString)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let response = request(&client, &current, deadline)?.send().await?;
+        let location = response
+            .status()
+            .is_redirection()
+            .then(|| response.headers().get(LOCATION))
+            .flatten()
+            .and_then(|l| current.join(l.to_str().ok()?.trim()).ok());
+        match location {
+            // curl doesn't follow non-HTTP targets either
+            Some(next) if !matches!(next.scheme(), "http" | "https") => {
+                ctx.hops.push(next.as_str());
+                return Ok(next.into());
+            }
+            Some(next) => {
+                ctx.hops.push(next.as_str());
+                current = next;
+            }
+            None => {
+                return if current.as_str() != url {
+                    Ok(current.into())
+                } else {
+                    Err(Error::NoString)
+                };
             }
-        }
-        Err(_) => {
-            // reqwest failed, try curl fallback
-            curl_fallback(&url, timeout).await
         }
     }
+    Err(Error::Reqwest("too many redirects".into()))
 }
 
-async fn curl_fallback(url: &str, timeout: Option<Duration>) -> Result<String> {
-    //! Fallback method using curl command to get redirect location.
+fn request(client: &Client, url: &Url, deadline: Option<Instant>) -> Result<reqwest::RequestBuilder> {
+    //! A GET request with curl's headers, bounded by what is left before the
+    //! deadline.
+    let host = &url[url::Position::BeforeHost..url::Position::AfterPort];
+    let mut headers = HeaderMap::new();
+    headers.insert(HOST, HeaderValue::from_str(host).map_err(|_| Error::NoString)?);
+    headers.insert(USER_AGENT, HeaderValue::from_static(CURL_CLI_UA));
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+
+    let request = client.get(url.as_str()).headers(headers);
+    Ok(match deadline {
+        Some(deadline) => {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(Error::Reqwest("operation timed out".into()));
+            }
+            request.timeout(left)
+        }
+        None => request,
+    })
+}
+
+#[cfg(feature = "curl-command")]
+async fn curl_command(url: &str, timeout: Option<std::time::Duration>) -> Result<String> {
+    //! Fallback method using the curl command to get the redirect location.
     //!
     //! # Arguments
     //!
@@ -63,40 +156,37 @@ async fn curl_fallback(url: &str, timeout: Option<Duration>) -> Result<String> {
     //!
     //! Returns `Ok(String)` with the final destination URL on success,
     //! or `Err(Error)` if the URL cannot be expanded.
-    
-    // Build curl command to get the final URL after redirects
+    //!
+    //! # Behavior
+    //!
+    //! - The command runs on its own thread, so it doesn't block the executor
+    //! - A missing `curl` binary is an `Error::NoString`
+    use futures::channel::oneshot;
+    use std::process::Command;
+
     let mut cmd = Command::new("curl");
-    cmd.arg("-s");
-    cmd.arg("-L"); // Follow redirects
-    cmd.arg("-w");
-    cmd.arg("%{url_effective}"); // Show final URL
-    cmd.arg("-o");
-    cmd.arg("/dev/null"); // Discard body
-    cmd.arg(url);
-    
-    // Set timeout if provided
+    cmd.args(["-s", "-L", "-w", "%{url_effective}", "-o", "/dev/null", url]);
     if let Some(timeout) = timeout {
-        cmd.arg("--max-time");
-        cmd.arg(timeout.as_secs().to_string());
+        cmd.arg("--max-time").arg(format!("{:.3}", timeout.as_secs_f64()));
     }
-    
-    // Execute curl command
-    let output = cmd.output().map_err(|e| {
-        // Check if curl command is not found
+
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(cmd.output());
+    });
+    let output = rx.await.map_err(|_| Error::Unknown)?.map_err(|e| {
         if e.kind() == std::io::ErrorKind::NotFound {
-            crate::Error::NoString
+            Error::NoString
         } else {
-            crate::Error::Reqwest(e.to_string())
+            Error::Reqwest(e.to_string())
         }
     })?;
-    
-    // Get the final URL from curl output
+
     let output_str = String::from_utf8_lossy(&output.stdout);
     let final_url = output_str.trim();
-    
     if !final_url.is_empty() && final_url != url {
         Ok(final_url.to_string())
     } else {
-        Err(crate::Error::NoString)
+        Err(Error::NoString)
     }
 }
@@ -0,0 +1,50 @@
+// Kutt - self-hosted (kutt.it is the public instance), with the v2 API
+use futures::future::{ready, TryFutureExt};
+use url::Url;
+
+use super::{get_client_builder, json, Context};
+use crate::{Error, Result};
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Option<Result<String>> {
+    //! Expands URLs of a Kutt instance through its links API; `None` without
+    //! an API key, which Kutt always requires.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The shortened URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Some(Ok(String))` with the `target` of the link on success,
+    //! or `Some(Err(Error))` if the API doesn't know it.
+    //!
+    //! # Behavior
+    //!
+    //! - Searches `/api/v2/links` for the address, with the `X-API-KEY` set
+    //!   with [`Options::instance_key`](crate::Options::instance_key); only the
+    //!   links of that account are found
+    let key = ctx.instance_key()?;
+    let short = Url::parse(url).ok()?;
+    let address = short.path().trim_matches('/');
+    if address.is_empty() {
+        return Some(Err(Error::NoString));
+    }
+    let mut api = short.join("/api/v2/links").ok()?;
+    api.query_pairs_mut().append_pair("search", address).append_pair("limit", "50");
+    Some(
+        ready(get_client_builder(ctx.timeout()).build())
+            .err_into()
+            .and_then(|client| json(client.get(api).header("X-API-KEY", key)))
+            .and_then(|answer| {
+                let found = answer
+                    .get("data")
+                    .and_then(|d| d.as_array())
+                    .and_then(|links| links.iter().find(|l| l.get("address").and_then(|a| a.as_str()) == Some(address)))
+                    .and_then(|link| link.get("target")?.as_str())
+                    .and_then(|u| ctx.destination(u));
+                ready(found.ok_or(Error::NoString))
+            })
+            .await,
+    )
+}
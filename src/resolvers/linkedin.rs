@@ -4,13 +4,12 @@
 // 2. Interstitial warning page with URL in HTML (when flagged/rate-limited)
 // We try both approaches for robustness
 
-use crate::resolvers::{from_url, generic};
+use crate::resolvers::{from_url, generic, Context};
 use futures::future::{ready, TryFutureExt};
-use std::time::Duration;
 
-use crate::{Error, Result};
+use crate::{rules, services::Stage, Error, Result};
 
-pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<String> {
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Expands LinkedIn shortened URLs (lnkd.in).
     //!
     //! LinkedIn uses two different redirect mechanisms:
@@ -22,7 +21,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! # Arguments
     //!
     //! * `url` - The LinkedIn shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -36,19 +35,19 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! - Extracts the final URL from the warning page HTML
     //! - Returns the expanded URL or falls back to the redirect result
     // First try standard HTTP redirect (most common LinkedIn behavior)
-    let expanded_url = generic::unshort(url, timeout).await?;
+    let expanded_url = generic::unshort(url, ctx).await?;
 
     // If we're still on LinkedIn domain, try parsing the interstitial page
     Ok(
         if expanded_url.contains("linkedin.com") || expanded_url.contains("lnkd.in") {
-            get_from_html(url, timeout).await.unwrap_or_else(|_| expanded_url)
+            get_from_html(url, ctx).await.unwrap_or(expanded_url)
         } else {
             expanded_url
         },
     )
 }
 
-async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
+async fn get_from_html(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Extracts the final URL from LinkedIn's interstitial warning page.
     //!
     //! This function parses the HTML content of LinkedIn's warning page
@@ -57,7 +56,7 @@ async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
     //! # Arguments
     //!
     //! * `url` - The LinkedIn URL that may show an interstitial page
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -68,18 +67,9 @@ async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
     //!
     //! - Fetches the HTML content of the URL
     //! - Searches for the external URL link in the warning page
-    //! - Extracts the href attribute from the tracking link
-    from_url(url, timeout)
-        .and_then(|html| {
-            ready(
-                // Parse the interstitial warning page
-                html.split("data-tracking-control-name=\"external_url_click\"")
-                    .nth(1)
-                    .and_then(|r| r.split("href=\"").nth(1))
-                    .and_then(|r| r.split("\">").next())
-                    .map(|r| r.to_string())
-                    .ok_or(Error::NoString),
-            )
-        })
+    //! - Extracts the href attribute from the tracking link (`lnkd.in`
+    //!   extraction rule)
+    from_url(url, ctx.timeout())
+        .and_then(|html| ready(rules::extract(ctx.service, Stage::Api, &html).ok_or(Error::NoString)))
         .await
 }
@@ -1,25 +1,162 @@
+// The functions here carry a one-line `///` summary above the `//!` docs.
+#![allow(clippy::mixed_attributes_style)]
+
 use core::time::Duration;
-use regex::Regex;
-use reqwest::{redirect::Policy, Client, ClientBuilder, StatusCode};
+use reqwest::{header::HeaderMap, redirect::Policy, Client, ClientBuilder, RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use url::Url;
 
 pub(crate) mod adfly;
 pub(crate) mod adfocus;
-pub(crate) mod browser;
-pub(crate) mod curl;
+pub(crate) mod detect;
 pub(crate) mod fallback;
 pub(crate) mod generic;
+pub(crate) mod kutt;
 pub(crate) mod linkedin;
+pub(crate) mod official;
+pub(crate) mod pipeline;
+pub(crate) mod polr;
+pub(crate) mod preview;
 pub(crate) mod redirect;
-pub(crate) mod refresh;
+pub(crate) mod shlink;
 pub(crate) mod shorturl;
 pub(crate) mod surlli;
 pub(crate) mod urlshortdev;
+pub(crate) mod yourls;
 
 use futures::future::{ready, TryFutureExt};
 
-use crate::Result;
+use crate::{
+    options::instance_domain,
+    services::{RedirectPolicy, Service, UaProfile},
+    Error, Options, Result,
+};
+
+static UA: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0";
+static CURL_UA: &str = "URLEXPANDER/0.3";
+
+/// URLs visited while expanding, shared with the redirect policies.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Hops(Arc<Mutex<Vec<String>>>);
+
+impl Hops {
+    pub(crate) fn push(&self, url: &str) {
+        //! Records a visited URL, ignoring immediate repeats.
+        let mut hops = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if hops.last().is_none_or(|last| last != url) {
+            hops.push(url.into());
+        }
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<String> {
+        //! The visited URLs, in order.
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Everything a resolver needs to know about the expansion in progress.
+pub(crate) struct Context<'a> {
+    /// The service entry matched for the URL
+    pub(crate) service: &'a Service,
+    /// The caller's options
+    pub(crate) options: &'a Options,
+    /// URLs visited so far
+    pub(crate) hops: Hops,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(service: &'a Service, options: &'a Options) -> Self {
+        //! Creates the context of a new expansion.
+        Self {
+            service,
+            options,
+            hops: Hops::default(),
+        }
+    }
+
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        //! Timeout applied to every HTTP request.
+        self.options.timeout
+    }
+
+    pub(crate) fn instance_key(&self) -> Option<&str> {
+        //! The API key set for the service's self-hosted instance.
+        self.options.instance_keys.get(&instance_domain(&self.service.domain)).map(|k| k.0.as_str())
+    }
+
+    pub(crate) fn destination(&self, candidate: &str) -> Option<String> {
+        //! A destination reported by an API or page: an `http(s)` URL off the
+        //! service's hosts.
+        let url = url::Url::parse(candidate.trim()).ok()?;
+        (matches!(url.scheme(), "http" | "https") && !self.service.matches_url(url.as_str())).then(|| url.into())
+    }
+
+    pub(crate) fn client_builder(&self) -> ClientBuilder {
+        //! A client builder with the service's UA profile and redirect policy,
+        //! recording every followed redirect as a hop.
+        get_client_builder_for(
+            self.timeout(),
+            self.service.ua_profile(),
+            self.service.redirect_policy(),
+            &self.hops,
+        )
+    }
+}
+
+/// Run a URL through the resolution pipeline of its service
+pub(crate) async fn resolve(url: &str, ctx: &Context<'_>) -> Result<pipeline::Resolved> {
+    //! Expands a URL with the pipeline stages of its service (see [`Stage`](crate::Stage)).
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The validated short URL
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns the destination, the stage that found it and the refresh delay.
+    //!
+    //! # Behavior
+    //!
+    //! - The redirect-following stages honour the service's UA profile and
+    //!   redirect policy overrides
+    //! - The page-parsing stages locate the destination with the service's
+    //!   extraction rules
+    //! - The API stage runs the dedicated resolver of the service's [`Strategy`](crate::Strategy)
+    ctx.hops.push(url);
+    let resolved = pipeline::run(url, ctx).await?;
+    ctx.hops.push(&resolved.url);
+    Ok(resolved)
+}
 
-static  UA: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0";
+/// Run a captured page through the resolution pipeline of its service
+pub(crate) fn resolve_captured(
+    url: &str,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: &str,
+    ctx: &Context<'_>,
+) -> Result<pipeline::Resolved> {
+    //! Finds the destination of a page captured earlier, without any request.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - URL of the captured page
+    //! * `status` - HTTP status of the captured response
+    //! * `headers` - HTTP headers of the captured response
+    //! * `body` - Body of the captured response
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns the destination, the stage that found it and the refresh delay.
+    ctx.hops.push(url);
+    let page = pipeline::Page::captured(url, headers, body);
+    let resolved = pipeline::run_captured(page, status, ctx)?;
+    ctx.hops.push(&resolved.url);
+    Ok(resolved)
+}
 
 /// Get the reqwest ClientBuilder with common configuration
 pub(crate) fn get_client_builder(timeout: Option<Duration>) -> ClientBuilder {
@@ -42,20 +179,70 @@ pub(crate) fn get_client_builder(timeout: Option<Duration>) -> ClientBuilder {
     //! - Sets a realistic browser User-Agent string
     //! - Accepts invalid SSL certificates (for some shorteners)
     //! - Applies timeout if provided
+    //! - Follows up to 10 redirects, like reqwest's default policy, and stops
+    //!   at non-HTTP targets (`tel:`, `market://`, ...) instead of failing
     match timeout {
         Some(x) => Client::builder().timeout(x),
         None => Client::builder(),
     }
     .user_agent(UA)
     .danger_accept_invalid_certs(true)
+    .redirect(Policy::custom(|attempt| {
+        if !is_web(attempt.url()) {
+            attempt.stop()
+        } else if attempt.previous().len() > 10 {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    }))
+}
+
+/// Get the reqwest ClientBuilder for a UA profile and redirect policy
+pub(crate) fn get_client_builder_for(
+    timeout: Option<Duration>,
+    ua: UaProfile,
+    redirect: RedirectPolicy,
+    hops: &Hops,
+) -> ClientBuilder {
+    //! Creates a [`get_client_builder`] with the given user agent profile and
+    //! redirect policy applied.
+    //!
+    //! # Arguments
+    //!
+    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ua` - User agent profile (the browser `Accept*` headers are added per request)
+    //! * `redirect` - Which redirects reqwest follows automatically
+    //! * `hops` - Where every followed redirect is recorded
+    let builder = match ua {
+        UaProfile::Curl => get_client_builder(timeout).user_agent(CURL_UA),
+        UaProfile::Default | UaProfile::Browser => get_client_builder(timeout),
+    };
+    let hops = hops.clone();
+    builder.redirect(match redirect {
+        RedirectPolicy::SameHost => custom_redirect_policy(hops),
+        RedirectPolicy::Follow => Policy::custom(move |attempt| {
+            // same limit as reqwest's default policy
+            if !is_web(attempt.url()) {
+                attempt.stop()
+            } else if attempt.previous().len() > 10 {
+                attempt.error("too many redirects")
+            } else {
+                hops.push(attempt.url().as_str());
+                attempt.follow()
+            }
+        }),
+        RedirectPolicy::None => Policy::none(),
+    })
 }
 
 /// Reqwest Custom Redirect Policy
-pub(crate) fn custom_redirect_policy() -> Policy {
+pub(crate) fn custom_redirect_policy(hops: Hops) -> Policy {
     //! Creates a custom redirect policy for safe URL expansion.
     //!
     //! This policy prevents potentially malicious cross-domain redirects
-    //! while allowing legitimate same-domain redirect chains.
+    //! while allowing legitimate same-domain redirect chains. Every followed
+    //! redirect is recorded in `hops`.
     //!
     //! # Returns
     //!
@@ -66,16 +253,24 @@ pub(crate) fn custom_redirect_policy() -> Policy {
     //! - Allows redirects within the same domain
     //! - Stops redirects when the domain changes from the original
     //! - Prevents potential redirect loops or malicious chains
-    Policy::custom(|attempt| {
+    //! - Stops at non-HTTP targets (`tel:`, `intent://...`, ...), which reqwest
+    //!   can't follow
+    Policy::custom(move |attempt| {
         let n_attempt = attempt.previous().len();
-        if attempt.previous()[0].host() != attempt.previous()[n_attempt - 1].host() {
+        if !is_web(attempt.url()) || attempt.previous()[0].host() != attempt.previous()[n_attempt - 1].host() {
             attempt.stop()
         } else {
+            hops.push(attempt.url().as_str());
             attempt.follow()
         }
     })
 }
 
+fn is_web(url: &Url) -> bool {
+    //! Whether reqwest can request a URL.
+    matches!(url.scheme(), "http" | "https")
+}
+
 /// Get Page Content if status != 200
 pub(crate) async fn from_url_not_200(url: &str, timeout: Option<Duration>) -> Result<String> {
     //! Fetches HTML content from URLs that typically return non-200 status codes.
@@ -97,7 +292,8 @@ pub(crate) async fn from_url_not_200(url: &str, timeout: Option<Duration>) -> Re
     //! # Behavior
     //!
     //! - Sets appropriate headers for HTML content
-    //! - Returns content only when status code is not 200
+    //! - Returns content only when status code is not 200, up to
+    //!   [`pipeline::MAX_BODY`] bytes
     //! - Returns error for 200 status (expected for these shorteners)
     ready(get_client_builder(timeout).build())
         .and_then(|client| async move {
@@ -117,7 +313,7 @@ pub(crate) async fn from_url_not_200(url: &str, timeout: Option<Duration>) -> Re
             if response.status() == StatusCode::OK {
                 Err(crate::error::Error::NoString)
             } else {
-                Ok(response.text().await?)
+                Ok(pipeline::read_body(response).await)
             }
         })
         .await
@@ -144,7 +340,8 @@ pub(crate) async fn from_url(url: &str, timeout: Option<Duration>) -> Result<Str
     //! # Behavior
     //!
     //! - Sets appropriate headers for HTML content
-    //! - Returns content for any status code
+    //! - Returns content for any status code, up to [`pipeline::MAX_BODY`]
+    //!   bytes
     //! - Used for parsing pages that may contain redirect information
     ready(get_client_builder(timeout).build())
         .and_then(|client| async move {
@@ -160,38 +357,19 @@ pub(crate) async fn from_url(url: &str, timeout: Option<Duration>) -> Result<Str
                 .await
         })
         .err_into()
-        .and_then(|response| async move { Ok(response.text().await?) })
+        .and_then(|response| async move { Ok(pipeline::read_body(response).await) })
         .await
 }
 
-/// Extract text from regex pattern
-fn from_re(txt: &str, p: &str) -> Option<String> {
-    //! Extracts the first capture group from text using a regex pattern.
-    //!
-    //! This helper function is used throughout resolvers to extract URLs
-    //! or other information from HTML content using regular expressions.
-    //!
-    //! # Arguments
-    //!
-    //! * `txt` - The text to search within
-    //! * `p` - The regex pattern to use for matching
+/// Get the JSON answer of an API request
+pub(crate) async fn json(request: RequestBuilder) -> Result<Value> {
+    //! Sends an API request and parses its JSON answer.
     //!
     //! # Returns
     //!
-    //! Returns `Some(String)` with the first capture group if a match is found,
-    //! or `None` if no match is found or the regex is invalid.
-    //!
-    //! # Behavior
-    //!
-    //! - Compiles the regex pattern
-    //! - Returns the first capture group (skipping the full match)
-    //! - Handles regex compilation errors gracefully
-    Regex::new(p)
-        .ok()
-        .and_then(|pattern| {
-            pattern
-                .captures(txt)
-                .and_then(|c| c.iter().skip(1).flatten().next())
-        })
-        .map(|x| x.as_str().into())
+    //! Returns `Ok(Value)` for a 2xx JSON answer, or `Err(Error)` for a failed
+    //! request, another status or a body that isn't JSON (or is longer than
+    //! [`pipeline::MAX_BODY`]).
+    let response = request.header("Accept", "application/json").send().await?.error_for_status()?;
+    serde_json::from_str(&pipeline::read_body(response).await).map_err(|_| Error::NoString)
 }
@@ -0,0 +1,94 @@
+// Official APIs of the shorteners, with an account token
+use futures::future::{ready, TryFutureExt};
+use serde_json::Value;
+use url::Url;
+
+use super::{get_client_builder, json, Context};
+use crate::{ApiProvider, Error, LinkMetadata, Result};
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Option<Result<(String, LinkMetadata)>> {
+    //! Expands a URL through the official API of its service; `None` when the
+    //! service has no API or no token is set for it.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The shortened URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Some(Ok((String, LinkMetadata)))` with the destination and
+    //! what the API reports about the link, or `Some(Err(Error))` if the API
+    //! doesn't know it.
+    //!
+    //! # Behavior
+    //!
+    //! - The link is looked up by its domain and key (path), so branded
+    //!   domains work too; the short URL itself is never requested
+    //! - Requests go to the base URL set with
+    //!   [`Options::api_base_url`](crate::Options::api_base_url), or the
+    //!   provider's own
+    //! - An answer that isn't a 2xx or holds no `http(s)` destination is an error
+    let provider = ctx.service.api()?;
+    let access = ctx.options.apis.get(&provider)?;
+    let token = access.token.as_ref()?.0.as_str();
+    let base = access.base_url.as_deref().unwrap_or(provider.base_url());
+    Some(lookup(url, provider, base, token, ctx).await)
+}
+
+async fn lookup(
+    url: &str,
+    provider: ApiProvider,
+    base: &str,
+    token: &str,
+    ctx: &Context<'_>,
+) -> Result<(String, LinkMetadata)> {
+    //! Asks the API about a link, then reads its answer.
+    let parsed = Url::parse(url).map_err(|_| Error::NoString)?;
+    let domain = parsed.host_str().ok_or(Error::NoString)?.trim_start_matches("www.").to_string();
+    let key = parsed.path().trim_matches('/').to_string();
+    if key.is_empty() {
+        return Err(Error::NoString);
+    }
+    let with_query = |path: &str, query: &[(&str, &str)]| {
+        Url::parse_with_params(&format!("{base}{path}"), query).map_err(|_| Error::NoString)
+    };
+    let endpoint = match provider {
+        ApiProvider::Bitly => Url::parse(&format!("{base}/v4/bitlinks/{domain}/{key}")).map_err(|_| Error::NoString)?,
+        ApiProvider::Rebrandly => with_query("/v1/links", &[("domain.fullName", &domain), ("slashtag", &key)])?,
+        ApiProvider::Dub => with_query("/links/info", &[("domain", &domain), ("key", &key)])?,
+        ApiProvider::TinyUrl => Url::parse(&format!("{base}/alias/{domain}/{key}")).map_err(|_| Error::NoString)?,
+    };
+    ready(get_client_builder(ctx.timeout()).build())
+        .err_into()
+        .and_then(|client| async move {
+            let request = match provider {
+                ApiProvider::Rebrandly => client.get(endpoint).header("apikey", token),
+                ApiProvider::Bitly | ApiProvider::Dub | ApiProvider::TinyUrl => client.get(endpoint).bearer_auth(token),
+            };
+            json(request).await
+        })
+        .and_then(|answer| ready(read(provider, &answer).ok_or(Error::NoString)))
+        .await
+}
+
+fn read(provider: ApiProvider, answer: &Value) -> Option<(String, LinkMetadata)> {
+    //! The destination and metadata in an API answer.
+    let (link, url, title, created_at) = match provider {
+        ApiProvider::Bitly => (answer, "long_url", "title", "created_at"),
+        // the links matching the domain and slashtag
+        ApiProvider::Rebrandly => (answer.as_array()?.first()?, "destination", "title", "createdAt"),
+        ApiProvider::Dub => (answer, "url", "title", "createdAt"),
+        ApiProvider::TinyUrl => (answer.get("data")?, "url", "title", "created_at"),
+    };
+    let text = |key: &str| link.get(key)?.as_str().map(str::to_string).filter(|s| !s.is_empty());
+    let url = Url::parse(text(url)?.trim()).ok()?;
+    matches!(url.scheme(), "http" | "https").then(|| {
+        let metadata = LinkMetadata {
+            provider,
+            title: text(title),
+            created_at: text(created_at),
+        };
+        (url.into(), metadata)
+    })
+}
@@ -0,0 +1,418 @@
+// Layered resolution pipeline
+use reqwest::{
+    header::{HeaderMap, LOCATION},
+    Response, StatusCode,
+};
+use std::time::Duration;
+use url::Url;
+
+use super::{
+    adfly, adfocus, fallback, generic, kutt, linkedin, official, polr, preview, redirect,
+    shlink, shorturl, surlli, urlshortdev, yourls, Context,
+};
+use crate::{
+    decode::{decode_url, find_url_in_query},
+    refresh::{meta_refresh, parse_refresh},
+    rules,
+    services::{Stage, Strategy},
+    parse_deep_link, parse_destination, Destination, Error, LinkMetadata, Result,
+};
+
+/// Largest page body read by the pipeline stages.
+pub(crate) const MAX_BODY: usize = 1 << 20;
+
+/// The page a short URL lands on once the HTTP redirects have been followed.
+pub(crate) struct Page {
+    /// URL of the page
+    pub(crate) url: String,
+    /// HTTP headers of the page
+    pub(crate) headers: HeaderMap,
+    body: Option<String>,
+    response: Option<Response>,
+}
+
+impl Page {
+    pub(crate) fn from_response(response: Response) -> Self {
+        //! A page whose body is downloaded on first use.
+        Self {
+            url: response.url().as_str().into(),
+            headers: response.headers().clone(),
+            body: None,
+            response: Some(response),
+        }
+    }
+
+    pub(crate) fn captured(url: &str, headers: HeaderMap, body: &str) -> Self {
+        //! A page captured earlier, with its body at hand.
+        Self {
+            url: url.into(),
+            headers,
+            body: Some(body[..body.floor_char_boundary(MAX_BODY)].into()),
+            response: None,
+        }
+    }
+
+    pub(crate) async fn body(&mut self) -> &str {
+        //! The page body, truncated to [`MAX_BODY`] bytes; empty when it can't
+        //! be read.
+        if let Some(response) = self.response.take() {
+            self.body = Some(read_body(response).await);
+        }
+        self.body.as_deref().unwrap_or_default()
+    }
+
+    fn loaded(&self) -> &str {
+        //! The page body once [`Page::body`] read it (captured pages have it
+        //! from the start); empty before.
+        self.body.as_deref().unwrap_or_default()
+    }
+}
+
+pub(crate) async fn read_body(mut response: Response) -> String {
+    //! The body of a response, truncated to [`MAX_BODY`] bytes; what was read
+    //! before an error.
+    let mut buf = Vec::new();
+    while let Ok(Some(chunk)) = response.chunk().await {
+        buf.extend_from_slice(&chunk);
+        if buf.len() >= MAX_BODY {
+            buf.truncate(MAX_BODY);
+            break;
+        }
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// The destination found by the pipeline.
+pub(crate) struct Resolved {
+    /// The destination URL
+    pub(crate) url: String,
+    /// The stage that found it
+    pub(crate) stage: Stage,
+    /// Delay of the refresh that led to it, for the refresh stages
+    pub(crate) delay: Option<Duration>,
+    /// What the official API reports about the link, when it found it
+    pub(crate) metadata: Option<LinkMetadata>,
+}
+
+pub(crate) async fn run(url: &str, ctx: &Context<'_>) -> Result<Resolved> {
+    //! Runs the service's pipeline stages in order and stops at the first
+    //! confident hit.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The validated short URL
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns the destination and the stage that found it.
+    //!
+    //! # Behavior
+    //!
+    //! - The landing page is requested once, whatever its status, by the first
+    //!   page stage, and its body is only read by the stages that parse it
+    //! - A page stage is confident when it finds an `http(s)` URL off the
+    //!   shortener's hosts; relative targets are resolved against the page and
+    //!   encoded ones are decoded (see [`decode_url`])
+    //! - The API stage is confident whenever the dedicated resolver succeeds,
+    //!   the preview stage whenever the preview shows a destination; the
+    //!   resolver of the [`Strategy::Redirect`] services parses the landing
+    //!   page requested for the page stages rather than requesting it again
+    //! - With [`Options::api_token`](crate::Options::api_token), the API stage
+    //!   runs first and asks the service's official API; when it fails, the
+    //!   stage goes on with the dedicated resolver, if any
+    //! - With [`Options::preview_first`](crate::Options::preview_first), the
+    //!   preview stage runs first
+    //! - The rewrite stage runs before any other when the service has an
+    //!   offline resolver; with [`Options::offline`](crate::Options::offline),
+    //!   it is the only one, and [`Error::Offline`] is returned without a hit
+    //! - Without a confident hit, the first error is returned; without an
+    //!   error either, the landing page itself is the destination when the
+    //!   redirect stage ran
+    let mut stages = ctx.service.stages();
+    let official = ctx.service.api().and_then(|a| ctx.options.apis.get(&a)).is_some_and(|a| a.token.is_some());
+    if official {
+        stages.retain(|s| *s != Stage::Api);
+        stages.insert(0, Stage::Api);
+    }
+    if ctx.options.preview_first {
+        stages.retain(|s| *s != Stage::Preview);
+        stages.insert(0, Stage::Preview);
+    }
+    if ctx.service.rewrite().is_some() {
+        stages.retain(|s| *s != Stage::Rewrite);
+        stages.insert(0, Stage::Rewrite);
+    }
+    if ctx.options.offline {
+        stages.retain(|s| *s == Stage::Rewrite);
+    }
+    let mut page: Option<Page> = None;
+    let mut fetched = false;
+    let mut error = None;
+
+    for &stage in &stages {
+        if stage == Stage::Rewrite {
+            if let Some(url) = ctx.service.rewrite().and_then(|r| r.url(url)) {
+                return Ok(Resolved { url, stage, delay: None, metadata: None });
+            }
+            continue;
+        }
+        if stage == Stage::Preview {
+            // a preview that shows nothing is no reason to stop
+            if let Some(Ok(url)) = preview::unshort(url, ctx).await {
+                return Ok(Resolved { url, stage, delay: None, metadata: None });
+            }
+            continue;
+        }
+        if stage == Stage::Api {
+            if let Some(Ok((url, metadata))) = official::unshort(url, ctx).await {
+                return Ok(Resolved { url, stage, delay: None, metadata: Some(metadata) });
+            }
+            // the redirect resolver only parses the landing page, fetched once below
+            if ctx.service.strategy != Strategy::Redirect {
+                match api(url, ctx).await {
+                    Some(Ok(url)) => return Ok(Resolved { url, stage, delay: None, metadata: None }),
+                    Some(Err(e)) => {
+                        error.get_or_insert(e);
+                    }
+                    None => {}
+                }
+                continue;
+            }
+        }
+        if !fetched {
+            fetched = true;
+            match generic::fetch(url, ctx).await {
+                Ok(p) => page = Some(p),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        let Some(page) = page.as_mut() else {
+            continue;
+        };
+        if matches!(stage, Stage::MetaRefresh | Stage::JsLocation | Stage::Canonical | Stage::Api) {
+            page.body().await;
+        }
+        let found = match stage {
+            Stage::Api => match api_from_page(page, ctx) {
+                Some(url) => Some((url, None)),
+                None => {
+                    error.get_or_insert(Error::NoString);
+                    None
+                }
+            },
+            Stage::JsLocation => find(stage, page, js_location(page, ctx).await, ctx),
+            _ => find(stage, page, None, ctx),
+        };
+        if let Some((url, delay)) = found {
+            return Ok(Resolved { url, stage, delay, metadata: None });
+        }
+    }
+
+    match (error, page) {
+        (Some(e), _) => Err(e),
+        (None, Some(page)) if stages.contains(&Stage::Redirect) => Ok(Resolved {
+            url: page.url,
+            stage: Stage::Redirect,
+            delay: None,
+            metadata: None,
+        }),
+        _ if ctx.options.offline => Err(Error::Offline),
+        _ => Err(Error::NoString),
+    }
+}
+
+pub(crate) fn run_captured(page: Page, status: StatusCode, ctx: &Context<'_>) -> Result<Resolved> {
+    //! Runs the service's pipeline stages on a captured page, without any
+    //! request.
+    //!
+    //! # Arguments
+    //!
+    //! * `page` - The captured page
+    //! * `status` - Its HTTP status
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns the destination and the stage that found it.
+    //!
+    //! # Behavior
+    //!
+    //! - A redirection status with a `Location` header leads to its target
+    //! - The page stages are confident as in [`run`]
+    //! - The API stage runs the page parser of the dedicated resolver, when
+    //!   it has one (see [`api_from_page`])
+    if status.is_redirection()
+        && let Some(location) = page.headers.get(LOCATION).and_then(|v| v.to_str().ok())
+    {
+        let url = Url::parse(&page.url).ok().and_then(|u| u.join(location.trim()).ok()).ok_or(Error::NoString)?;
+        return Ok(Resolved {
+            url: url.into(),
+            stage: Stage::Redirect,
+            delay: None,
+            metadata: None,
+        });
+    }
+    for stage in ctx.service.stages() {
+        let found = match stage {
+            Stage::Api => api_from_page(&page, ctx).map(|url| (url, None)),
+            Stage::JsLocation => find(stage, &page, js_location_sync(&page, ctx), ctx),
+            _ => find(stage, &page, None, ctx),
+        };
+        if let Some((url, delay)) = found {
+            return Ok(Resolved { url, stage, delay, metadata: None });
+        }
+    }
+    Err(Error::NoString)
+}
+
+fn api_from_page(page: &Page, ctx: &Context<'_>) -> Option<String> {
+    //! Runs the page parser of the service's dedicated resolver; `None` for
+    //! the resolvers that need requests of their own.
+    let body = page.loaded();
+    match ctx.service.strategy {
+        Strategy::Adfly => adfly::from_html(body).ok(),
+        Strategy::Adfocus | Strategy::LinkedIn => rules::extract(ctx.service, Stage::Api, body),
+        Strategy::SurlLi => surlli::from_html(body),
+        Strategy::Redirect => redirect::extract(ctx.service, body),
+        Strategy::Generic
+        | Strategy::Browser
+        | Strategy::Curl
+        | Strategy::Refresh
+        | Strategy::Fallback
+        | Strategy::UrlShortDev
+        | Strategy::ShortUrl
+        | Strategy::Yourls
+        | Strategy::Shlink
+        | Strategy::Kutt
+        | Strategy::Polr => None,
+    }
+}
+
+async fn api(url: &str, ctx: &Context<'_>) -> Option<Result<String>> {
+    //! Runs the service's dedicated resolver; `None` for the strategies
+    //! without one.
+    Some(match ctx.service.strategy {
+        // the redirect resolver runs on the pipeline's page (see `api_from_page`)
+        Strategy::Generic | Strategy::Browser | Strategy::Curl | Strategy::Refresh | Strategy::Redirect => {
+            return None;
+        }
+        Strategy::Adfly => adfly::unshort(url, ctx).await,
+        Strategy::Adfocus => adfocus::unshort(url, ctx).await,
+        Strategy::Fallback => fallback::unshort(url, ctx).await,
+        Strategy::UrlShortDev => urlshortdev::unshort(url, ctx).await,
+        Strategy::LinkedIn => linkedin::unshort(url, ctx).await,
+        Strategy::ShortUrl => shorturl::unshort(url, ctx).await,
+        Strategy::SurlLi => surlli::unshort(url, ctx).await,
+        Strategy::Yourls => yourls::unshort(url, ctx).await,
+        Strategy::Shlink => return shlink::unshort(url, ctx).await,
+        Strategy::Kutt => return kutt::unshort(url, ctx).await,
+        Strategy::Polr => return polr::unshort(url, ctx).await,
+    })
+}
+
+fn find(stage: Stage, page: &Page, js: Option<String>, ctx: &Context<'_>) -> Option<(String, Option<Duration>)> {
+    //! Runs one page stage on a page whose body is read, given the JavaScript
+    //! redirect of the page for the JavaScript stage; `Some` only for a
+    //! confident hit, with the refresh delay for the refresh stages.
+    let (candidate, delay) = match stage {
+        Stage::Redirect => match page.headers.get(LOCATION).and_then(|v| v.to_str().ok()) {
+            // deep links and non-HTTP targets the redirect policies stop at
+            Some(location) if parse_deep_link(location).is_some() || is_app_or_contact(location) => {
+                return Some((location.trim().into(), None));
+            }
+            _ => (page.url.clone(), None),
+        },
+        Stage::RefreshHeader => {
+            let refresh = page
+                .headers
+                .get("refresh")
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_refresh)?;
+            (refresh.url?, Some(refresh.delay))
+        }
+        Stage::MetaRefresh => {
+            let body = page.loaded();
+            match rules::extract(ctx.service, stage, body) {
+                Some(found) => (found, None),
+                None => {
+                    let refresh = meta_refresh(body)?;
+                    (refresh.url?, Some(refresh.delay))
+                }
+            }
+        }
+        Stage::JsLocation => (js?, None),
+        Stage::Canonical => (rules::extract(ctx.service, stage, page.loaded())?, None),
+        Stage::Api | Stage::Preview | Stage::Rewrite | Stage::Unwrap => return None,
+    };
+    // refreshes to a phone number, an app, ... are followed by browsers too
+    if matches!(stage, Stage::RefreshHeader | Stage::MetaRefresh) && is_app_or_contact(&candidate) {
+        return Some((candidate.trim().into(), delay));
+    }
+    // destinations hidden in the candidate, or in a query parameter of an
+    // intermediate URL on the shortener
+    let candidate = decode_url(&candidate).unwrap_or(candidate);
+    let mut found = Url::parse(&page.url).ok()?.join(&candidate).ok()?;
+    if ctx.service.matches_url(found.as_str())
+        && let Some(inner) = find_url_in_query(found.as_str()).and_then(|u| Url::parse(&u).ok())
+    {
+        found = inner;
+    }
+    (matches!(found.scheme(), "http" | "https")
+        && (stage == Stage::Redirect || found.as_str() != page.url)
+        && !ctx.service.matches_url(found.as_str()))
+    .then(|| (found.into(), delay))
+}
+
+fn is_app_or_contact(target: &str) -> bool {
+    //! Whether a target is an absolute non-HTTP URL that opens an app, a
+    //! store, a mail, a call or a message (see [`Destination`]).
+    Url::parse(target.trim()).is_ok_and(|url| !matches!(url.scheme(), "http" | "https"))
+        && !matches!(parse_destination(target), Destination::Other { .. })
+}
+
+async fn js_location(page: &Page, ctx: &Context<'_>) -> Option<String> {
+    //! The JavaScript redirect of a page: the extraction rules and the scored
+    //! script scan, then (with the `js` feature) the page scripts run in the
+    //! sandbox, on a thread of its own.
+    if let Some(found) = redirect::extract(ctx.service, page.loaded()) {
+        return Some(found);
+    }
+    #[cfg(feature = "js")]
+    if let Some(found) = best_navigation(crate::run_scripts(page.loaded(), &page.url, &ctx.options.sandbox).await, ctx) {
+        return Some(found);
+    }
+    None
+}
+
+fn js_location_sync(page: &Page, ctx: &Context<'_>) -> Option<String> {
+    //! [`js_location`] on the current thread, for the captured pages.
+    if let Some(found) = redirect::extract(ctx.service, page.loaded()) {
+        return Some(found);
+    }
+    #[cfg(feature = "js")]
+    if let Some(found) = best_navigation(
+        crate::sandbox::run_scripts_sync(page.loaded(), &page.url, &ctx.options.sandbox),
+        ctx,
+    ) {
+        return Some(found);
+    }
+    None
+}
+
+#[cfg(feature = "js")]
+fn best_navigation(navigations: Vec<crate::Navigation>, ctx: &Context<'_>) -> Option<String> {
+    //! The best scored navigation attempted by the page scripts, off the
+    //! shortener's hosts.
+    let mut scored = navigations
+        .into_iter()
+        .filter(|n| !ctx.service.matches_url(&n.url))
+        .filter_map(|n| Some((crate::js::score(n.sink, &n.url)?, n.url)))
+        .filter(|(score, _)| *score > 0)
+        .collect::<Vec<_>>();
+    // stable: the first navigation wins among equal scores
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().next().map(|(_, url)| url)
+}
@@ -0,0 +1,52 @@
+// Polr - self-hosted, with the v2 API
+use futures::future::{ready, TryFutureExt};
+use url::Url;
+
+use super::{get_client_builder, json, Context};
+use crate::{Error, Result};
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Option<Result<String>> {
+    //! Expands URLs of a Polr instance through its `lookup` API action;
+    //! `None` without an API key, which Polr always requires.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The shortened URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Some(Ok(String))` with the long URL of the ending on success,
+    //! or `Some(Err(Error))` if the API doesn't know it.
+    //!
+    //! # Behavior
+    //!
+    //! - Requests `/api/v2/action/lookup` with the key set with
+    //!   [`Options::instance_key`](crate::Options::instance_key)
+    //! - Reads both answer shapes: `result` as the long URL itself (Polr 2.0)
+    //!   or as an object with a `long_url` (later versions)
+    let key = ctx.instance_key()?;
+    let short = Url::parse(url).ok()?;
+    let ending = short.path().trim_matches('/');
+    if ending.is_empty() {
+        return Some(Err(Error::NoString));
+    }
+    let mut api = short.join("/api/v2/action/lookup").ok()?;
+    api.query_pairs_mut()
+        .append_pair("key", key)
+        .append_pair("url_ending", ending)
+        .append_pair("response_type", "json");
+    Some(
+        ready(get_client_builder(ctx.timeout()).build())
+            .err_into()
+            .and_then(|client| json(client.get(api)))
+            .and_then(|answer| {
+                let found = answer
+                    .get("result")
+                    .and_then(|r| r.as_str().or_else(|| r.get("long_url")?.as_str()))
+                    .and_then(|u| ctx.destination(u));
+                ready(found.ok_or(Error::NoString))
+            })
+            .await,
+    )
+}
@@ -0,0 +1,54 @@
+// Preview pages and lookup APIs, which don't count as a click
+use futures::future::{ready, FutureExt, TryFutureExt};
+use serde_json::Value;
+
+use super::{get_client_builder, pipeline::read_body, Context};
+use crate::{rules, services::Stage, Error, Result};
+
+/// Keys of the destination in JSON lookup answers.
+static JSON_KEYS: &[&str] = &["url", "long_url", "longUrl", "destination"];
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Option<Result<String>> {
+    //! Finds the destination of a short URL on its service's preview page or
+    //! lookup API; `None` for the services without one.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The shortened URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Some(Ok(String))` with an `http(s)` destination off the
+    //! shortener's hosts, or `Some(Err(Error))` when the preview doesn't show
+    //! one.
+    //!
+    //! # Behavior
+    //!
+    //! - Requests the preview URL of the service (see [`Preview`](crate::Preview));
+    //!   the short URL itself is never requested
+    //! - Reads the destination from a JSON answer (`url`, `long_url`, ...),
+    //!   then with the `preview` extraction rules and the service's own rules,
+    //!   in the first [`MAX_BODY`](super::pipeline::MAX_BODY) bytes of the answer
+    let preview = ctx.service.preview()?.url(url)?;
+    Some(
+        ready(get_client_builder(ctx.timeout()).build())
+            .and_then(|client| async move { client.get(&preview).send().await })
+            .err_into()
+            .and_then(|response| read_body(response).map(Ok))
+            .and_then(|body| ready(destination(&body, ctx).ok_or(Error::NoString)))
+            .await,
+    )
+}
+
+fn destination(body: &str, ctx: &Context<'_>) -> Option<String> {
+    //! The destination shown by a preview, when it is off the shortener's hosts.
+    let found = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|json| {
+            let object = json.get("data").filter(|d| d.is_object()).unwrap_or(&json);
+            JSON_KEYS.iter().find_map(|k| object.get(k)?.as_str().map(str::to_string))
+        })
+        .or_else(|| rules::extract(ctx.service, Stage::Preview, body))?;
+    ctx.destination(&found)
+}
@@ -0,0 +1,44 @@
+// Shlink - self-hosted, with the REST API
+use futures::future::{ready, TryFutureExt};
+use url::Url;
+
+use super::{get_client_builder, json, Context};
+use crate::{Error, Result};
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Option<Result<String>> {
+    //! Expands URLs of a Shlink instance through its REST API; `None` without
+    //! an API key, which Shlink always requires.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The shortened URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Some(Ok(String))` with the `longUrl` of the short code on
+    //! success, or `Some(Err(Error))` if the API doesn't know it.
+    //!
+    //! # Behavior
+    //!
+    //! - Requests `/rest/v3/short-urls/{shortCode}?domain={host}` with the
+    //!   `X-Api-Key` set with [`Options::instance_key`](crate::Options::instance_key)
+    let key = ctx.instance_key()?;
+    let short = Url::parse(url).ok()?;
+    let code = short.path().trim_matches('/');
+    if code.is_empty() {
+        return Some(Err(Error::NoString));
+    }
+    let mut api = short.join(&format!("/rest/v3/short-urls/{code}")).ok()?;
+    api.query_pairs_mut().append_pair("domain", short.host_str()?);
+    Some(
+        ready(get_client_builder(ctx.timeout()).build())
+            .err_into()
+            .and_then(|client| json(client.get(api).header("X-Api-Key", key)))
+            .and_then(|answer| {
+                let found = answer.get("longUrl").and_then(|u| u.as_str()).and_then(|u| ctx.destination(u));
+                ready(found.ok_or(Error::NoString))
+            })
+            .await,
+    )
+}
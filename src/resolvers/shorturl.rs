@@ -1,12 +1,11 @@
 // ShortURL.AT service
-use super::{custom_redirect_policy, get_client_builder};
-use std::time::Duration;
+use super::{custom_redirect_policy, get_client_builder, Context};
 
 use futures::future::{ready, TryFutureExt};
 
 use crate::{Error, Result};
 
-pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<String> {
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Expands URLs shortened by shorturl.at.
     //!
     //! This resolver handles shorturl.at's specific redirect mechanism
@@ -16,7 +15,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! # Arguments
     //!
     //! * `url` - The shorturl.at shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -29,9 +28,9 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! - Applies custom redirect policy for safety
     //! - Extracts destination URL from Location header
     //! - Handles network errors appropriately
-    let custom = custom_redirect_policy();
+    let custom = custom_redirect_policy(ctx.hops.clone());
 
-    ready(get_client_builder(timeout).redirect(custom).build())
+    ready(get_client_builder(ctx.timeout()).redirect(custom).build())
         .and_then(|client| async move { client.head(url).send().await })
         .err_into()
         .and_then(|response| {
@@ -1,9 +1,8 @@
 // SURL.LI Resolver
-use crate::resolvers::{from_url, generic};
+use crate::resolvers::{from_url, generic, Context};
 use crate::{Error, Result};
-use std::time::Duration;
 
-pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<String> {
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Expands URLs shortened by surl.li.
     //!
     //! This resolver handles surl.li's two-stage redirect process:
@@ -13,7 +12,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! # Arguments
     //!
     //! * `url` - The surl.li shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -25,11 +24,11 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! - First tries standard HTTP redirect following
     //! - If no expansion occurs, parses HTML for API redirect URL
     //! - Extracts the final URL from api.miniature.io calls
-    let expanded_url = generic::unshort(url, timeout).await?;
+    let expanded_url = generic::unshort(url, ctx).await?;
     Ok(
         if expanded_url.ends_with(url) {
             // No redirect occurred (generic resolver just added scheme), need to parse HTML for the real URL
-            match get_from_html(url, timeout).await {
+            match get_from_html(url, ctx).await {
                 Ok(u) => u,
                 Err(_) => expanded_url,
             }
@@ -40,7 +39,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     )
 }
 
-async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
+async fn get_from_html(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Extracts the final URL from surl.li's HTML page.
     //!
     //! This function parses the HTML content to find the direct link
@@ -49,7 +48,7 @@ async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
     //! # Arguments
     //!
     //! * `url` - The surl.li URL to parse
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -59,10 +58,18 @@ async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
     //! # Behavior
     //!
     //! - Fetches the HTML content of the page
-    //! - Searches for direct link in the HTML
-    //! - Extracts the final URL from the href attribute
-    let html = from_url(url, timeout).await?;
-    
+    //! - Locates the destination with [`from_html`]
+    let html = from_url(url, ctx.timeout()).await?;
+    from_html(&html).ok_or(Error::NoString)
+}
+
+pub(crate) fn from_html(html: &str) -> Option<String> {
+    //! Extracts the destination from the HTML of a surl.li page.
+    //!
+    //! # Behavior
+    //!
+    //! - Searches for the "To direct link" anchor and returns its href
+    //! - Falls back to the api.miniature.io preview call and JSON `url` fields
     // Look for the "To direct link" pattern
     if let Some(start) = html.find("To direct link") {
         // Look backwards to find the href attribute
@@ -72,7 +79,7 @@ async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
             if let Some(href_end) = href_content.find("\"") {
                 let extracted_url = &href_content[..href_end];
                 if !extracted_url.is_empty() && (extracted_url.starts_with("http://") || extracted_url.starts_with("https://")) {
-                    return Ok(extracted_url.to_string());
+                    return Some(extracted_url.to_string());
                 }
             }
         }
@@ -90,10 +97,10 @@ async fn get_from_html(url: &str, timeout: Option<Duration>) -> Result<String> {
         if let Some(result) = html.split(pattern).last().and_then(|r| r.split('"').next()) {
             let extracted_url = result.to_string();
             if !extracted_url.is_empty() && (extracted_url.starts_with("http://") || extracted_url.starts_with("https://")) {
-                return Ok(extracted_url);
+                return Some(extracted_url);
             }
         }
     }
     
-    Err(Error::NoString)
+    None
 }
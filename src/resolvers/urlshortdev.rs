@@ -50,8 +50,7 @@
 //! - Password-protected or expired links may not resolve via the API.
 //! - The resolver does not execute JavaScript; it relies solely on HTTP and API calls.
 //! - Redirect limits and timeouts are controlled by the shared HTTP client builder.
-use super::get_client_builder;
-use std::time::Duration;
+use super::{get_client_builder, Context};
 
 use futures::future::{ready, TryFutureExt};
 use serde::Deserialize;
@@ -145,12 +144,12 @@ async fn resolve_via_dr_api(
         .await?;
 
     // Case A: redirect
-    if resp.status().is_redirection() {
-        if let Some(loc) = resp.headers().get(reqwest::header::LOCATION) {
-            let s = loc.to_str().unwrap_or("").trim();
-            if !s.is_empty() {
-                return Ok(s.to_string());
-            }
+    if resp.status().is_redirection()
+        && let Some(loc) = resp.headers().get(reqwest::header::LOCATION)
+    {
+        let s = loc.to_str().unwrap_or("").trim();
+        if !s.is_empty() {
+            return Ok(s.to_string());
         }
     }
 
@@ -168,7 +167,7 @@ async fn resolve_via_dr_api(
     Err(Error::Reqwest("dr-api could not resolve slug".to_string()))
 }
 
-pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<String> {
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
     //! Expands URLs from urlshort.dev, l1nq.com, and associated services.
     //!
     //! This resolver handles the complex two-stage resolution process used by
@@ -178,7 +177,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! # Arguments
     //!
     //! * `url` - The shortened URL to expand
-    //! * `timeout` - Optional timeout for HTTP requests
+    //! * `ctx` - The expansion context (service, options, hops)
     //!
     //! # Returns
     //!
@@ -191,11 +190,11 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
     //! 2. Extract the slug from the landing page or original URL
     //! 3. Call the Encurtador API to resolve the final destination
     //! 4. Return the resolved URL
-    ready(get_client_builder(timeout).build())
+    ready(ctx.client_builder().build())
         .map_err(Error::from)
         .and_then(|client| async move {
             // Client that does NOT auto-follow redirects (so we can read Location headers)
-            let client_no_redirect = get_client_builder(timeout)
+            let client_no_redirect = get_client_builder(ctx.timeout())
                 .redirect(reqwest::redirect::Policy::none())
                 .build()
                 .map_err(Error::from)?;
@@ -210,9 +209,7 @@ pub(crate) async fn unshort(url: &str, timeout: Option<Duration>) -> Result<Stri
                 .ok_or(Error::NoString)?;
 
             // Step 3: resolve via dr-api
-            let resolved = resolve_via_dr_api(&client_no_redirect, &slug)
-                .await
-                .map_err(Error::from)?;
+            let resolved = resolve_via_dr_api(&client_no_redirect, &slug).await?;
 
             Ok(resolved)
         })
@@ -0,0 +1,48 @@
+// YOURLS - self-hosted, with the yourls-api.php API
+use futures::future::{ready, TryFutureExt};
+use url::Url;
+
+use super::{get_client_builder, json, Context};
+use crate::{Error, Result};
+
+pub(crate) async fn unshort(url: &str, ctx: &Context<'_>) -> Result<String> {
+    //! Expands URLs of a YOURLS instance through its `expand` API action.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The shortened URL to expand
+    //! * `ctx` - The expansion context (service, options, hops)
+    //!
+    //! # Returns
+    //!
+    //! Returns `Ok(String)` with the `longurl` of the keyword on success,
+    //! or `Err(Error)` if the API doesn't answer or doesn't know it.
+    //!
+    //! # Behavior
+    //!
+    //! - The API lives next to the keyword (`https://sho.rt/sub/yourls-api.php`
+    //!   for `https://sho.rt/sub/abc`), so instances in a directory work too
+    //! - Public instances answer without credentials; private ones need the
+    //!   signature token set with [`Options::instance_key`](crate::Options::instance_key)
+    let short = Url::parse(url).map_err(|_| Error::NoString)?;
+    let keyword = short.path_segments().and_then(|mut s| s.next_back()).unwrap_or_default();
+    if keyword.is_empty() {
+        return Err(Error::NoString);
+    }
+    let mut api = short.join("yourls-api.php").map_err(|_| Error::NoString)?;
+    api.query_pairs_mut()
+        .append_pair("action", "expand")
+        .append_pair("shorturl", keyword)
+        .append_pair("format", "json");
+    if let Some(key) = ctx.instance_key() {
+        api.query_pairs_mut().append_pair("signature", key);
+    }
+    ready(get_client_builder(ctx.timeout()).build())
+        .err_into()
+        .and_then(|client| json(client.get(api)))
+        .and_then(|answer| {
+            let found = answer.get("longurl").and_then(|u| u.as_str()).and_then(|u| ctx.destination(u));
+            ready(found.ok_or(Error::NoString))
+        })
+        .await
+}
@@ -0,0 +1,399 @@
+//! Declarative extraction rules for HTML / JS redirect pages.
+//!
+//! Many shorteners don't answer with an HTTP redirect but with an intermediate
+//! page that carries the destination somewhere in its markup or scripts. Instead
+//! of hand-written string surgery in every resolver, the destination is located
+//! with a list of [`Rule`]s.
+//!
+//! ## Rule format
+//!
+//! A rule either selects an element with a **CSS selector** (and reads one of its
+//! attributes, or its text), or matches a **regex** (and reads one capture group).
+//! The extracted string then goes through optional post-processing steps.
+//!
+//! ```toml
+//! [[rule]]
+//! service = "sho.rt"                # domain, alias or strategy name; omit for all
+//! stage = "js_location"             # only in this stage; omit for every stage
+//! selector = "a#skip"               # or: regex = 'target = "([^"]+)"'
+//! attribute = "href"                # selector only; omit to use the element text
+//! post = ["html_unescape", "percent_decode"]   # also: base64, hex, reverse
+//! precedence = 100                  # higher runs first
+//! ```
+//!
+//! Rules can be loaded with the service definition file (see
+//! [`load_services`](crate::load_services)) or added at runtime with [`add_rule`].
+//!
+//! ## Evaluation
+//!
+//! Rules run in a stage of the resolution pipeline (see
+//! [`Stage`]). In a stage, every rule whose `stage` is that stage (or unset)
+//! and whose `service` is the service's domain, one of its aliases, its
+//! strategy name (e.g. `redirect`) or unset is tried, by descending
+//! precedence. The first rule that yields a non-empty string wins.
+//! Runtime rules are tried before built-in rules of the same precedence, and
+//! the service's own `patterns` are tried before any rule.
+//!
+//! A rule scoped to a service runs in every stage that parses the service's
+//! pages (the API, meta refresh, script and canonical stages, and the preview
+//! page); with a `stage`, it only runs in that one.
+//!
+//! Selectors and regexes are compiled once, when the rule is added.
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+};
+
+use crate::{
+    decode::{decode_base64, decode_hex},
+    services::{Service, Stage},
+    Error, Result,
+};
+
+/// Built-in rules used by the resolvers.
+static BUILTIN_RULES: LazyLock<Vec<Compiled>> = LazyLock::new(|| {
+    [
+        // redirect pages of specific services; scripts are handled by `crate::js`
+        Rule::regex("rlu.ru", r#"Here is the URL which you want to visit:<br><br>\n<a href="([^">]*)"#),
+        Rule::regex("nowlinks.net", r#"target='_blank'>([^<]*)"#),
+        Rule::regex("tinyurl.com", r#""redirecturl" href="(.*)">"#),
+        Rule::regex("vzturl.com", r#"src=['"]([^"']*)" scrolling"#),
+        // canonical URL of the page
+        Rule {
+            stage: Some(Stage::Canonical),
+            selector: Some(r#"link[rel="canonical"]"#.into()),
+            attribute: Some("href".into()),
+            precedence: 10,
+            ..Default::default()
+        },
+        Rule {
+            stage: Some(Stage::Canonical),
+            selector: Some(r#"meta[property="og:url"]"#.into()),
+            attribute: Some("content".into()),
+            ..Default::default()
+        },
+        // preview pages: TinyURL, Bitly
+        Rule {
+            stage: Some(Stage::Preview),
+            selector: Some("a#redirecturl".into()),
+            attribute: Some("href".into()),
+            ..Default::default()
+        },
+        Rule {
+            stage: Some(Stage::Preview),
+            regex: Some(r#""long_url"\s*:\s*"([^"]+)""#.into()),
+            ..Default::default()
+        },
+        // adfoc.us
+        Rule::regex("adfoc.us", r#"click_url = "([^"]*)";"#),
+        // lnkd.in interstitial warning page
+        Rule::selector(
+            "lnkd.in",
+            r#"a[data-tracking-control-name="external_url_click"]"#,
+            Some("href"),
+        ),
+    ]
+    .into_iter()
+    .map(|rule| Compiled::new(rule).expect("valid built-in rule"))
+    .collect()
+});
+
+/// Rules added at runtime.
+static RULES: LazyLock<RwLock<Vec<Compiled>>> = LazyLock::new(Default::default);
+
+/// The services' `patterns`, compiled, by pattern.
+static PATTERNS: LazyLock<RwLock<HashMap<String, Option<Regex>>>> = LazyLock::new(Default::default);
+
+/// A post-processing step applied to an extracted string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostStep {
+    /// Decode HTML entities (`&amp;`, `&#x2F;`, ...)
+    HtmlUnescape,
+    /// Decode `%XX` escapes
+    PercentDecode,
+    /// Decode standard or URL-safe base64
+    Base64,
+    /// Decode a hex string
+    Hex,
+    /// Reverse the string
+    Reverse,
+}
+
+impl PostStep {
+    fn apply(&self, s: &str) -> Option<String> {
+        //! Applies the step; `None` when the input can't be decoded.
+        match self {
+            Self::HtmlUnescape => Some(html_unescape(s)),
+            Self::PercentDecode => Some(percent_decode_str(s).decode_utf8_lossy().into()),
+            Self::Base64 => decode_base64(s),
+            Self::Hex => decode_hex(s),
+            Self::Reverse => Some(s.chars().rev().collect()),
+        }
+    }
+}
+
+/// A declarative rule locating a destination URL inside a page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Rule {
+    /// Domain, alias or strategy name the rule applies to; `None` for every service
+    #[serde(default)]
+    pub service: Option<String>,
+    /// The only stage the rule runs in; `None` for every stage
+    #[serde(default)]
+    pub stage: Option<Stage>,
+    /// CSS selector of the element holding the destination
+    #[serde(default)]
+    pub selector: Option<String>,
+    /// Regex matching the destination
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// Attribute read from the selected element; its text when `None`
+    #[serde(default)]
+    pub attribute: Option<String>,
+    /// Capture group read from the regex; the first one that matched when `None`
+    #[serde(default)]
+    pub group: Option<usize>,
+    /// Post-processing steps, applied in order
+    #[serde(default)]
+    pub post: Vec<PostStep>,
+    /// Rules with a higher precedence are tried first
+    #[serde(default)]
+    pub precedence: i32,
+}
+
+impl Rule {
+    pub fn regex(service: &str, pattern: &str) -> Self {
+        //! Creates a regex rule reading the first capture group that matched.
+        Self {
+            service: Some(service.into()),
+            regex: Some(pattern.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn selector(service: &str, selector: &str, attribute: Option<&str>) -> Self {
+        //! Creates a CSS selector rule reading `attribute`, or the element text.
+        Self {
+            service: Some(service.into()),
+            selector: Some(selector.into()),
+            attribute: attribute.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    pub fn group(mut self, group: usize) -> Self {
+        //! Reads the given capture group instead of the first one that matched.
+        self.group = Some(group);
+        self
+    }
+
+    pub fn post(mut self, step: PostStep) -> Self {
+        //! Appends a post-processing step.
+        self.post.push(step);
+        self
+    }
+
+    pub fn precedence(mut self, precedence: i32) -> Self {
+        //! Sets the precedence; higher runs first.
+        self.precedence = precedence;
+        self
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Self {
+        //! Only runs the rule in the given stage.
+        self.stage = Some(stage);
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        //! Checks that the rule has exactly one of `selector` / `regex` and that
+        //! it compiles.
+        self.matcher().map(|_| ())
+    }
+
+    fn matcher(&self) -> Result<Matcher> {
+        //! Compiles the rule's selector or regex.
+        match (&self.selector, &self.regex) {
+            (Some(s), None) => Selector::parse(s)
+                .map(Matcher::Selector)
+                .map_err(|e| Error::Rule(format!("{}: {}", s, e))),
+            (None, Some(r)) => Regex::new(r)
+                .map(Matcher::Regex)
+                .map_err(|e| Error::Rule(e.to_string())),
+            _ => Err(Error::Rule("a rule needs either a selector or a regex".into())),
+        }
+    }
+
+    fn applies_to(&self, service: &Service, stage: Stage) -> bool {
+        //! Whether the rule is scoped to this stage and service (or to everything).
+        //!
+        //! Stages are only matched through `stage`: a `service` of `redirect`
+        //! names the Redirect strategy, never the redirect stage.
+        self.stage.is_none_or(|s| s == stage)
+            && self.service.as_deref().is_none_or(|s| {
+                s == service.domain
+                    || s == service.strategy.name()
+                    || service.aliases.iter().any(|a| a == s)
+            })
+    }
+}
+
+/// The compiled selector or regex of a rule.
+#[derive(Debug)]
+enum Matcher {
+    Selector(Selector),
+    Regex(Regex),
+}
+
+/// A rule with its selector or regex compiled.
+#[derive(Debug)]
+struct Compiled {
+    rule: Rule,
+    matcher: Matcher,
+}
+
+impl Compiled {
+    fn new(rule: Rule) -> Result<Self> {
+        //! Compiles a rule; an error when it doesn't validate.
+        let matcher = rule.matcher()?;
+        Ok(Self { rule, matcher })
+    }
+
+    fn apply(&self, html: &str, doc: &mut Option<Html>) -> Option<String> {
+        //! Runs the rule against a page; the parsed document is shared between
+        //! selector rules.
+        let raw = match &self.matcher {
+            Matcher::Selector(selector) => {
+                let doc = doc.get_or_insert_with(|| Html::parse_document(html));
+                let element = doc.select(selector).next()?;
+                match &self.rule.attribute {
+                    Some(a) => element.value().attr(a)?.to_string(),
+                    None => element.text().collect(),
+                }
+            }
+            Matcher::Regex(regex) => capture(regex, self.rule.group, html)?,
+        };
+        self.rule
+            .post
+            .iter()
+            .try_fold(raw, |s, step| step.apply(&s))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+}
+
+fn capture(regex: &Regex, group: Option<usize>, html: &str) -> Option<String> {
+    //! The given capture group of the first match, or its first capture group
+    //! that matched.
+    let captures = regex.captures(html)?;
+    let matched = match group {
+        Some(g) => captures.get(g)?,
+        None => captures.iter().skip(1).flatten().next()?,
+    };
+    Some(matched.as_str().to_string())
+}
+
+fn pattern(pattern: &str) -> Option<Regex> {
+    //! A service pattern, compiled on first use; `None` when it doesn't compile.
+    if let Some(regex) = PATTERNS.read().unwrap_or_else(|e| e.into_inner()).get(pattern) {
+        return regex.clone();
+    }
+    let regex = Regex::new(pattern).ok();
+    PATTERNS.write().unwrap_or_else(|e| e.into_inner()).insert(pattern.into(), regex.clone());
+    regex
+}
+
+pub fn add_rule(rule: Rule) -> Result<()> {
+    //! Adds an extraction rule at runtime.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{add_rule, PostStep, Rule};
+    //!
+    //! add_rule(Rule::regex("sho.rt", r#"data-b64="([^"]+)""#).post(PostStep::Base64)).unwrap();
+    //! ```
+    let rule = Compiled::new(rule)?;
+    RULES.write().unwrap_or_else(|e| e.into_inner()).push(rule);
+    Ok(())
+}
+
+pub(crate) fn extract(service: &Service, stage: Stage, html: &str) -> Option<String> {
+    //! Extracts the destination from a page with the rules that apply to the
+    //! service in a pipeline stage.
+    //!
+    //! # Arguments
+    //!
+    //! * `service` - The service the page belongs to
+    //! * `stage` - The pipeline stage running the rules
+    //! * `html` - The page content
+    //!
+    //! # Returns
+    //!
+    //! Returns `Some(String)` with the result of the first matching rule, or
+    //! `None` when no rule matched.
+    let found = service.patterns.iter().find_map(|p| {
+        let found = capture(&pattern(p)?, None, html)?;
+        Some(found.trim().to_string()).filter(|s| !s.is_empty())
+    });
+    if found.is_some() {
+        return found;
+    }
+
+    let runtime = RULES.read().unwrap_or_else(|e| e.into_inner());
+    let mut rules = runtime
+        .iter()
+        .chain(BUILTIN_RULES.iter())
+        .filter(|r| r.rule.applies_to(service, stage))
+        .collect::<Vec<_>>();
+    // stable: runtime rules stay ahead of built-in rules of the same precedence
+    rules.sort_by_key(|r| std::cmp::Reverse(r.rule.precedence));
+
+    let mut doc = None;
+    rules.iter().find_map(|r| r.apply(html, &mut doc))
+}
+
+pub(crate) fn html_unescape(s: &str) -> String {
+    //! Decodes the common named HTML entities and all numeric ones.
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let decoded = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .map(|h| u32::from_str_radix(h, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(|n| n.ok())
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
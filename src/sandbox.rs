@@ -0,0 +1,395 @@
+//! Sandboxed evaluation of page scripts (`js` feature).
+//!
+//! Some gateways hide the destination behind packed or obfuscated scripts
+//! that [`find_js_redirects`](crate::find_js_redirects) can't follow. With the
+//! `js` feature, the inline scripts of such pages run in [boa], an embedded
+//! pure-Rust JavaScript engine, against a minimal fake browser:
+//!
+//! - `window` (also `self`, `top`, `parent`), `document`, `location`,
+//!   `navigator`, `console`, `atob` / `btoa`
+//! - `setTimeout` / `setInterval` callbacks and `load` listeners run once the
+//!   scripts are done; the delay is ignored
+//! - every navigation is captured instead of performed: `location` /
+//!   `location.href` assignments, `location.replace` / `assign`,
+//!   `window.open`, clicks on links and form submissions
+//!
+//! Scripts have no network access (`fetch` never settles, `XMLHttpRequest`
+//! never sends) and run on a worker thread under the [`SandboxLimits`]: a
+//! wall clock deadline, checked by the engine every [`SLICE`] instructions,
+//! and loop, recursion and stack limits. The caller never waits more than
+//! [`GRACE`] past the deadline, even for a script stuck in a callback of a
+//! built-in, which the engine can't interrupt.
+//!
+//! Memory is only bounded in part: the strings and arrays that built-ins
+//! produce or walk (`repeat`, `padStart` / `padEnd`, `Array.from` and the
+//! array methods) are capped to [`MAX_LENGTH`] elements, but concatenation
+//! isn't, so a script doubling a string can still allocate a lot within one
+//! slice.
+//!
+//! [boa]: https://boajs.dev
+use boa_engine::{Context, JsResult, JsValue, Script, Source};
+use futures::{channel::oneshot, task::noop_waker_ref};
+use scraper::{Html, Selector};
+use serde_json::json;
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{mpsc, LazyLock},
+    task::{self, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+use url::{Position, Url};
+
+use crate::JsSink;
+
+static SCRIPT: LazyLock<Selector> = LazyLock::new(|| Selector::parse("script").expect("valid script selector"));
+static ONLOAD: LazyLock<Selector> = LazyLock::new(|| Selector::parse("body[onload]").expect("valid body selector"));
+
+/// Largest amount of script source evaluated for a page.
+const MAX_SOURCE: usize = 512 * 1024;
+
+/// Cost of the instructions run between two checks of the deadline.
+const SLICE: u32 = 10_000;
+
+/// How long past the deadline the caller waits for the worker thread.
+const GRACE: Duration = Duration::from_millis(500);
+
+/// Longest string or array the built-ins may produce or walk.
+const MAX_LENGTH: usize = 1 << 20;
+
+/// The fake browser environment, evaluated before the page scripts.
+static PRELUDE: &str = r#"
+var __nav = [], __timers = [], __href = "";
+function __go(kind, u) { if (u !== undefined && u !== null) __nav.push([kind, String(u)]); }
+var __location = {
+    get href() { return __href; }, set href(u) { __go("location", u); },
+    replace: function (u) { __go("replace", u); },
+    assign: function (u) { __go("assign", u); },
+    reload: function () {},
+    toString: function () { return __href; }
+};
+["protocol", "host", "hostname", "port", "pathname", "search", "hash", "origin"].forEach(function (k) {
+    Object.defineProperty(__location, k, { get: function () { return __parts[k]; }, set: function () {} });
+});
+Object.defineProperty(globalThis, "location", {
+    get: function () { return __location; }, set: function (u) { __go("location", u); }
+});
+var window = globalThis, self = globalThis, top = globalThis, parent = globalThis, frames = globalThis;
+function __listen(type, fn) { if (type === "load" || type === "DOMContentLoaded") __timers.push(fn); }
+window.addEventListener = __listen;
+window.open = function (u) { __go("open", u); return { focus: function () {}, close: function () {} }; };
+window.setTimeout = function (fn) { __timers.push(fn); return __timers.length; };
+window.setInterval = window.setTimeout;
+window.clearTimeout = window.clearInterval = function () {};
+window.fetch = function () { return new Promise(function () {}); };
+window.XMLHttpRequest = function () {
+    this.open = this.send = this.setRequestHeader = this.abort = function () {};
+};
+window.console = { log: function () {}, warn: function () {}, error: function () {}, info: function () {} };
+window.navigator = {
+    userAgent: "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:145.0) Gecko/20100101 Firefox/145.0",
+    language: "en-US", languages: ["en-US", "en"], platform: "MacIntel", cookieEnabled: true
+};
+window.screen = { width: 1920, height: 1080 };
+window.innerWidth = 1920; window.innerHeight = 1080;
+function __element(tag) {
+    var el = {
+        tagName: String(tag || "div").toUpperCase(), style: {}, children: [], attributes: {},
+        setAttribute: function (k, v) { this.attributes[k] = String(v); this[k] = String(v); },
+        getAttribute: function (k) { return k in this.attributes ? this.attributes[k] : null; },
+        appendChild: function (c) { this.children.push(c); return c; },
+        removeChild: function (c) { return c; },
+        addEventListener: function () {},
+        click: function () { if (this.href) __go("location", this.href); },
+        submit: function () { if (this.action) __go("location", this.action); }
+    };
+    return el;
+}
+var document = window.document = {
+    get location() { return __location; }, set location(u) { __go("location", u); },
+    get URL() { return __href; }, referrer: "", cookie: "", title: "", readyState: "complete", domain: "",
+    body: __element("body"), head: __element("head"), documentElement: __element("html"),
+    createElement: __element,
+    getElementById: function () { return __element("div"); },
+    getElementsByTagName: function () { return []; },
+    getElementsByClassName: function () { return []; },
+    getElementsByName: function () { return []; },
+    querySelector: function () { return null; },
+    querySelectorAll: function () { return []; },
+    addEventListener: __listen,
+    write: function () {}, writeln: function () {}
+};
+var __b64 = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+window.atob = function (s) {
+    s = String(s).replace(/[\s=]/g, "");
+    var out = "", bits = 0, acc = 0;
+    for (var i = 0; i < s.length; i++) {
+        var v = __b64.indexOf(s.charAt(i));
+        if (v < 0) throw new Error("invalid base64");
+        acc = (acc << 6) | v; bits += 6;
+        if (bits >= 8) { bits -= 8; out += String.fromCharCode((acc >> bits) & 255); }
+    }
+    return out;
+};
+window.btoa = function (s) {
+    s = String(s);
+    var out = "";
+    for (var i = 0; i < s.length; i += 3) {
+        var a = s.charCodeAt(i), b = s.charCodeAt(i + 1), c = s.charCodeAt(i + 2);
+        var n = (a << 16) | ((b || 0) << 8) | (c || 0);
+        out += __b64.charAt(n >> 18) + __b64.charAt((n >> 12) & 63)
+            + (isNaN(b) ? "=" : __b64.charAt((n >> 6) & 63)) + (isNaN(c) ? "=" : __b64.charAt(n & 63));
+    }
+    return out;
+};
+(function () {
+    function check(n) { if (n > __max) throw new RangeError("too long"); }
+    var strings = String.prototype;
+    ["padStart", "padEnd"].forEach(function (name) {
+        var original = strings[name];
+        strings[name] = function (n) { check(Number(n) || 0); return original.apply(this, arguments); };
+    });
+    var repeat = strings.repeat;
+    strings.repeat = function (n) { check(String(this).length * (Number(n) || 0)); return repeat.call(this, n); };
+    var from = Array.from;
+    Array.from = function (items) {
+        if (items !== null && items !== undefined) check(Number(items.length) || 0);
+        return from.apply(this, arguments);
+    };
+    var arrays = Array.prototype;
+    ["join", "fill", "forEach", "map", "filter", "some", "every", "find", "findIndex", "findLast",
+        "findLastIndex", "reduce", "reduceRight", "indexOf", "lastIndexOf", "includes", "sort", "reverse",
+        "copyWithin", "flat", "flatMap", "slice", "splice", "concat", "toString"].forEach(function (name) {
+        var original = arrays[name];
+        if (typeof original === "function") arrays[name] = function () {
+            check(this.length >>> 0);
+            return original.apply(this, arguments);
+        };
+    });
+})();
+function __drain() {
+    for (var i = 0; i < __timers.length && i < 100; i++) {
+        var fn = __timers[i];
+        try { if (typeof fn === "function") fn(); else eval(String(fn)); } catch (e) {}
+    }
+}
+"#;
+
+/// Limits applied to the scripts of a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxLimits {
+    /// Wall clock time allowed for all the scripts of a page
+    pub time: Duration,
+    /// Iterations allowed per loop
+    pub loop_iterations: u64,
+    /// Depth of nested function calls
+    pub recursion: usize,
+    /// Size of the VM value stack
+    pub stack_size: usize,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            time: Duration::from_secs(2),
+            loop_iterations: 1_000_000,
+            recursion: 256,
+            stack_size: 64 * 1024,
+        }
+    }
+}
+
+/// A navigation attempted by a page script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Navigation {
+    /// The target, resolved against the page URL
+    pub url: String,
+    /// How the script navigated; link clicks and form submissions count as
+    /// [`JsSink::Location`]
+    pub sink: JsSink,
+}
+
+pub async fn run_scripts(html: &str, page_url: &str, limits: &SandboxLimits) -> Vec<Navigation> {
+    //! Runs the inline scripts of a page in the sandbox and returns the
+    //! navigations they attempted, in order.
+    //!
+    //! # Arguments
+    //!
+    //! * `html` - The page content
+    //! * `page_url` - URL of the page, seen by the scripts as `location.href`
+    //! * `limits` - Time, loop, recursion and stack limits
+    //!
+    //! # Behavior
+    //!
+    //! - Scripts with a `src` and non-JavaScript `type`s are skipped
+    //! - A script that throws doesn't stop the following ones
+    //! - Past the time limit, the evaluation is abandoned within [`SLICE`]
+    //!   instructions and the navigations are dropped
+    //! - Functions called back by built-ins (`forEach` callbacks, getters,
+    //!   `fn.call`, ...) are not interrupted; the call returns no navigation
+    //!   [`GRACE`] past the time limit anyway, leaving the worker thread to the
+    //!   loop and recursion limits
+    //! - Memory isn't bounded beyond the caps on the built-ins (see the
+    //!   module documentation)
+    //!
+    //! ## Example
+    //! ```rust,ignore
+    //! use urlexpand::{run_scripts, SandboxLimits};
+    //!
+    //! let html = r#"<script>var p = ["com", "example", "https://"]; location = p[2] + p[1] + "." + p[0];</script>"#;
+    //! let found = run_scripts(html, "https://sho.rt/x", &SandboxLimits::default()).await;
+    //! assert_eq!(found[0].url, "https://example.com/");
+    //! ```
+    let scripts = page_scripts(html);
+    if scripts.is_empty() {
+        return vec![];
+    }
+    let (tx, rx) = oneshot::channel();
+    if !spawn(scripts, page_url, limits, move |found| {
+        let _ = tx.send(found);
+    }) {
+        return vec![];
+    }
+    match tokio::time::timeout(limits.time + GRACE, rx).await {
+        Ok(Ok(found)) => found,
+        _ => vec![],
+    }
+}
+
+pub(crate) fn run_scripts_sync(html: &str, page_url: &str, limits: &SandboxLimits) -> Vec<Navigation> {
+    //! [`run_scripts`], blocking the current thread, for the captured pages.
+    let scripts = page_scripts(html);
+    if scripts.is_empty() {
+        return vec![];
+    }
+    let (tx, rx) = mpsc::channel();
+    if !spawn(scripts, page_url, limits, move |found| {
+        let _ = tx.send(found);
+    }) {
+        return vec![];
+    }
+    rx.recv_timeout(limits.time + GRACE).unwrap_or_default()
+}
+
+fn spawn(
+    scripts: Vec<String>,
+    page_url: &str,
+    limits: &SandboxLimits,
+    send: impl FnOnce(Vec<Navigation>) + Send + 'static,
+) -> bool {
+    //! Evaluates the scripts on a worker thread, which sends the navigations
+    //! found; whether the thread started.
+    let page_url = page_url.to_string();
+    let limits = *limits;
+    thread::Builder::new()
+        .name("urlexpand-js".into())
+        .spawn(move || send(navigations(&scripts, &page_url, &limits)))
+        .is_ok()
+}
+
+fn navigations(scripts: &[String], page_url: &str, limits: &SandboxLimits) -> Vec<Navigation> {
+    //! Evaluates the scripts and resolves the navigations they attempted
+    //! against the page URL.
+    let base = Url::parse(page_url).ok();
+    evaluate(scripts, page_url, limits)
+        .into_iter()
+        .filter_map(|(kind, url)| {
+            let url = match &base {
+                Some(base) => base.join(url.trim()).ok()?.into(),
+                None => url,
+            };
+            let sink = match kind.as_str() {
+                "replace" => JsSink::Replace,
+                "assign" => JsSink::Assign,
+                "open" => JsSink::Open,
+                _ => JsSink::Location,
+            };
+            Some(Navigation { url, sink })
+        })
+        .collect()
+}
+
+fn page_scripts(html: &str) -> Vec<String> {
+    //! The inline scripts of a page, then its `<body onload>` handler, up to
+    //! [`MAX_SOURCE`] bytes.
+    let doc = Html::parse_document(html);
+    let inline = doc.select(&SCRIPT).filter(|s| {
+        s.value().attr("src").is_none()
+            && s.value().attr("type").is_none_or(|t| {
+                let t = t.trim().to_ascii_lowercase();
+                t.is_empty() || t.contains("javascript") || t == "module"
+            })
+    });
+    let mut total = 0;
+    inline
+        .map(|s| s.text().collect::<String>())
+        .chain(doc.select(&ONLOAD).filter_map(|b| b.value().attr("onload").map(Into::into)))
+        .take_while(|s| {
+            total += s.len();
+            total <= MAX_SOURCE
+        })
+        .collect()
+}
+
+fn evaluate(scripts: &[String], page_url: &str, limits: &SandboxLimits) -> Vec<(String, String)> {
+    //! Evaluates the scripts in a fresh context; returns the captured
+    //! `(kind, url)` navigations, or none past the time limit.
+    let deadline = Instant::now() + limits.time;
+    let mut context = Context::default();
+    let runtime = context.runtime_limits_mut();
+    runtime.set_loop_iteration_limit(limits.loop_iterations);
+    runtime.set_recursion_limit(limits.recursion);
+    runtime.set_stack_size_limit(limits.stack_size);
+
+    let parts = Url::parse(page_url)
+        .map(|u| {
+            json!({
+                "protocol": format!("{}:", u.scheme()),
+                "host": &u[Position::BeforeHost..Position::AfterPort],
+                "hostname": u.host_str().unwrap_or_default(),
+                "port": u.port().map(|p| p.to_string()).unwrap_or_default(),
+                "pathname": u.path(),
+                "search": &u[Position::AfterPath..Position::AfterQuery],
+                "hash": &u[Position::AfterQuery..],
+                "origin": u.origin().ascii_serialization(),
+            })
+        })
+        .unwrap_or_else(|_| json!({}));
+    let setup = format!("var __parts = {parts}, __max = {MAX_LENGTH};{PRELUDE}__href = {};", json!(page_url));
+    if !matches!(run(&mut context, &setup, deadline), Some(Ok(_))) {
+        return vec![];
+    }
+    for script in scripts.iter().map(String::as_str).chain(["__drain();"]) {
+        // a script that throws doesn't stop the following ones
+        if run(&mut context, script, deadline).is_none() {
+            return vec![];
+        }
+    }
+
+    let navigations = match run(&mut context, "JSON.stringify(__nav)", deadline) {
+        Some(Ok(v)) => v.as_string().map(|s| s.to_std_string_escaped()).unwrap_or_default(),
+        _ => return vec![],
+    };
+    serde_json::from_str(&navigations).unwrap_or_default()
+}
+
+fn run(context: &mut Context, source: &str, deadline: Instant) -> Option<JsResult<JsValue>> {
+    //! Evaluates a script in slices of [`SLICE`] instructions; `None` when it
+    //! is abandoned at the first slice past the deadline (the context is then
+    //! unusable).
+    let script = match Script::parse(Source::from_bytes(source), None, context) {
+        Ok(script) => script,
+        Err(e) => return Some(Err(e)),
+    };
+    let mut evaluation = pin!(script.evaluate_async_with_budget(context, SLICE));
+    let mut cx = task::Context::from_waker(noop_waker_ref());
+    loop {
+        if let Poll::Ready(result) = evaluation.as_mut().poll(&mut cx) {
+            return Some(result);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+    }
+}
@@ -13,17 +13,14 @@
 //!
 //! ## How detection works
 //!
-//! The detection is string-based: if the URL contains any domain listed in
-//! [`SERVICES`], it is considered to belong to a known shortener.
-//!
-//! This approach is fast and works well in practice, since shortener URLs
-//! usually embed their domain directly in the visible URL.
+//! The detection is host-based: the host of the URL is normalised with
+//! IDNA / UTS-46 (so `xn--` and Unicode forms are the same host), and it
+//! belongs to a known shortener when it is one of the domains listed in
+//! [`SERVICES`] (or a runtime-registered one), or a subdomain of it. Registry
+//! entries may be written in either form.
 //!
 //! ## Limitations
 //!
-//! - Detection is **substring-based**, not a strict hostname match. This is
-//!   intentional for performance and simplicity, but it may produce false
-//!   positives in rare edge cases.
 //! - Some shortening services use custom domains per customer. Those will not
 //!   be detected unless added to this list.
 //! - New shorteners appear frequently; this list may need periodic updates.
@@ -34,19 +31,64 @@
 //!
 //! 1. Add its domain to the [`SERVICES`] array
 //! 2. (Optional) Implement a dedicated resolver module if it requires special
-//!    handling beyond standard HTTP redirects
+//!    handling beyond standard HTTP redirects, and map the domain to it in
+//!    [`STRATEGIES`]
+//!
+//! ## Service definition files
+//!
+//! Services can also be added at runtime, without a new crate release, by
+//! loading a TOML or JSON definition file with [`load_services`]. Entries in
+//! the file are merged with the built-ins and take precedence over them, so a
+//! file can both add new shorteners and change how a built-in one is resolved.
+//!
+//! ```toml
+//! [[service]]
+//! domain = "sho.rt"
+//! aliases = ["www.sho.rt"]
+//! strategy = "redirect"          # see `Strategy`
+//! ua = "browser"                 # default | browser | curl
+//! redirect = "follow"            # same_host | follow | none
+//! patterns = ['data-target="([^"]+)"']
+//! stages = ["redirect", "meta_refresh", "api"]   # see `Stage`
+//! preview = { suffix = "+" }     # see `Preview`
+//! api = "bitly"                  # see `ApiProvider`, for branded domains
+//! rewrite = { template = "https://example.com/item/{id}" }   # see `Rewrite`
+//!
+//! [[rule]]                       # see `crate::rules`
+//! service = "sho.rt"
+//! selector = "a#skip"
+//! attribute = "href"
+//! ```
+//!
+//! The JSON form uses the same field names: `{"service": [{"domain": "sho.rt"}]}`.
 //!
 //! ## Example
 //!
 //! ```ignore
 //! let url = "https://bit.ly/abc123";
 //! if let Some(service) = which_service(url) {
-//!     println!("Shortened using: {service}");
+//!     println!("Shortened using: {}", service.domain);
 //! }
 //! ```
 //!
 //! If the URL does not match any known shortener domain, [`which_service`]
 //! returns `None`.
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    path::Path,
+    sync::{LazyLock, RwLock},
+};
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use regex::Regex;
+use url::Url;
+
+use crate::{
+    idn::{domain_to_ascii, host_matches, host_of},
+    rules::{add_rule, Rule},
+    Error, Result,
+};
 
 /// List of domains for some known
 /// URL shortening services.
@@ -55,6 +97,9 @@ pub(crate) static SERVICES: &[&str] = &[
     "adfoc.us",
     "amzn.to",
     "amzn.id",
+    "amzn.com",
+    "app.goo.gl",
+    "app.link",
     "atominik.com",
     "ay.gy",
     "b.link",
@@ -106,13 +151,22 @@ pub(crate) static SERVICES: &[&str] = &[
     "nmc.sg",
     "nowlinks.net",
     "ow.ly",
+    "page.link",
     "plu.sh",
     "prf.hn",
+    // Mimecast URL Protect links only carry the destination's domain
+    "protect-au.mimecast.com",
+    "protect-ca.mimecast.com",
+    "protect-de.mimecast.com",
+    "protect-eu.mimecast.com",
+    "protect-us.mimecast.com",
+    "protect-za.mimecast.com",
     "q.gs",
     "qr.ae",
     "qr.net",
     "rb.gy",
     "rebrand.ly",
+    "redd.it",
     "rlu.ru",
     "rotf.lol",
     "s.click.aliexpress.com",
@@ -151,6 +205,7 @@ pub(crate) static SERVICES: &[&str] = &[
     "v.gd",
     "virg.in",
     "vzturl.com",
+    "wa.me",
     "waa.ai",
     "washex.am",
     "we.tl",
@@ -158,29 +213,640 @@ pub(crate) static SERVICES: &[&str] = &[
     "y2u.be",
     "yt.vu",
     "yourwish.es",
+    "youtu.be",
     "zpr.io",
 ];
 
-pub(crate) fn which_service(url: &str) -> Option<&'static str> {
+/// Built-in services that need something other than the [`Strategy::Generic`]
+/// resolver.
+pub(crate) static STRATEGIES: &[(&str, Strategy)] = &[
+    // Browser UA Resolvers (services that require browser user agent)
+    ("2cm.es", Strategy::Browser),
+    ("rb.gy", Strategy::Browser),
+    // Curl UA Resolvers (services that work with curl user agent)
+    ("bit.ly", Strategy::Curl),
+    ("b.link", Strategy::Curl),
+    ("cutt.ly", Strategy::Curl),
+    ("fb.me", Strategy::Curl),
+    ("git.io", Strategy::Curl),
+    ("plu.sh", Strategy::Curl),
+    ("rlu.ru", Strategy::Curl),
+    ("t.co", Strategy::Curl),
+    ("t.ly", Strategy::Curl),
+    ("t2m.io", Strategy::Curl),
+    ("tiny.cc", Strategy::Curl),
+    ("tinyurl.com", Strategy::Curl),
+    ("tny.sh", Strategy::Curl),
+    // Adfly Resolver
+    ("adf.ly", Strategy::Adfly),
+    ("atominik.com", Strategy::Adfly),
+    ("fumacrom.com", Strategy::Adfly),
+    ("intamema.com", Strategy::Adfly),
+    ("j.gs", Strategy::Adfly),
+    ("q.gs", Strategy::Adfly),
+    // Redirect Resolvers
+    ("gns.io", Strategy::Redirect),
+    ("ity.im", Strategy::Redirect),
+    ("ldn.im", Strategy::Redirect),
+    ("nowlinks.net", Strategy::Redirect),
+    ("tr.im", Strategy::Redirect),
+    ("u.to", Strategy::Redirect),
+    ("vzturl.com", Strategy::Redirect),
+    // Meta Refresh Resolvers
+    ("cutt.us", Strategy::Refresh),
+    ("soo.gd", Strategy::Refresh),
+    // Specific Resolvers
+    ("adfoc.us", Strategy::Adfocus),
+    ("goo.gl", Strategy::Fallback),
+    ("ow.ly", Strategy::Fallback),
+    ("l1nq.com", Strategy::UrlShortDev),
+    ("sl1nk.com", Strategy::UrlShortDev),
+    ("lnkd.in", Strategy::LinkedIn),
+    ("shorturl.at", Strategy::ShortUrl),
+    ("surl.li", Strategy::SurlLi),
+    // Public instances of self-hosted platforms
+    ("kutt.it", Strategy::Kutt),
+];
+
+/// Preview endpoints of the built-in services: they show the destination
+/// without counting a click (see [`Stage::Preview`]).
+pub(crate) static PREVIEWS: &[(&str, Preview)] = &[
+    ("bit.ly", Preview::Suffix(Cow::Borrowed("+"))),
+    ("j.mp", Preview::Suffix(Cow::Borrowed("+"))),
+    ("t.ly", Preview::Suffix(Cow::Borrowed("+"))),
+    ("tinyurl.com", Preview::Host(Cow::Borrowed("preview.tinyurl.com"))),
+    ("is.gd", Preview::Endpoint(Cow::Borrowed("https://is.gd/forward.php?format=json&shorturl={id}"))),
+    ("v.gd", Preview::Endpoint(Cow::Borrowed("https://v.gd/forward.php?format=json&shorturl={id}"))),
+];
+
+/// Offline resolvers of the built-in services: their links map to the
+/// destination without any request (see [`Stage::Rewrite`]).
+pub(crate) static REWRITES: &[(&str, Rewrite)] = &[
+    ("amzn.com", Rewrite::Amazon),
+    ("redd.it", Rewrite::Reddit),
+    ("wa.me", Rewrite::WhatsApp),
+    ("y2u.be", Rewrite::YouTube),
+    ("youtu.be", Rewrite::YouTube),
+];
+
+/// Official APIs of the built-in services, used when a token is set (see
+/// [`Options::api_token`](crate::Options::api_token)).
+pub(crate) static APIS: &[(&str, ApiProvider)] = &[
+    ("bit.ly", ApiProvider::Bitly),
+    ("j.mp", ApiProvider::Bitly),
+    ("rebrand.ly", ApiProvider::Rebrandly),
+    ("dub.sh", ApiProvider::Dub),
+    ("tinyurl.com", ApiProvider::TinyUrl),
+];
+
+/// The ASCII form of every [`SERVICES`] entry, with the entry itself.
+static SERVICE_HOSTS: LazyLock<Vec<(String, &'static str)>> = LazyLock::new(|| {
+    SERVICES
+        .iter()
+        .map(|&d| (domain_to_ascii(d).unwrap_or_else(|| d.into()), d))
+        .collect()
+});
+
+/// Services registered at runtime, checked before the built-ins.
+static REGISTERED: LazyLock<RwLock<Vec<Service>>> = LazyLock::new(Default::default);
+
+/// The resolver used to expand a service's short links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Follow HTTP 3xx redirects while they stay on the same host
+    #[default]
+    Generic,
+    /// Follow HTTP 3xx redirects with browser-like headers
+    Browser,
+    /// Follow HTTP 3xx redirects with a curl-like user agent
+    Curl,
+    /// Decode the Adf.ly `ysmm` variable
+    Adfly,
+    /// Extract the destination from JavaScript / HTML redirect patterns
+    Redirect,
+    /// Follow the `Refresh` header / meta refresh tag of the landing page, whatever its status
+    Refresh,
+    /// Extract the Adfoc.us `click_url`
+    Adfocus,
+    /// Follow redirects, falling back to a request shaped like curl's
+    Fallback,
+    /// Resolve through the Encurtador API (l1nq.com, sl1nk.com)
+    UrlShortDev,
+    /// Follow redirects, then parse the LinkedIn interstitial page
+    LinkedIn,
+    /// Read the `Location` header of a HEAD request
+    ShortUrl,
+    /// Follow redirects, then parse the surl.li landing page
+    SurlLi,
+    /// Self-hosted YOURLS: the `yourls-api.php?action=expand` API, then redirects
+    Yourls,
+    /// Self-hosted Shlink: the `/rest/v3/short-urls` API (with a key), then redirects
+    Shlink,
+    /// Self-hosted Kutt: the `/api/v2/links` API (with a key), then redirects
+    Kutt,
+    /// Self-hosted Polr: the `/api/v2/action/lookup` API (with a key), then redirects
+    Polr,
+}
+
+/// User agent sent when following redirects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UaProfile {
+    /// Desktop Firefox user agent
+    #[default]
+    Default,
+    /// Desktop Firefox user agent plus the usual browser `Accept*` headers
+    Browser,
+    /// curl-like user agent
+    Curl,
+}
+
+/// Which HTTP redirects are followed automatically.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectPolicy {
+    /// Follow redirects until the host changes
+    #[default]
+    SameHost,
+    /// Follow every redirect (up to reqwest's limit of 10)
+    Follow,
+    /// Do not follow redirects
+    None,
+}
+
+/// A step of the resolution pipeline.
+///
+/// Every expansion runs the stages of its service in order and stops at the
+/// first one that finds a destination off the shortener's host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Where the HTTP 3xx redirects lead
+    Redirect,
+    /// The HTTP `Refresh` header of the landing page
+    RefreshHeader,
+    /// A `<meta http-equiv="refresh">` tag in the landing page
+    MetaRefresh,
+    /// A JavaScript `location` / `window.open` redirect in the landing page
+    JsLocation,
+    /// The `<link rel="canonical">` / `og:url` of the landing page
+    Canonical,
+    /// The service's dedicated resolver (see [`Strategy`])
+    Api,
+    /// The service's preview page or lookup API (see [`Preview`]), which
+    /// doesn't count as a click; only run when listed in the service's
+    /// stages, or first with [`Options::preview_first`](crate::Options::preview_first)
+    Preview,
+    /// The service's offline resolver (see [`Rewrite`]), which maps the link
+    /// to its destination without any request; run first whenever the
+    /// service has one
+    Rewrite,
+    /// The link was wrapped by an email security gateway, and unwrapped
+    /// offline (see [`unwrap_link`](crate::unwrap_link)); never run by the pipeline
+    Unwrap,
+}
+
+/// How a service shows the destination of a link without counting a click.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preview {
+    /// The short URL with a suffix appended to its path (`bit.ly/abc+`)
+    Suffix(Cow<'static, str>),
+    /// The same path on another host (`preview.tinyurl.com/abc`)
+    Host(Cow<'static, str>),
+    /// A lookup URL; `{id}` is replaced with the path of the short URL and
+    /// `{url}` with the percent-encoded short URL
+    Endpoint(Cow<'static, str>),
+}
+
+impl Preview {
+    pub(crate) fn url(&self, short_url: &str) -> Option<String> {
+        //! The preview URL of a short URL.
+        let mut url = Url::parse(short_url).ok()?;
+        match self {
+            Self::Suffix(suffix) => {
+                let path = format!("{}{suffix}", url.path().trim_end_matches('/'));
+                url.set_path(&path);
+            }
+            Self::Host(host) => url.set_host(Some(host)).ok()?,
+            Self::Endpoint(template) => {
+                let id = url.path().trim_matches('/');
+                let encoded = utf8_percent_encode(short_url, NON_ALPHANUMERIC).to_string();
+                return Some(template.replace("{id}", id).replace("{url}", &encoded));
+            }
+        }
+        Some(url.into())
+    }
+}
+
+/// How a service's links map to their destination without any request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rewrite {
+    /// `youtu.be/<id>` to `www.youtube.com/watch?v=<id>`, keeping the other
+    /// parameters (`t`, ...)
+    #[serde(rename = "youtube")]
+    YouTube,
+    /// `redd.it/<id>` to `www.reddit.com/comments/<id>`
+    Reddit,
+    /// `wa.me/<number>` to `api.whatsapp.com/send?phone=<number>`, keeping
+    /// the `text` parameter
+    #[serde(rename = "whatsapp")]
+    WhatsApp,
+    /// `amzn.com/<ASIN>` to `www.amazon.com/dp/<ASIN>`
+    Amazon,
+    /// A destination URL; `{id}` is replaced with the path of the short URL
+    Template(Cow<'static, str>),
+}
+
+impl Rewrite {
+    pub(crate) fn url(&self, short_url: &str) -> Option<String> {
+        //! The destination of a short URL, or `None` when the link doesn't
+        //! have the shape of the service's links.
+        let url = Url::parse(short_url).ok()?;
+        let id = url.path().trim_matches('/');
+        let shaped = |len: std::ops::RangeInclusive<usize>, valid: fn(char) -> bool| {
+            len.contains(&id.len()) && id.chars().all(valid)
+        };
+        let destination = match self {
+            // video ids are 11 characters of the URL-safe base64 alphabet
+            Self::YouTube if shaped(11..=11, |c| c.is_ascii_alphanumeric() || c == '-' || c == '_') => {
+                let mut destination = Url::parse("https://www.youtube.com/watch").ok()?;
+                destination
+                    .query_pairs_mut()
+                    .append_pair("v", id)
+                    .extend_pairs(url.query_pairs().filter(|(k, _)| k != "v"));
+                destination.into()
+            }
+            // post ids are base 36
+            Self::Reddit if shaped(1..=10, |c| c.is_ascii_lowercase() || c.is_ascii_digit()) => {
+                format!("https://www.reddit.com/comments/{id}")
+            }
+            // phone numbers in international format, without `+`
+            Self::WhatsApp if shaped(6..=15, |c| c.is_ascii_digit()) => {
+                let mut destination = Url::parse("https://api.whatsapp.com/send").ok()?;
+                destination.query_pairs_mut().append_pair("phone", id);
+                if let Some((_, text)) = url.query_pairs().find(|(k, _)| k == "text") {
+                    destination.query_pairs_mut().append_pair("text", &text);
+                }
+                destination.into()
+            }
+            Self::Amazon if shaped(10..=10, |c| c.is_ascii_uppercase() || c.is_ascii_digit()) => {
+                format!("https://www.amazon.com/dp/{id}")
+            }
+            Self::Template(template) if !id.is_empty() => template.replace("{id}", id),
+            _ => return None,
+        };
+        Some(destination)
+    }
+}
+
+/// A shortener's official API, which expands links of its own and branded
+/// domains with an account token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiProvider {
+    /// Bitly API v4 (`GET /v4/bitlinks/{domain}/{key}`)
+    Bitly,
+    /// Rebrandly API v1 (`GET /v1/links?domain.fullName=...&slashtag=...`)
+    Rebrandly,
+    /// Dub API (`GET /links/info?domain=...&key=...`)
+    Dub,
+    /// TinyURL API v2 (`GET /alias/{domain}/{alias}`)
+    TinyUrl,
+}
+
+impl ApiProvider {
+    pub fn name(&self) -> &'static str {
+        //! The name used in service definition files.
+        match self {
+            Self::Bitly => "bitly",
+            Self::Rebrandly => "rebrandly",
+            Self::Dub => "dub",
+            Self::TinyUrl => "tinyurl",
+        }
+    }
+
+    pub fn base_url(&self) -> &'static str {
+        //! The default base URL of the API.
+        match self {
+            Self::Bitly => "https://api-ssl.bitly.com",
+            Self::Rebrandly => "https://api.rebrandly.com",
+            Self::Dub => "https://api.dub.co",
+            Self::TinyUrl => "https://api.tinyurl.com",
+        }
+    }
+}
+
+/// The stages, in their default order.
+pub(crate) static STAGES: &[Stage] = &[
+    Stage::Redirect,
+    Stage::RefreshHeader,
+    Stage::MetaRefresh,
+    Stage::JsLocation,
+    Stage::Canonical,
+    Stage::Api,
+];
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        //! The name used in service definition files and extraction rules.
+        match self {
+            Self::Redirect => "redirect",
+            Self::RefreshHeader => "refresh_header",
+            Self::MetaRefresh => "meta_refresh",
+            Self::JsLocation => "js_location",
+            Self::Canonical => "canonical",
+            Self::Api => "api",
+            Self::Preview => "preview",
+            Self::Rewrite => "rewrite",
+            Self::Unwrap => "unwrap",
+        }
+    }
+}
+
+/// A URL shortening service and how to expand its links.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Service {
+    /// Main domain of the service, e.g. `bit.ly`
+    pub domain: String,
+    /// Other domains served by the same service
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Resolver used to expand the links
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// User agent override, used by the `generic`, `browser`, `curl` and
+    /// `redirect` strategies
+    #[serde(default)]
+    pub ua: Option<UaProfile>,
+    /// Redirect policy override, used by the `generic`, `browser`, `curl` and
+    /// `redirect` strategies
+    #[serde(default)]
+    pub redirect: Option<RedirectPolicy>,
+    /// Extra regex patterns whose first capture group is the destination,
+    /// tried before any extraction rule by the resolvers that parse pages
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Pipeline stages to run, in order; stages left out are disabled
+    #[serde(default)]
+    pub stages: Option<Vec<Stage>>,
+    /// Preview endpoint override (see [`Stage::Preview`])
+    #[serde(default)]
+    pub preview: Option<Preview>,
+    /// Official API override, e.g. for branded domains (see [`ApiProvider`])
+    #[serde(default)]
+    pub api: Option<ApiProvider>,
+    /// Offline resolver override (see [`Stage::Rewrite`])
+    #[serde(default)]
+    pub rewrite: Option<Rewrite>,
+}
+
+impl Strategy {
+    pub fn name(&self) -> &'static str {
+        //! The name used in service definition files and extraction rules.
+        match self {
+            Self::Generic => "generic",
+            Self::Browser => "browser",
+            Self::Curl => "curl",
+            Self::Adfly => "adfly",
+            Self::Redirect => "redirect",
+            Self::Refresh => "refresh",
+            Self::Adfocus => "adfocus",
+            Self::Fallback => "fallback",
+            Self::UrlShortDev => "urlshortdev",
+            Self::LinkedIn => "linkedin",
+            Self::ShortUrl => "shorturl",
+            Self::SurlLi => "surlli",
+            Self::Yourls => "yourls",
+            Self::Shlink => "shlink",
+            Self::Kutt => "kutt",
+            Self::Polr => "polr",
+        }
+    }
+}
+
+impl Service {
+    pub fn new(domain: &str, strategy: Strategy) -> Self {
+        //! Creates a service entry with default UA, redirect policy and patterns.
+        Self {
+            domain: domain.into(),
+            aliases: vec![],
+            strategy,
+            ua: None,
+            redirect: None,
+            patterns: vec![],
+            stages: None,
+            preview: None,
+            api: None,
+            rewrite: None,
+        }
+    }
+
+    pub(crate) fn builtin(domain: &str) -> Self {
+        //! Creates the entry for a domain of the built-in [`SERVICES`] list.
+        let strategy = STRATEGIES
+            .iter()
+            .find(|(d, _)| *d == domain)
+            .map(|(_, s)| *s)
+            .unwrap_or_default();
+        Self::new(domain, strategy)
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        //! Host match against the domain and aliases, like [`SERVICES`]; they
+        //! are in ASCII form once registered.
+        host_matches(host, &self.domain) || self.aliases.iter().any(|a| host_matches(host, a))
+    }
+
+    pub(crate) fn matches_url(&self, url: &str) -> bool {
+        //! Whether a URL is on one of the service's hosts.
+        host_of(url).is_some_and(|host| self.matches(&host))
+    }
+
+    pub(crate) fn stages(&self) -> Vec<Stage> {
+        //! The effective pipeline: the override, or the default for the strategy.
+        //!
+        //! Services with a dedicated resolver run it first and fall back to the
+        //! generic stages; the redirect-following and meta refresh strategies
+        //! have no API stage.
+        self.stages.clone().unwrap_or_else(|| match self.strategy {
+            Strategy::Generic | Strategy::Browser | Strategy::Curl | Strategy::Refresh => {
+                STAGES.iter().copied().filter(|s| *s != Stage::Api).collect()
+            }
+            _ => [Stage::Api]
+                .into_iter()
+                .chain(STAGES.iter().copied().filter(|s| *s != Stage::Api))
+                .collect(),
+        })
+    }
+
+    pub(crate) fn preview(&self) -> Option<Preview> {
+        //! The effective preview endpoint: the override or the built-in one.
+        self.preview.clone().or_else(|| {
+            PREVIEWS
+                .iter()
+                .find(|(d, _)| *d == self.domain)
+                .map(|(_, p)| p.clone())
+        })
+    }
+
+    pub(crate) fn rewrite(&self) -> Option<Rewrite> {
+        //! The effective offline resolver: the override or the built-in one.
+        self.rewrite.clone().or_else(|| {
+            REWRITES
+                .iter()
+                .find(|(d, _)| *d == self.domain)
+                .map(|(_, r)| r.clone())
+        })
+    }
+
+    pub(crate) fn api(&self) -> Option<ApiProvider> {
+        //! The effective official API: the override or the built-in one.
+        self.api.or_else(|| APIS.iter().find(|(d, _)| *d == self.domain).map(|(_, a)| *a))
+    }
+
+    pub(crate) fn ua_profile(&self) -> UaProfile {
+        //! The effective user agent profile: the override or the strategy default.
+        self.ua.unwrap_or(match self.strategy {
+            Strategy::Browser => UaProfile::Browser,
+            Strategy::Curl | Strategy::Fallback | Strategy::Kutt => UaProfile::Curl,
+            _ => UaProfile::Default,
+        })
+    }
+
+    pub(crate) fn redirect_policy(&self) -> RedirectPolicy {
+        //! The effective redirect policy: the override or the strategy default.
+        self.redirect.unwrap_or(match self.strategy {
+            Strategy::Browser
+            | Strategy::Curl
+            | Strategy::Fallback
+            | Strategy::Kutt
+            | Strategy::Redirect
+            | Strategy::UrlShortDev => RedirectPolicy::Follow,
+            _ => RedirectPolicy::SameHost,
+        })
+    }
+}
+
+/// A set of service definitions and extraction rules, as read from a
+/// definition file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Registry {
+    #[serde(rename = "service", default)]
+    services: Vec<Service>,
+    #[serde(rename = "rule", default)]
+    rules: Vec<Rule>,
+}
+
+impl Registry {
+    pub fn from_toml(s: &str) -> Result<Self> {
+        //! Parses a TOML service definition file.
+        toml::from_str(s).map_err(|e| Error::Registry(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self> {
+        //! Parses a JSON service definition file.
+        serde_json::from_str(s).map_err(|e| Error::Registry(e.to_string()))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        //! Reads a service definition file; the format is chosen from the
+        //! extension (`.json`, anything else is read as TOML).
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Error::Registry(format!("{}: {}", path.display(), e)))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::from_json(&content),
+            _ => Self::from_toml(&content),
+        }
+    }
+
+    pub fn services(&self) -> &[Service] {
+        //! The service entries in file order.
+        &self.services
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        //! The extraction rules in file order.
+        &self.rules
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        //! Checks that every service pattern and every rule compiles.
+        for service in &self.services {
+            for pattern in &service.patterns {
+                Regex::new(pattern)
+                    .map_err(|e| Error::Registry(format!("{}: {}: {}", service.domain, pattern, e)))?;
+            }
+        }
+        self.rules.iter().try_for_each(Rule::validate)
+    }
+}
+
+pub fn load_services<P: AsRef<Path>>(path: P) -> Result<usize> {
+    //! Loads a TOML or JSON service definition file and merges it with the
+    //! built-in registry. Extraction rules in the file are added as well.
+    //!
+    //! Returns the number of services loaded from the file. Nothing is
+    //! registered when a service pattern or a rule does not compile.
+    //!
+    //! ## Example
+    //! ```ignore
+    //! use urlexpand::load_services;
+    //!
+    //! load_services("/etc/urlexpand/services.toml")?;
+    //! assert!(urlexpand::is_shortened("https://sho.rt/abc"));
+    //! ```
+    let registry = Registry::from_file(path)?;
+    registry.validate()?;
+    let n = registry.services.len();
+    registry.services.into_iter().for_each(register_service);
+    registry.rules.into_iter().try_for_each(add_rule)?;
+    Ok(n)
+}
+
+pub fn register_service(mut service: Service) {
+    //! Adds a service to the registry, replacing any runtime entry with the
+    //! same domain. Runtime entries take precedence over the built-ins.
+    //!
+    //! The domain and aliases are stored in their ASCII form, so that a
+    //! Unicode domain and its `xn--` form are the same entry.
+    let ascii = |d: &mut String| {
+        if let Some(a) = domain_to_ascii(d) {
+            *d = a;
+        }
+    };
+    ascii(&mut service.domain);
+    service.aliases.iter_mut().for_each(ascii);
+    let mut registered = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+    registered.retain(|s| s.domain != service.domain);
+    registered.push(service);
+}
+
+pub(crate) fn which_service(url: &str) -> Option<Service> {
     //! Identifies which URL shortening service is used for a given URL.
     //!
-    //! This function searches the URL string for any domain that matches known
-    //! shortening services listed in the [`SERVICES`] array.
+    //! This function normalises the host of the URL and looks it up in the
+    //! runtime-registered services, then in the known shortening services
+    //! listed in the [`SERVICES`] array.
     //!
     //! # Arguments
     //!
-    //! * `url` - The URL string to check for known shortener domains
+    //! * `url` - The URL (or `host/path` string without a scheme) to check
     //!
     //! # Returns
     //!
-    //! Returns `Some(&'static str)` with the name of the matching shortener service
-    //! if found, or `None` if the URL doesn't contain any known shortener domains.
+    //! Returns `Some(Service)` with the matching shortener service if found, or
+    //! `None` if the URL's host isn't a known shortener domain.
     //!
     //! # Behavior
     //!
-    //! - Uses substring-based matching (not strict hostname matching)
+    //! - Matches the host or any of its parent domains, after IDNA / UTS-46
+    //!   normalisation (case-insensitive, Unicode and `xn--` forms are equal)
+    //! - Runtime-registered services win over the built-ins
     //! - Returns the first matching service from the [`SERVICES`] list
-    //! - Case-sensitive matching based on the exact strings in [`SERVICES`]
     //!
     //! # Example
     //!
@@ -188,7 +854,7 @@ pub(crate) fn which_service(url: &str) -> Option<&'static str> {
     //! use urlexpand::services::which_service;
     //!
     //! let url = "https://bit.ly/abc123";
-    //! assert_eq!(which_service(url), Some("bit.ly"));
+    //! assert_eq!(which_service(url).map(|s| s.domain), Some("bit.ly".into()));
     //!
     //! let normal_url = "https://example.com/page";
     //! assert_eq!(which_service(normal_url), None);
@@ -197,7 +863,18 @@ pub(crate) fn which_service(url: &str) -> Option<&'static str> {
     //! # Note
     //!
     //! This is a fast, first-pass filter used by the URL expansion logic to determine
-    //! which resolver to use. The substring-based approach may produce false positives
-    //! in rare cases but provides good performance for typical usage.
-    SERVICES.iter().find(|&x| url.contains(x)).copied()
+    //! which resolver to use.
+    let host = host_of(url)?;
+    let registered = REGISTERED.read().unwrap_or_else(|e| e.into_inner());
+    registered
+        .iter()
+        .rev()
+        .find(|s| s.matches(&host))
+        .cloned()
+        .or_else(|| {
+            SERVICE_HOSTS
+                .iter()
+                .find(|(ascii, _)| host_matches(&host, ascii))
+                .map(|(_, x)| Service::builtin(x))
+        })
 }
@@ -1,7 +1,21 @@
-use super::{is_shortened, unshorten, validate};
+use super::{
+    add_rule, add_tracking_parameter, canonicalize, find_affiliates, parse_amazon_product, strip_affiliates, AffiliateProgram, ApiProvider, DeepLinkPlatform, Destination, decode_base64, decode_ysmm, decode_hex, decode_percent, decode_url, defang, expand_from_response,
+    find_js_redirects, find_outbound_links, find_short_urls, find_url_in_query, find_ysmm, idn, is_shortened, meta_refresh,
+    parse_deep_link, parse_destination, parse_refresh, refang, register_service, rules, services::which_service, unpack, unshorten, unwrap_link,
+    validate, Error, Expansion, JsSink, Options, PostStep, Preview, RedirectPolicy, Registry, Rewrite, Rule, Service,
+    Stage, Store, Strategy, UaProfile, Wrapper, YsmmError,
+};
 #[cfg(feature = "blocking")]
 use super::unshorten_async;
+#[cfg(not(feature = "blocking"))]
+use base64::{engine::general_purpose, Engine as _};
 use std::time::Duration;
+#[cfg(not(feature = "blocking"))]
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+};
 
 /// Test data for URL expansion
 struct TestCase {
@@ -47,6 +61,64 @@ const TEST_CASES: &[TestCase] = &[
     TestCase::new("fb_me", "https://fb.me/3alqLKi", "https://www.facebook.com/"),
 ];
 
+/// Serve canned raw HTTP responses, by request path, on a local port.
+/// `{base}` in a response is replaced with the base URL, `{ip}` with the same
+/// server on another host (`http://127.0.0.1:<port>`).
+/// Returns the base URL (`http://localhost:<port>`).
+#[cfg(not(feature = "blocking"))]
+fn serve(routes: &[(&str, &str)]) -> String {
+    serve_recorded(routes).0
+}
+
+/// [`serve`], also handing back every raw request received.
+#[cfg(not(feature = "blocking"))]
+fn serve_recorded(routes: &[(&str, &str)]) -> (String, std::sync::mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let ip_b64 = general_purpose::URL_SAFE_NO_PAD.encode(format!("http://127.0.0.1:{port}/x"));
+    let fill = |s: &str| {
+        s.replace("{base}", &format!("http://localhost:{port}"))
+            .replace("{ip_b64}", &ip_b64)
+            .replace("{ip}", &format!("http://127.0.0.1:{port}"))
+    };
+    let routes = routes
+        .iter()
+        .map(|(path, response)| (fill(path), fill(response)))
+        .collect::<Vec<_>>();
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut buf = [0; 8192];
+            let n = stream.read(&mut buf).unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+            let response = routes
+                .iter()
+                .find(|(p, _)| p == path)
+                .map(|(_, r)| r.as_str())
+                .unwrap_or("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+            let _ = tx.send(request.clone());
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    (format!("http://localhost:{port}"), rx)
+}
+
+/// A raw `302 Found` response to `location`.
+#[cfg(not(feature = "blocking"))]
+fn found(location: &str) -> String {
+    format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+}
+
+/// A raw `200 OK` response with an HTML body.
+#[cfg(not(feature = "blocking"))]
+fn ok(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
 /// Helper function to test URL expansion
 fn test_expansion(result: super::Result<String>, test_case: &TestCase) -> std::result::Result<(), String> {
     match result {
@@ -94,11 +166,903 @@ fn test_is_shortened() {
     assert!(!is_shortened(url));
 }
 
+#[test]
+fn test_builtin_strategies() {
+    assert_eq!(which_service("https://bit.ly/x").unwrap().strategy, Strategy::Curl);
+    assert_eq!(which_service("https://adfoc.us/x").unwrap().strategy, Strategy::Adfocus);
+    assert_eq!(which_service("https://is.gd/x").unwrap().strategy, Strategy::Generic);
+    assert!(which_service("https://www.google.com").is_none());
+}
+
+#[test]
+fn test_registry_from_toml() {
+    let registry = Registry::from_toml(
+        r#"
+        [[service]]
+        domain = "sho.rt"
+        aliases = ["www.sho.rt"]
+        strategy = "redirect"
+        ua = "browser"
+        redirect = "none"
+        patterns = ['data-target="([^"]+)"']
+
+        [[service]]
+        domain = "tiny.example"
+        "#,
+    )
+    .unwrap();
+    let services = registry.services();
+    assert_eq!(services.len(), 2);
+    assert_eq!(services[0].strategy, Strategy::Redirect);
+    assert_eq!(services[0].ua_profile(), UaProfile::Browser);
+    assert_eq!(services[0].redirect_policy(), RedirectPolicy::None);
+    assert_eq!(services[1], Service::new("tiny.example", Strategy::Generic));
+    assert!(Registry::from_toml("[[service]]\nstrategy = \"nope\"").is_err());
+    assert!(registry.validate().is_ok());
+    let invalid = Registry::from_toml("[[service]]\ndomain = \"bad.example\"\npatterns = ['href=\"([^\"]+\"']").unwrap();
+    assert!(matches!(invalid.validate(), Err(Error::Registry(_))));
+}
+
+#[test]
+fn test_preview_urls() {
+    let preview = |url: &str| which_service(url).and_then(|s| s.preview()).and_then(|p| p.url(url));
+    assert_eq!(preview("https://bit.ly/abc").as_deref(), Some("https://bit.ly/abc+"));
+    assert_eq!(preview("https://bit.ly/abc/").as_deref(), Some("https://bit.ly/abc+"));
+    assert_eq!(preview("https://tinyurl.com/abc").as_deref(), Some("https://preview.tinyurl.com/abc"));
+    assert_eq!(
+        preview("https://v.gd/abc").as_deref(),
+        Some("https://v.gd/forward.php?format=json&shorturl=abc")
+    );
+    assert_eq!(preview("https://cutt.ly/abc"), None);
+    let endpoint = Preview::Endpoint("https://api.example/lookup?u={url}".into());
+    assert_eq!(
+        endpoint.url("https://s.example/a").as_deref(),
+        Some("https://api.example/lookup?u=https%3A%2F%2Fs%2Eexample%2Fa")
+    );
+
+    let registry = Registry::from_toml(
+        r#"
+        [[service]]
+        domain = "peek.example"
+        preview = { suffix = "~" }
+        stages = ["preview", "redirect"]
+        "#,
+    )
+    .unwrap();
+    let service = &registry.services()[0];
+    assert_eq!(service.preview(), Some(Preview::Suffix("~".into())));
+    assert_eq!(service.stages(), [Stage::Preview, Stage::Redirect]);
+}
+
+#[test]
+fn test_api_providers() {
+    let api = |url: &str| which_service(url).and_then(|s| s.api());
+    assert_eq!(api("https://bit.ly/abc"), Some(ApiProvider::Bitly));
+    assert_eq!(api("https://j.mp/abc"), Some(ApiProvider::Bitly));
+    assert_eq!(api("https://rebrand.ly/abc"), Some(ApiProvider::Rebrandly));
+    assert_eq!(api("https://dub.sh/abc"), Some(ApiProvider::Dub));
+    assert_eq!(api("https://tinyurl.com/abc"), Some(ApiProvider::TinyUrl));
+    assert_eq!(api("https://cutt.ly/abc"), None);
+
+    // branded domains
+    let registry = Registry::from_toml("[[service]]\ndomain = \"go.brand.example\"\napi = \"rebrandly\"").unwrap();
+    assert_eq!(registry.services()[0].api(), Some(ApiProvider::Rebrandly));
+    assert!(Registry::from_toml("[[service]]\ndomain = \"x.example\"\napi = \"nope\"").is_err());
+
+    // tokens stay out of logs
+    let options = Options::new().api_token(ApiProvider::Bitly, "s3cr3t-token");
+    assert!(!format!("{options:?}").contains("s3cr3t-token"));
+}
+
+#[test]
+fn test_rewrites() {
+    let rewrite = |url: &str| which_service(url).and_then(|s| s.rewrite()?.url(url));
+    assert_eq!(
+        rewrite("https://youtu.be/dQw4w9WgXcQ?t=42").as_deref(),
+        Some("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42")
+    );
+    assert_eq!(rewrite("https://y2u.be/dQw4w9WgXcQ").as_deref(), Some("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+    assert_eq!(rewrite("https://redd.it/1abcxyz").as_deref(), Some("https://www.reddit.com/comments/1abcxyz"));
+    assert_eq!(
+        rewrite("https://wa.me/15551234567?text=hi%20there").as_deref(),
+        Some("https://api.whatsapp.com/send?phone=15551234567&text=hi+there")
+    );
+    assert_eq!(rewrite("https://amzn.com/B00X4WHP5E").as_deref(), Some("https://www.amazon.com/dp/B00X4WHP5E"));
+    // links of another shape, and services without an offline resolver
+    for url in [
+        "https://youtu.be/short",
+        "https://redd.it/",
+        "https://wa.me/message/IAZK3N6ZQ5ABC1",
+        "https://amzn.com/gp/help",
+        "https://bit.ly/3alqLKi",
+    ] {
+        assert_eq!(rewrite(url), None, "{url}");
+    }
+
+    let registry =
+        Registry::from_toml("[[service]]\ndomain = \"s.shop.example\"\nrewrite = { template = \"https://shop.example/p/{id}\" }")
+            .unwrap();
+    let service = &registry.services()[0];
+    assert_eq!(service.rewrite, Some(Rewrite::Template("https://shop.example/p/{id}".into())));
+    assert_eq!(service.rewrite().unwrap().url("https://s.shop.example/42").as_deref(), Some("https://shop.example/p/42"));
+    let registry = Registry::from_toml("[[service]]\ndomain = \"yt.example\"\nrewrite = \"youtube\"").unwrap();
+    assert_eq!(registry.services()[0].rewrite, Some(Rewrite::YouTube));
+}
+
+#[test]
+fn test_self_hosted_platforms() {
+    assert_eq!(which_service("https://kutt.it/abc").unwrap().strategy, Strategy::Kutt);
+    let registry = Registry::from_toml(
+        r#"
+        [[service]]
+        domain = "go.corp.example"
+        strategy = "yourls"
+
+        [[service]]
+        domain = "s.corp.example"
+        strategy = "shlink"
+        "#,
+    )
+    .unwrap();
+    let strategies = registry.services().iter().map(|s| s.strategy.name()).collect::<Vec<_>>();
+    assert_eq!(strategies, ["yourls", "shlink"]);
+    assert_eq!(registry.services()[0].stages()[0], Stage::Api);
+
+    let options = Options::new().instance_key("S.corp.example", "s3cr3t-key");
+    assert!(!format!("{options:?}").contains("s3cr3t-key"));
+    // keys are found whichever case the domains are written in
+    for domain in ["s.corp.example", "S.Corp.Example"] {
+        let service = Service::new(domain, Strategy::Shlink);
+        assert_eq!(super::resolvers::Context::new(&service, &options).instance_key(), Some("s3cr3t-key"));
+    }
+    // and whichever form, Unicode or `xn--`, an internationalised domain is in
+    let options = Options::new().instance_key("Kurz.Bücher.example", "idn-key");
+    for domain in ["kurz.bücher.example", "kurz.xn--bcher-kva.example"] {
+        let service = Service::new(domain, Strategy::Shlink);
+        assert_eq!(super::resolvers::Context::new(&service, &options).instance_key(), Some("idn-key"));
+    }
+}
+
+#[test]
+fn test_registry_from_json() {
+    let registry =
+        Registry::from_json(r#"{"service": [{"domain": "j.example", "strategy": "refresh"}]}"#).unwrap();
+    assert_eq!(registry.services()[0].strategy, Strategy::Refresh);
+}
+
+#[test]
+fn test_register_service() {
+    assert!(!is_shortened("https://reg.example/abc"));
+    let mut service = Service::new("reg.example", Strategy::Browser);
+    service.aliases.push("go.reg.example".into());
+    register_service(service);
+    assert!(is_shortened("https://reg.example/abc"));
+    assert!(validate("go.reg.example/abc").is_some());
+
+    // runtime entries override the built-ins
+    register_service(Service::new("soo.gd", Strategy::Curl));
+    assert_eq!(which_service("https://soo.gd/x").unwrap().strategy, Strategy::Curl);
+}
+
+#[test]
+fn test_builtin_rules() {
+    let adfocus = Service::builtin("adfoc.us");
+    let html = r#"<script>var click_url = "https://example.com/a";</script>"#;
+    assert_eq!(rules::extract(&adfocus, Stage::Api, html).as_deref(), Some("https://example.com/a"));
+
+    let linkedin = Service::builtin("lnkd.in");
+    let html = r#"<a data-tracking-control-name="external_url_click" href="https://example.com/?a=1&amp;b=2">go</a>"#;
+    assert_eq!(rules::extract(&linkedin, Stage::Api, html).as_deref(), Some("https://example.com/?a=1&b=2"));
+
+    // the redirect page rules are scoped to their service
+    let nowlinks = Service::builtin("nowlinks.net");
+    let html = r#"<a href="/x" target='_blank'>https://example.com/n</a>"#;
+    assert_eq!(rules::extract(&nowlinks, Stage::JsLocation, html).as_deref(), Some("https://example.com/n"));
+    let redirect = Service::builtin("u.to");
+    assert_eq!(rules::extract(&redirect, Stage::JsLocation, html), None);
+    let tinyurl = Service::builtin("tinyurl.com");
+    let html = r#"<p>You will be redirected to <a id="redirecturl" href="https://example.com/t?a=1">https://example.com/t?a=1</a></p>"#;
+    assert_eq!(rules::extract(&tinyurl, Stage::JsLocation, html).as_deref(), Some("https://example.com/t?a=1"));
+    // rules are scoped to their stage or service
+    let html = r#"<script>var click_url = "https://example.com/a";</script>"#;
+    assert_eq!(rules::extract(&adfocus, Stage::MetaRefresh, html).as_deref(), Some("https://example.com/a"));
+    assert_eq!(rules::extract(&redirect, Stage::Api, html), None);
+    let html = r#"<link rel="canonical" href="https://example.com/c"><meta property="og:url" content="https://example.com/og">"#;
+    assert_eq!(rules::extract(&redirect, Stage::Canonical, html).as_deref(), Some("https://example.com/c"));
+    assert_eq!(rules::extract(&redirect, Stage::JsLocation, html), None);
+}
+
+#[test]
+fn test_js_redirects() {
+    let best = |source: &str| find_js_redirects(source).into_iter().next().map(|r| (r.url, r.sink));
+    assert_eq!(best("location.href='https://example.com/a'"), Some(("https://example.com/a".into(), JsSink::Location)));
+    assert_eq!(best("document.location = \"https:\\/\\/example.com\\/b\";"), Some(("https://example.com/b".into(), JsSink::Location)));
+    assert_eq!(best("self.location.assign('/c')"), Some(("/c".into(), JsSink::Assign)));
+    assert_eq!(best("top.location.replace(\"https://example.com/\" + 'd')"), Some(("https://example.com/d".into(), JsSink::Replace)));
+    assert_eq!(best("setTimeout(\"window.location.href = 'https://example.com/e'\", 3000)"), Some(("https://example.com/e".into(), JsSink::Location)));
+    assert_eq!(best("var p = atob('aHR0cHM6Ly9leGFtcGxlLmNvbS9m'); location = p;"), Some(("https://example.com/f".into(), JsSink::Location)));
+    assert_eq!(best("location.href = decodeURIComponent('https%3A%2F%2Fexample.com%2Fg')"), Some(("https://example.com/g".into(), JsSink::Location)));
+    // comparisons, unknown values and non-destinations are not redirects
+    assert_eq!(best("if (location.href == 'https://example.com/') {}"), None);
+    assert_eq!(best("if (top != self) top.location = self.location;"), None);
+    assert_eq!(best("window.open('', '_blank'); location.href = 'javascript:void(0)'"), None);
+
+    // the real destination outranks popups, analytics and assets
+    let page = r#"<script>
+        window.open("https://popup.example.net/ad");
+        location.href = "https://www.google-analytics.com/collect?v=1";
+        location.assign("https://cdn.example.org/app.js");
+        setTimeout(function () { window.location.href = "https://example.com/dest"; }, 1000);
+    </script>"#;
+    let found = find_js_redirects(page);
+    assert_eq!(found.len(), 4);
+    assert_eq!(found[0].url, "https://example.com/dest");
+    assert_eq!(found[1].sink, JsSink::Open);
+    assert!(found[1].score > 0 && found[2].score < 0 && found[3].score < 0);
+    let page = r#"location.replace("https://www.googletagmanager.com/gtm.js")"#;
+    assert!(find_js_redirects(page).iter().all(|r| r.score < 0));
+
+    // nesting is bounded
+    assert_eq!(best("location = (('https://example.com/h'))"), Some(("https://example.com/h".into(), JsSink::Location)));
+    assert_eq!(best(&format!("location = {}", "(".repeat(200_000))), None);
+    assert_eq!(best(&format!("location = {}'https://example.com/i'", "unescape(".repeat(100_000))), None);
+    assert_eq!(best("var a = b; var b = a; location = a"), None);
+
+    // variables are evaluated once, values and the navigations evaluated are capped
+    let chain = (0..15).map(|i| format!("var v{i} = v{0} + v{0};", i + 1)).collect::<String>() + "var v15 = 'a';";
+    let page = format!("{chain}{}", "location.href = v0;".repeat(20_000));
+    let started = std::time::Instant::now();
+    assert_eq!(best(&page), None);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let page = format!("{chain}location.href = 'https://example.com/' + v10;");
+    assert_eq!(best(&page), Some((format!("https://example.com/{}", "a".repeat(32)), JsSink::Location)));
+    let page = "location.href = '/x';".repeat(1_000);
+    assert_eq!(find_js_redirects(&page).len(), 256);
+}
+
+#[test]
+fn test_decoders() {
+    assert_eq!(decode_base64("aHR0cHM6Ly9leGFtcGxlLmNvbS8_YT0x").as_deref(), Some("https://example.com/?a=1"));
+    assert_eq!(decode_base64("aHR0cHM6Ly9leGFtcGxlLmNvbS8/YT0x==").as_deref(), Some("https://example.com/?a=1"));
+    assert_eq!(decode_base64("not base64!"), None);
+    assert_eq!(decode_hex("68747470733a2f2f").as_deref(), Some("https://"));
+    assert_eq!(decode_hex("\\x68\\x74\\x74\\x70").as_deref(), Some("http"));
+    assert_eq!(decode_hex("0x6869").as_deref(), Some("hi"));
+    assert_eq!(decode_hex("686"), None);
+    assert_eq!(decode_percent("https%253A%252F%252Fexample.com%252F"), "https://example.com/");
+
+    assert_eq!(decode_url("https://example.com/?a=%41").as_deref(), Some("https://example.com/?a=%41"));
+    assert_eq!(decode_url("68747470733a2f2f6578616d706c652e636f6d2f").as_deref(), Some("https://example.com/"));
+    // reversed, then base64
+    assert_eq!(decode_url("8SbvNmLlxGctFGel9yL6MHc0RHa").as_deref(), Some("https://example.com/"));
+    assert_eq!(decode_url("/relative/path"), None);
+    assert_eq!(decode_url("hello"), None);
+    assert_eq!(
+        find_url_in_query("https://gate.example/go?id=7&u=aHR0cHM6Ly9leGFtcGxlLmNvbS9k").as_deref(),
+        Some("https://example.com/d")
+    );
+    assert_eq!(find_url_in_query("https://gate.example/go?id=7"), None);
+
+    // keywords past index 35 are encoded with upper case letters
+    let packed = r#"eval(function(p,a,c,k,e,d){while(c--){if(k[c]){p=p.replace(new RegExp('\\b'+c.toString(a)+'\\b','g'),k[c])}}return p}('a.b.A(\'c://d.e/f?g=1\');',62,37,'||||||||||window|location|https|example|com|q|f||||||||||||||||||||replace'.split('|'),0,{}))"#;
+    assert_eq!(unpack(packed).as_deref(), Some("window.location.replace('https://example.com/q?f=1');"));
+    assert_eq!(unpack("eval(function(p,a,c,k,e,d){}('0',1,1,'x'.split('|'),0,{}))"), None);
+    let found = find_js_redirects(&format!("<script>{packed}</script>"));
+    assert_eq!(found[0].url, "https://example.com/q?f=1");
+    assert_eq!(found[0].sink, JsSink::Replace);
+
+    let service = Service::new("rules.example", Strategy::Redirect);
+    let rule = Rule::regex("rules.example", r#"data-h="([^"]+)""#).post(PostStep::Hex).post(PostStep::Reverse);
+    assert!(rule.validate().is_ok());
+    add_rule(rule.precedence(5)).unwrap();
+    let html = r#"<p data-h="2f6d6f632e656c706d6178652f2f3a7370747468">"#;
+    assert_eq!(rules::extract(&service, Stage::JsLocation, html).as_deref(), Some("https://example.com/"));
+}
+
+#[test]
+fn test_expand_from_response() {
+    let expand = |service, url, status, headers: &[(&str, &str)], body| {
+        expand_from_response(service, url, status, headers, body).map(|e| (e.url, e.stage, e.refresh_delay))
+    };
+    // HTTP redirect, resolved against the page
+    assert_eq!(
+        expand("bit.ly", "https://bit.ly/abc", 301, &[("Location", "/next")], ""),
+        Ok(("https://bit.ly/next".into(), Stage::Redirect, None))
+    );
+    assert_eq!(
+        expand("bit.ly", "https://bit.ly/abc", 200, &[("refresh", "5;url=https://example.com/h")], ""),
+        Ok(("https://example.com/h".into(), Stage::RefreshHeader, Some(Duration::from_secs(5))))
+    );
+    assert_eq!(expand("bit.ly", "https://bit.ly/abc", 1000, &[], ""), Err(Error::InvalidStatus(1000)));
+    let expansion = expand_from_response(
+        "tinyurl.com",
+        "https://tinyurl.com/abc",
+        200,
+        &[],
+        r#"<script>window.location.replace("https://example.com/js");</script>"#,
+    )
+    .unwrap();
+    assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/js", Stage::JsLocation));
+    assert_eq!(expansion.service, "tinyurl.com");
+    assert_eq!(expansion.hops.iter().map(|h| h.url.as_str()).collect::<Vec<_>>(), ["https://tinyurl.com/abc", "https://example.com/js"]);
+
+    // page parsers of the dedicated resolvers
+    let ysmm = "esn9F2pab3nVVn6Ydl2Vh3qcb3m1lWxYarnNJnrbY7Wdhz8Nd3HQB3zMOliQ8WvaYGWNRTmJL5mxxm7ZLk2FdmvRPy1URSlbcv3NQm9LalHxRG0cctHFMGleMl5ZEklMMlkY";
+    let adfly = format!("<script>var ysmm = '{ysmm}';</script>");
+    assert_eq!(
+        expand("adf.ly", "https://adf.ly/abc", 200, &[], &adfly),
+        Ok(("https://example.com/adfly?id=777".into(), Stage::Api, None))
+    );
+    assert_eq!(
+        expand("adfoc.us", "https://adfoc.us/abc", 200, &[], r#"<script>var click_url = "https://example.com/af";</script>"#),
+        Ok(("https://example.com/af".into(), Stage::Api, None))
+    );
+    let lnkd = r#"<a data-tracking-control-name="external_url_click" href="https://example.com/li">go</a>"#;
+    assert_eq!(
+        expand("lnkd.in", "https://lnkd.in/abc", 200, &[], lnkd),
+        Ok(("https://example.com/li".into(), Stage::Api, None))
+    );
+    let surl = r#"<a class="btn" href="https://example.com/surl">To direct link</a>"#;
+    assert_eq!(
+        expand("surl.li", "https://surl.li/abc", 200, &[], surl),
+        Ok(("https://example.com/surl".into(), Stage::Api, None))
+    );
+
+    // nothing to find, unknown service, invalid status
+    assert_eq!(expand("bit.ly", "https://bit.ly/abc", 200, &[], "<p>gone</p>"), Err(Error::NoString));
+    assert_eq!(expand("example.com", "https://example.com/", 301, &[("location", "/x")], ""), Err(Error::NoString));
+    assert!(expand("bit.ly", "https://bit.ly/abc", 1000, &[], "").is_err());
+}
+
+#[test]
+fn test_adfly_pages() {
+    // the fuzz corpus: current (wrapped in `dest` or bare) and previous variants
+    for (domain, page, url) in [
+        (
+            "atominik.com",
+            include_str!("../fuzz/corpus/adfly/atominik.com.html"),
+            "https://example.com/atominik?ref=1&lang=en",
+        ),
+        (
+            "fumacrom.com",
+            include_str!("../fuzz/corpus/adfly/fumacrom.com.html"),
+            "https://www.example.org/fumacrom/",
+        ),
+        ("intamema.com", include_str!("../fuzz/corpus/adfly/intamema.com.html"), "https://example.net/intamema"),
+        ("j.gs", include_str!("../fuzz/corpus/adfly/j.gs.html"), "https://example.com/jgs/page.html#zt4"),
+        ("q.gs", include_str!("../fuzz/corpus/adfly/q.gs.html"), "http://example.org/qgs?x=1#s"),
+    ] {
+        let expansion = expand_from_response(domain, &format!("https://{domain}/abc"), 200, &[], page).unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), (url, Stage::Api), "{domain}");
+    }
+}
+
+#[test]
+fn test_decode_ysmm() {
+    assert_eq!(find_ysmm(r#"var ysmm = "abc";"#), Ok("abc"));
+    assert_eq!(find_ysmm("var ysmm = '';"), Err(YsmmError::Missing));
+    assert_eq!(find_ysmm("var ysmmx = 'abc';"), Err(YsmmError::Missing));
+    assert_eq!(decode_ysmm(""), Err(YsmmError::Missing));
+    // odd lengths, non-ASCII and invalid base64 used to panic or slice out of bounds
+    assert_eq!(decode_ysmm("abc"), Err(YsmmError::Encoding));
+    assert_eq!(decode_ysmm("é1"), Err(YsmmError::Encoding));
+    assert_eq!(decode_ysmm("!!!!"), Err(YsmmError::Encoding));
+    // base64 of "short", shorter than the noise
+    assert_eq!(decode_ysmm("cQ2nhcv"), Err(YsmmError::TooShort));
+    // base64 of 42 characters without any URL
+    let ysmm = "YhWFFWhYYhWFFWhYYhWFFWhYYhWFFWhYYhWFFWhYYhWFFWhYYhWFFWhY";
+    assert_eq!(decode_ysmm(ysmm), Err(YsmmError::NoDestination));
+    assert_eq!(
+        decode_ysmm("elTdBWoYdwH9RCwZcsz9o2vLL02VVm4bYuWU1Gwb").as_deref(),
+        Ok("https://example.net/old/page")
+    );
+    assert_eq!(
+        expand_from_response("adf.ly", "https://adf.ly/abc", 200, &[], "var ysmm = '!!!!';").map(|e| e.url),
+        Err(Error::NoString)
+    );
+}
+
+#[test]
+fn test_unwrap_redirectors() {
+    for (wrapped, wrapper, url) in [
+        ("https://www.google.com/url?sa=t&q=https://example.com/a&ved=0", Wrapper::Redirector, "https://example.com/a"),
+        ("https://www.google.co.uk/url?url=https%3A%2F%2Fexample.com%2F", Wrapper::Redirector, "https://example.com/"),
+        ("https://l.facebook.com/l.php?u=https%3A%2F%2Fexample.com%2F%3Fa%3D1&h=AT0", Wrapper::Redirector, "https://example.com/?a=1"),
+        ("https://www.youtube.com/redirect?event=video_description&q=https%3A%2F%2Fexample.com", Wrapper::Redirector, "https://example.com/"),
+        ("https://slack-redir.net/link?url=http%3A%2F%2Fexample.com%2Fpage", Wrapper::Redirector, "http://example.com/page"),
+        ("https://duckduckgo.com/l/?uddg=https%3A%2F%2Fexample.com%2F&rut=abc", Wrapper::Redirector, "https://example.com/"),
+        ("https://click.example.net/track?id=7&redirect=https%3A%2F%2Fexample.com%2F", Wrapper::Parameter, "https://example.com/"),
+        ("example.net/go?To=https://example.com/x", Wrapper::Parameter, "https://example.com/x"),
+        // encoded twice
+        ("https://example.net/out?dest=https%253A%252F%252Fexample.com%252F", Wrapper::Parameter, "https://example.com/"),
+        ("https://www.google.de/url?q=https://example.com/", Wrapper::Redirector, "https://example.com/"),
+        ("https://google.com.br/url?q=https://example.com/", Wrapper::Redirector, "https://example.com/"),
+    ] {
+        assert_eq!(unwrap_link(wrapped), Some((wrapper, url.to_string())), "{wrapped}");
+    }
+    // no destination, not a redirector path, or not a parameter of the heuristic
+    for url in [
+        "https://www.google.com/search?q=https://example.com",
+        "https://www.google.com/url?q=not+a+link",
+        "https://www.googleusercontent.com/url?q=https://example.com",
+        "https://l.facebook.com/l.php?u=javascript:alert(1)",
+        "https://example.net/share?url=https://example.com",
+        "https://example.net/login?redirect=/home",
+        // not a Google domain
+        "https://google.evil.com/url?q=https://example.com/",
+        "https://www.google.co.evil/url?q=https://example.com/",
+        "https://google.example/url?q=https://example.com/",
+        // a link to the same site
+        "https://example.com/login?redirect=https://example.com/home",
+        "https://www.example.com/login?redirect=https%3A%2F%2Fexample.com%2Fhome",
+    ] {
+        assert_eq!(unwrap_link(url), None, "{url}");
+    }
+
+    // a redirector around a short link is seen through in texts
+    let found = find_short_urls("see https://www.google.com/url?q=https://bit.ly/3alqLKi&sa=D");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].service, "bit.ly");
+}
+
+#[test]
+fn test_deep_links() {
+    let firebase = "https://example.page.link/?link=https%3A%2F%2Fexample.com%2Fitem%3Fid%3D1&apn=com.example.app&afl=https%3A%2F%2Fexample.com%2Fandroid&ibi=com.example.ios&isi=123456&ofl=https%3A%2F%2Fexample.com%2Fweb";
+    let link = parse_deep_link(firebase).unwrap();
+    assert_eq!(link.platform, DeepLinkPlatform::Firebase);
+    assert_eq!(link.web.as_deref(), Some("https://example.com/web"));
+    let android = link.android.unwrap();
+    assert_eq!(
+        (android.package.as_deref(), android.uri.as_deref(), android.fallback.as_deref()),
+        (Some("com.example.app"), Some("https://example.com/item?id=1"), Some("https://example.com/android"))
+    );
+    let ios = link.ios.unwrap();
+    assert_eq!(
+        (ios.bundle_id.as_deref(), ios.app_store_id.as_deref(), ios.url.as_deref()),
+        (Some("com.example.ios"), Some("123456"), Some("https://example.com/item?id=1"))
+    );
+    // without an explicit web fallback, the deep link itself
+    let link = parse_deep_link("https://app.goo.gl/?link=https://example.com/a").unwrap();
+    assert_eq!((link.web.as_deref(), link.android, link.ios), (Some("https://example.com/a"), None, None));
+
+    let branch = "https://example.app.link/abc?%24fallback_url=https%3A%2F%2Fexample.com%2Fweb&%24ios_url=https%3A%2F%2Fapps.apple.com%2Fapp%2Fid1";
+    let link = parse_deep_link(branch).unwrap();
+    assert_eq!(link.platform, DeepLinkPlatform::Branch);
+    assert_eq!(link.web.as_deref(), Some("https://example.com/web"));
+    assert_eq!(link.ios.unwrap().fallback.as_deref(), Some("https://apps.apple.com/app/id1"));
+
+    let intent = "intent://scan/#Intent;scheme=zxing;package=com.google.zxing.client.android;S.browser_fallback_url=http%3A%2F%2Fzxing.org;end";
+    let link = parse_deep_link(intent).unwrap();
+    assert_eq!(link.platform, DeepLinkPlatform::Intent);
+    assert_eq!(link.web.as_deref(), Some("http://zxing.org/"));
+    let android = link.android.unwrap();
+    assert_eq!(
+        (android.package.as_deref(), android.uri.as_deref()),
+        (Some("com.google.zxing.client.android"), Some("zxing://scan/"))
+    );
+    // an intent for a web page opens it in the browser
+    let link = parse_deep_link("intent://example.com/path#Intent;scheme=https;package=com.android.chrome;end").unwrap();
+    assert_eq!(link.web.as_deref(), Some("https://example.com/path"));
+    let link = parse_deep_link("intent://open#Intent;scheme=myapp;end").unwrap();
+    assert_eq!(link.web, None);
+
+    // short links, and links of other hosts
+    for url in [
+        "https://example.page.link/AbCd",
+        "https://example.app.link/abc",
+        "https://example.com/?link=https://example.org",
+        "intent://open",
+    ] {
+        assert_eq!(parse_deep_link(url), None, "{url}");
+    }
+}
+
+#[test]
+fn test_destinations() {
+    let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(parse_destination("https://Example.com/a"), Destination::Web { host: "example.com".into() });
+    assert_eq!(
+        parse_destination("mailto:a@example.com,b@example.com?subject=Hi%20there&body=Pay&to=c@example.com"),
+        Destination::Email {
+            to: strings(&["a@example.com", "b@example.com", "c@example.com"]),
+            subject: Some("Hi there".into()),
+            body: Some("Pay".into()),
+        }
+    );
+    assert_eq!(parse_destination("tel:+1-201-555-0123;ext=42"), Destination::Phone { number: "+12015550123".into() });
+    assert_eq!(
+        parse_destination("sms:+15551234567,+15557654321?body=STOP"),
+        Destination::Sms { numbers: strings(&["+15551234567", "+15557654321"]), body: Some("STOP".into()) }
+    );
+    assert_eq!(
+        parse_destination("smsto:5551234:hello"),
+        Destination::Sms { numbers: strings(&["5551234"]), body: Some("hello".into()) }
+    );
+    for (url, store, app_id) in [
+        ("market://details?id=com.example.app", Store::GooglePlay, "com.example.app"),
+        ("https://play.google.com/store/apps/details?id=com.example.app&hl=en", Store::GooglePlay, "com.example.app"),
+        ("itms-apps://apps.apple.com/app/id123456789", Store::Apple, "123456789"),
+        ("https://apps.apple.com/us/app/example/id123456789", Store::Apple, "123456789"),
+    ] {
+        assert_eq!(parse_destination(url), Destination::AppStore { store, app_id: app_id.into() }, "{url}");
+    }
+    assert_eq!(
+        parse_destination("whatsapp://send?text=hi"),
+        Destination::AppScheme { scheme: "whatsapp".into(), target: "send?text=hi".into(), package: None }
+    );
+    assert_eq!(
+        parse_destination("intent://scan/#Intent;scheme=zxing;package=com.google.zxing.client.android;end"),
+        Destination::AppScheme {
+            scheme: "intent".into(),
+            target: "scan/".into(),
+            package: Some("com.google.zxing.client.android".into()),
+        }
+    );
+    assert_eq!(parse_destination("javascript:alert(1)"), Destination::Other { scheme: "javascript".into() });
+    assert_eq!(parse_destination("not a url"), Destination::Other { scheme: String::new() });
+}
+
+#[test]
+fn test_outbound_links() {
+    let page = r#"<html><body>
+        <a href="https://linktr.ee/"><img alt="Linktree logo"></a>
+        <a data-testid="LinkButton" href="https://shop.example.com/?ref=bio">
+            <span>Shop</span> <span>now</span></a>
+        <a href="https://l.instagram.com/?u=https%3A%2F%2Fblog.example.com%2F&e=AT0"><img alt="My blog"></a>
+        <a href="https://shop.example.com/?ref=bio">Shop again</a>
+        <a href="tel:+15551234567" title="Call me"></a>
+        <a href="/someone/about">About</a>
+        <a href="javascript:void(0)">Menu</a>
+    </body></html>"#;
+    let links = find_outbound_links(page, "https://linktr.ee/someone");
+    let found = links.iter().map(|l| (l.position, l.url.as_str(), l.label.as_deref())).collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (0, "https://shop.example.com/?ref=bio", Some("Shop now")),
+            (1, "https://blog.example.com/", Some("My blog")),
+            (2, "tel:+15551234567", Some("Call me")),
+        ]
+    );
+    // on other hosts, links to the same host are not outbound
+    let links = find_outbound_links(r#"<a href="https://bio.example.com/x">x</a><a href="https://a.example/">a</a>"#, "https://bio.example.com/");
+    assert_eq!(links.iter().map(|l| l.url.as_str()).collect::<Vec<_>>(), ["https://a.example/"]);
+    assert!(find_outbound_links(page, "not a url").is_empty());
+}
+
+#[test]
+fn test_canonicalize() {
+    for (url, canonical) in [
+        (
+            "HTTPS://Example.COM:443/Path/?utm_source=news&utm_medium=email&b=2&a=1&fbclid=IwAR0x#",
+            "https://example.com/Path?a=1&b=2",
+        ),
+        ("http://example.com:80", "http://example.com/"),
+        ("http://example.com:8080//", "http://example.com:8080/"),
+        ("https://www.example.com./a//?", "https://www.example.com/a"),
+        ("https://example.com/?gclid=1&UTM_Campaign=x&_hsenc=p2&mc_eid=3", "https://example.com/"),
+        // repeated parameters keep their order, their values their encoding
+        ("https://example.com/s?q=b&p=1&q=a&x=%2Fy", "https://example.com/s?p=1&q=b&q=a&x=%2Fy"),
+        ("https://example.com/a?flag&&ref=1#top", "https://example.com/a?flag&ref=1#top"),
+        ("mailto:someone@example.com?utm_source=x", "mailto:someone@example.com?utm_source=x"),
+        (" not a url ", "not a url"),
+    ] {
+        assert_eq!(canonicalize(url), canonical, "{url}");
+    }
+    add_tracking_parameter("XTOR*");
+    assert_eq!(canonicalize("https://example.com/?xtor=RSS-1&xtor_src=2&id=3"), "https://example.com/?id=3");
+}
+
+#[test]
+fn test_affiliates() {
+    let tags = |url: &str| find_affiliates(url).into_iter().map(|a| (a.program, a.parameter, a.tag)).collect::<Vec<_>>();
+    for (url, program, parameter, tag) in [
+        ("https://www.amazon.de/dp/B08N5WRWNW/?tag=deals0c-21&linkCode=ll1", AffiliateProgram::Amazon, "tag", "deals0c-21"),
+        (
+            "https://www.aliexpress.com/item/1005001.html?aff_fcid=5f2c&aff_fsk=_DlSH0bj&aff_platform=link-c-tool",
+            AffiliateProgram::AliExpress,
+            "aff_fsk",
+            "_DlSH0bj",
+        ),
+        ("https://prf.hn/click/camref:1100l3k5V/pubref:spring/destination:https%3A%2F%2Fexample.com", AffiliateProgram::Partnerize, "camref", "1100l3k5V"),
+        ("https://www.awin1.com/cread.php?awinmid=1234&awinaffid=98765&ued=x", AffiliateProgram::Awin, "awinaffid", "98765"),
+        ("https://shop.example.com/p/1?cjevent=7d1f2e3a&utm_source=cj", AffiliateProgram::CommissionJunction, "cjevent", "7d1f2e3a"),
+        ("https://www.ebay.co.uk/itm/1234?mkcid=1&campid=5338273189", AffiliateProgram::Ebay, "campid", "5338273189"),
+        ("https://www.bhphotovideo.com/c/product/1.html?BI=2466&KBID=3296", AffiliateProgram::BhPhotoVideo, "kbid", "3296"),
+    ] {
+        assert_eq!(tags(url), [(program, parameter.to_string(), tag.to_string())], "{url}");
+    }
+    // parameters of a program are only read on its merchants
+    assert!(tags("https://example.com/?tag=rust&aff_fcid=1&campid=2").is_empty());
+    assert!(tags("https://www.amazon.com/dp/B08N5WRWNW").is_empty());
+    assert!(tags("https://amazon.secure-login.com/dp/B08N5WRWNW?tag=x-20").is_empty());
+    assert!(tags("https://www.ebay.co.evil/itm/1?campid=5338273189").is_empty());
+    assert!(tags("not a url").is_empty());
+
+    assert_eq!(
+        strip_affiliates("https://www.amazon.com/dp/B08N5WRWNW?tag=deals-20&th=1&linkCode=ll1&creativeASIN=B08N5WRWNW"),
+        "https://www.amazon.com/dp/B08N5WRWNW?th=1"
+    );
+    assert_eq!(
+        strip_affiliates("https://example.com/p?tag=rust&irclickid=x1&cjevent=y2"),
+        "https://example.com/p?tag=rust"
+    );
+    assert_eq!(strip_affiliates("https://example.com/p"), "https://example.com/p");
+}
+
+#[test]
+fn test_amazon_products() {
+    for (url, marketplace, asin) in [
+        ("https://www.amazon.com/dp/B08N5WRWNW", "amazon.com", "B08N5WRWNW"),
+        ("https://www.amazon.co.uk/Echo-Dot-3rd-Gen/dp/B07PJV3JPR/ref=sr_1_1?keywords=echo&qid=1", "amazon.co.uk", "B07PJV3JPR"),
+        ("https://amazon.de/gp/product/3446432345/?tag=x-21", "amazon.de", "3446432345"),
+        ("https://www.amazon.fr/exec/obidos/ASIN/2070612880/ref=nosim", "amazon.fr", "2070612880"),
+        ("https://www.amazon.co.jp/gp/aw/d/b0c1xyz123?psc=1", "amazon.co.jp", "B0C1XYZ123"),
+        ("https://smile.amazon.com/Some-Book/dp/product/0131103628;sessionid=x", "amazon.com", "0131103628"),
+        ("https://www.amazon.com.br/o/ASIN/B08N5WRWNW", "amazon.com.br", "B08N5WRWNW"),
+    ] {
+        let product = parse_amazon_product(url).unwrap_or_else(|| panic!("{url}"));
+        assert_eq!((product.marketplace.as_str(), product.asin.as_str()), (marketplace, asin), "{url}");
+        assert_eq!(canonicalize(url), format!("https://www.{marketplace}/dp/{asin}"));
+    }
+    for url in [
+        "https://www.amazon.com/s?k=rust",
+        "https://www.amazon.com/dp/B08N5",
+        "https://www.example.com/dp/B08N5WRWNW",
+        "https://www.amazon.com/gp/product/B08N5WRWNWX",
+        "https://amazon.secure-login.com/dp/B08N5WRWNW",
+        "https://www.amazon.co.evil/dp/B08N5WRWNW",
+        "https://amazon.example/dp/B08N5WRWNW",
+    ] {
+        assert_eq!(parse_amazon_product(url), None, "{url}");
+    }
+}
+
+#[test]
+fn test_unwrap_link() {
+    for (wrapped, wrapper, url) in [
+        (
+            "https://urldefense.proofpoint.com/v1/url?u=http://www.bouncycastle.org/&k=oIvRg1%2BdGAgOoM1BIlLLqw%3D%3D%0A&r=IKM5u8%2B%2F%2Fi8EBhWOS%2BqGbTqCC%2BrMqWI%2FVfEAEsQO%2F0Y%3D%0A&m=Ww6iaHO73mDQpPQwOwfLfN8WMapqHyvtu8jM8SjqmVQ%3D%0A&s=d3583cfa53dade97025bc6274c6c8951dc29fe0f38830cf8e5a447723b9f1c9a",
+            Wrapper::ProofpointV1,
+            "http://www.bouncycastle.org/",
+        ),
+        (
+            "https://urldefense.proofpoint.com/v2/url?u=http-3A__links.mkt3337.com_ctt-3Fkn-3D3-26ms-3DMzQ3OTg3MDQS1-26r-3DMzkxNzk3NDkwMDA0S0-26b-3D0-26j-3DNTc5NjEzMTM4S0-26mt-3D1-26rt-3D0&d=DwMFaQ&c=Vxt5e0Osvvt2gflwSlsJ5DmPGcPvTRKLJyp031rXjhg&r=yx6ms8b5GJrmz4abGuJRGKkfsZkcbv3ttqrw1Va3jdE&m=2xSz6uVlVSzoMtGSTUiz9eaVv8T6Rk0mm-RC6MPeo1w&s=qR0vfRm7Lp5YoD3RqvH9cuq1ljJr_zjdCntaVU3k5xU&e=",
+            Wrapper::ProofpointV2,
+            "http://links.mkt3337.com/ctt?kn=3&ms=MzQ3OTg3MDQS1&r=MzkxNzk3NDkwMDA0S0&b=0&j=NTc5NjEzMTM4S0&mt=1&rt=0",
+        ),
+        (
+            "https://urldefense.com/v3/__https://google.com:443/search?q=a*test&gs=ps__;Kw!-612Flbf0JvQ3kNJkRi5Jg!Ue6tQudNKaShHg93trcdjqDP8se2ySE65jyCIe2K1D_uNjZ1Lnf6YLQERujngZv9UWf66ujQIQ$",
+            Wrapper::ProofpointV3,
+            "https://google.com/search?q=a+test&gs=ps",
+        ),
+        // a run of replaced characters, and a single slash after the scheme
+        (
+            "https://urldefense.com/v3/__https:/example.com/?a=**A1__;JiY!!abc$",
+            Wrapper::ProofpointV3,
+            "https://example.com/?a=&&1",
+        ),
+        (
+            "https://urldefense.com/v3/__https://bit.ly/3alqLKi__;!!Q1s2t3!abc$",
+            Wrapper::ProofpointV3,
+            "https://bit.ly/3alqLKi",
+        ),
+        (
+            "https://nam02.safelinks.protection.outlook.com/?url=https%3A%2F%2Fbit.ly%2F3alqLKi&data=04%7C01%7Cuser%40example.com&sdata=abc&reserved=0",
+            Wrapper::SafeLinks,
+            "https://bit.ly/3alqLKi",
+        ),
+        (
+            "https://linkprotect.cudasvc.com/url?a=https%3a%2f%2fexample.com%2fpage%3fid%3d1&c=E,1,abc&typo=1",
+            Wrapper::Barracuda,
+            "https://example.com/page?id=1",
+        ),
+        (
+            "https://secure-web.cisco.com/1AbCdEf-GhIjK_lMn/https%3A%2F%2Fexample.com%2Fpath%3Fa%3D1",
+            Wrapper::Cisco,
+            "https://example.com/path?a=1",
+        ),
+    ] {
+        assert_eq!(unwrap_link(wrapped), Some((wrapper, url.to_string())), "{}", wrapper.name());
+    }
+    // not wrapped, not decodable, or not carrying a web link
+    for url in [
+        "https://bit.ly/3alqLKi",
+        "https://urldefense.com/v3/__https://example.com/*__;!!abc$",
+        "https://urldefense.com/v3/__https://example.com/",
+        "https://nam02.safelinks.protection.outlook.com/?data=04",
+        "https://eur01.safelinks.protection.outlook.com/?url=javascript%3Aalert(1)",
+        "https://secure-web.cisco.com/token",
+    ] {
+        assert_eq!(unwrap_link(url), None, "{url}");
+    }
+
+    // nested wrappers around a short link are seen through in texts
+    let inner = "https://urldefense.proofpoint.com/v2/url?u=https-3A__bit.ly_3alqLKi&d=DwMF";
+    let wrapped = format!(
+        "https://eur01.safelinks.protection.outlook.com/?url={}&data=05",
+        percent_encoding::utf8_percent_encode(inner, percent_encoding::NON_ALPHANUMERIC)
+    );
+    let found = find_short_urls(&format!("click {wrapped} now"));
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].url.as_str(), found[0].service.as_str()), (wrapped.as_str(), "bit.ly"));
+    let text = "see https://linkprotect.cudasvc.com/url?a=https%3a%2f%2fexample.com%2fpage&c=E";
+    assert!(find_short_urls(text).is_empty());
+}
+
+#[test]
+fn test_parse_refresh() {
+    let parsed = |s| parse_refresh(s).map(|r| (r.delay.as_secs(), r.url));
+    assert_eq!(parsed("0;URL=https://example.com/r"), Some((0, Some("https://example.com/r".into()))));
+    assert_eq!(parsed("  5 ; url = 'https://example.com/q'x"), Some((5, Some("https://example.com/q".into()))));
+    assert_eq!(parsed("1.5, Url=\"/rel\""), Some((1, Some("/rel".into()))));
+    assert_eq!(parsed("3; https://example.com/bare"), Some((3, Some("https://example.com/bare".into()))));
+    assert_eq!(parsed("10"), Some((10, None)));
+    assert_eq!(parsed("soon; url=/x"), None);
+    assert_eq!(parsed(""), None);
+
+    let html = r#"<p>URL=https://wrong.example/</p>
+        <meta name="description" content="0; url=https://wrong.example/">
+        <META HTTP-EQUIV="REFRESH" CONTENT="2; url=https://example.com/?a=1&amp;b=2">"#;
+    let refresh = meta_refresh(html).unwrap();
+    assert_eq!(refresh.delay, Duration::from_secs(2));
+    assert_eq!(refresh.url.as_deref(), Some("https://example.com/?a=1&b=2"));
+    assert_eq!(meta_refresh("<p>URL=https://wrong.example/</p>"), None);
+}
+
+#[test]
+fn test_runtime_rules() {
+    let service = Service::new("rules.example", Strategy::Redirect);
+    let html = r#"<div id="go" data-b64="aHR0cHM6Ly9leGFtcGxlLmNvbS9iNjQ_eD0x">x</div>"#;
+    add_rule(Rule::selector("rules.example", "div#go", Some("data-b64")).post(PostStep::Base64)).unwrap();
+    assert_eq!(rules::extract(&service, Stage::JsLocation, html).as_deref(), Some("https://example.com/b64?x=1"));
+
+    let html = "<p>next: https%3A%2F%2Fexample.com%2Fpct</p>";
+    add_rule(
+        Rule::regex("rules.example", r"next: (\S+)</p>")
+            .post(PostStep::PercentDecode)
+            .precedence(-1),
+    )
+    .unwrap();
+    assert_eq!(rules::extract(&service, Stage::JsLocation, html).as_deref(), Some("https://example.com/pct"));
+
+    assert!(add_rule(Rule::regex("rules.example", "(")).is_err());
+    assert!(add_rule(Rule::default()).is_err());
+
+    // a rule naming a stage only runs in that stage
+    let service = Service::new("staged.example", Strategy::Redirect);
+    let html = r#"<a id="out" href="https://example.com/staged">go</a>"#;
+    add_rule(Rule::selector("staged.example", "a#out", Some("href")).stage(Stage::Api)).unwrap();
+    assert_eq!(rules::extract(&service, Stage::Api, html).as_deref(), Some("https://example.com/staged"));
+    assert_eq!(rules::extract(&service, Stage::MetaRefresh, html), None);
+    assert_eq!(rules::extract(&service, Stage::Canonical, html), None);
+
+    // `redirect` names the Redirect strategy, not the redirect stage
+    let html = r#"<p data-scoped="https://example.com/scoped">x</p>"#;
+    add_rule(Rule::regex("redirect", r#"data-scoped="([^"]+)""#)).unwrap();
+    let generic = Service::new("generic.example", Strategy::Generic);
+    assert_eq!(rules::extract(&generic, Stage::Redirect, html), None);
+    let redirect = Service::new("redirect.example", Strategy::Redirect);
+    assert_eq!(rules::extract(&redirect, Stage::MetaRefresh, html).as_deref(), Some("https://example.com/scoped"));
+    // and stage-scoped built-ins don't leak into other stages of a service
+    let html = r#"<link rel="canonical" href="https://example.com/c">"#;
+    assert_eq!(rules::extract(&redirect, Stage::Canonical, html).as_deref(), Some("https://example.com/c"));
+    assert_eq!(rules::extract(&redirect, Stage::Redirect, html), None);
+}
+
+#[test]
+fn test_html_unescape() {
+    assert_eq!(rules::html_unescape("a&amp;b&#61;c&#x2F;&lt;&unknown;"), "a&b=c/<&unknown;");
+}
+
+#[test]
+fn test_find_short_urls() {
+    let text = "See https://bit.ly/3alqLKi. Or <t.co/bYeHhy9kAU>, [docs](https://git.io/JOiM6) \
+                and (surl.li/aap)! Not: bit.ly, me@bit.ly/x, https://example.com/bit.ly/x";
+    let found = find_short_urls(text);
+    let spans = found.iter().map(|m| &text[m.start..m.end]).collect::<Vec<_>>();
+    assert_eq!(
+        spans,
+        ["https://bit.ly/3alqLKi", "t.co/bYeHhy9kAU", "https://git.io/JOiM6", "surl.li/aap"]
+    );
+    assert_eq!(found[1].url, "https://t.co/bYeHhy9kAU");
+    assert_eq!(found[1].service, "t.co");
+
+    let text = "[bit.ly/abc](https://bit.ly/abc)";
+    let spans = find_short_urls(text)
+        .iter()
+        .map(|m| &text[m.start..m.end])
+        .collect::<Vec<_>>();
+    assert_eq!(spans, ["bit.ly/abc", "https://bit.ly/abc"]);
+    assert!(find_short_urls("nothing to see here").is_empty());
+}
+
+#[test]
+fn test_refang_defang() {
+    assert_eq!(refang("hxxps://bit[.]ly/abc"), "https://bit.ly/abc");
+    assert_eq!(refang("hXXp://bit(.)ly/abc"), "http://bit.ly/abc");
+    assert_eq!(refang("https[:]//t[.]co/x"), "https://t.co/x");
+    assert_eq!(refang("hxxps[://]bit[dot]ly[/]abc"), "https://bit.ly/abc");
+    assert_eq!(refang(" bit{.}ly/hxxp "), "bit.ly/hxxp");
+    assert!(validate(&refang("hxxps://bit[.]ly/abc")).is_some());
+    assert!(validate("hxxps://bit[.]ly/abc").is_none());
+
+    assert_eq!(defang("https://bit.ly/a.b?c=d.e"), "hxxps://bit[.]ly/a.b?c=d.e");
+    assert_eq!(defang("http://user@10.0.0.1:8080/"), "hxxp://user@10[.]0[.]0[.]1:8080/");
+    assert_eq!(defang("not a url.com"), "not a url[.]com");
+}
+
+#[test]
+fn test_expansion_defanged() {
+    let options = Options::new().defang(true);
+    let expansion = Expansion::new(
+        "bit.ly/x",
+        "bit.ly",
+        "https://www.google.com/".into(),
+        vec!["https://bit.ly/x".into(), "https://www.google.com/".into()],
+        Stage::Redirect,
+        &options,
+    );
+    assert_eq!(expansion.defanged.as_deref(), Some("hxxps://www[.]google[.]com/"));
+    assert_eq!(expansion.hops[0].defanged.as_deref(), Some("hxxps://bit[.]ly/x"));
+
+    let expansion = Expansion::new("x", "bit.ly", "https://a.b/".into(), vec![], Stage::Redirect, &Options::new());
+    assert_eq!(expansion.defanged, None);
+}
+
+#[test]
+fn test_host_matching() {
+    assert_eq!(which_service("https://BIT.LY/x").unwrap().domain, "bit.ly");
+    assert_eq!(which_service("www.bit.ly/x").unwrap().domain, "bit.ly");
+    assert!(which_service("https://notbit.ly/x").is_none());
+    assert!(which_service("https://example.com/bit.ly/x").is_none());
+    // lookalike host with a Cyrillic "і"
+    assert!(which_service("https://b\u{456}t.ly/x").is_none());
+}
+
+#[test]
+fn test_idn_services() {
+    register_service(Service::new("bücher.example", Strategy::Generic));
+    register_service(Service::new("xn--mnchen-3ya.example", Strategy::Generic));
+    register_service(Service::new("➡.example", Strategy::Generic));
+
+    assert!(is_shortened("https://xn--bcher-kva.example/x"));
+    assert!(is_shortened("BÜCHER.example/x"));
+    assert!(is_shortened("https://münchen.example/x"));
+    assert!(validate("münchen.example/x").is_some());
+    assert!(is_shortened("https://➡.example/x"));
+    // both forms of a domain are the same entry
+    register_service(Service::new("XN--BCHER-KVA.example.", Strategy::Curl));
+    assert_eq!(which_service("https://bücher.example/x").unwrap().strategy, Strategy::Curl);
+    assert_eq!(which_service("https://bücher.example/x").unwrap().domain, "xn--bcher-kva.example");
+
+    let found = find_short_urls("go to münchen.example/abc now");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].url, "https://xn--mnchen-3ya.example/abc");
+
+    assert_eq!(idn::url_to_unicode("https://xn--mnchen-3ya.example/a?b=c"), "https://münchen.example/a?b=c");
+    assert_eq!(idn::url_to_ascii("https://münchen.example/a"), "https://xn--mnchen-3ya.example/a");
+    let expansion = Expansion::new(
+        "x",
+        "bit.ly",
+        "https://xn--bcher-kva.example/".into(),
+        vec!["https://bit.ly/x".into()],
+        Stage::Redirect,
+        &Options::new(),
+    );
+    assert_eq!(expansion.unicode, "https://bücher.example/");
+    assert_eq!(expansion.ascii, "https://xn--bcher-kva.example/");
+    assert_eq!(expansion.hops[0].unicode, "https://bit.ly/x");
+}
+
 // Async tests (always available)
 #[cfg(not(feature = "blocking"))]
 mod async_tests {
     use super::*;
 
+    /// Held by the tests that register their own `localhost` service.
+    static LOCALHOST: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
     #[tokio::test]
     async fn test_async_expansions() {
         for test_case in TEST_CASES {
@@ -109,6 +1073,669 @@ mod async_tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_async_hops() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Generic));
+        let base = serve(&[
+            ("/a", &found("/b")),
+            ("/b", &found("{base}/c")),
+            ("/c", &ok("<p>landed</p>")),
+        ]);
+        let options = Options::new().timeout(Duration::from_secs(5)).refang(true).defang(true);
+        let input = format!("{}/a", base.replace("http", "hxxp"));
+        let expansion = super::super::expand(&input, &options).await.unwrap();
+        assert_eq!(expansion.input, input);
+        assert_eq!(expansion.service, "localhost");
+        assert_eq!(expansion.url, format!("{base}/c"));
+        let hops = expansion.hops.iter().map(|h| h.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(hops, [format!("{base}/a"), format!("{base}/b"), format!("{base}/c")]);
+        assert!(expansion.hops[0].defanged.as_deref().unwrap().starts_with("hxxp://localhost:"));
+        assert_eq!(expansion.stage, Stage::Redirect);
+    }
+
+    #[tokio::test]
+    async fn test_async_pipeline_stages() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Generic));
+        let base = serve(&[
+            ("/off", &found("{ip}/off")),
+            ("/landed", &ok("<p>landed</p>")),
+            (
+                "/header",
+                "HTTP/1.1 200 OK\r\nRefresh: 0; URL='https://example.org/header'\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ),
+            ("/meta", &ok(r#"<meta http-equiv="refresh" content="0; URL=https://example.com/meta">"#)),
+            ("/js", &ok(r#"<script>window.location = "https://example.com/js";</script>"#)),
+            ("/og", &ok(r#"<meta property="og:url" content="https://example.net/og">"#)),
+            ("/self", &ok(r#"<link rel="canonical" href="/self">"#)),
+            ("/hidden", &found("/warn?to={ip_b64}")),
+            ("/warn?to={ip_b64}", &ok("<p>you are leaving</p>")),
+            ("/encoded", &ok(r#"<script>location.href = "aHR0cHM6Ly9leGFtcGxlLmNvbS9lbmM=";</script>"#)),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+        for (path, url, stage) in [
+            ("/off", format!("{ip}/off").as_str(), Stage::Redirect),
+            ("/header", "https://example.org/header", Stage::RefreshHeader),
+            ("/meta", "https://example.com/meta", Stage::MetaRefresh),
+            ("/js", "https://example.com/js", Stage::JsLocation),
+            ("/og", "https://example.net/og", Stage::Canonical),
+        ] {
+            let expansion = super::super::expand(&format!("{base}{path}"), &options).await.unwrap();
+            assert_eq!((expansion.url.as_str(), expansion.stage), (url, stage), "{path}");
+            let delay = matches!(stage, Stage::RefreshHeader | Stage::MetaRefresh).then_some(Duration::ZERO);
+            assert_eq!(expansion.refresh_delay, delay, "{path}");
+        }
+        // destinations hidden in an intermediate URL on the shortener
+        let expansion = super::super::expand(&format!("{base}/hidden"), &options).await.unwrap();
+        assert_eq!((expansion.url, expansion.stage), (format!("{ip}/x"), Stage::Redirect));
+        let expansion = super::super::expand(&format!("{base}/encoded"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/enc", Stage::JsLocation));
+        // a canonical URL on the shortener itself is not a destination
+        let expansion = super::super::expand(&format!("{base}/self"), &options).await.unwrap();
+        assert_eq!((expansion.url, expansion.stage), (format!("{base}/self"), Stage::Redirect));
+    }
+
+    #[tokio::test]
+    async fn test_async_meta_refresh() {
+        let _lock = LOCALHOST.lock().await;
+        let mut service = Service::new("localhost", Strategy::Refresh);
+        service.stages = Some(vec![Stage::RefreshHeader, Stage::MetaRefresh]);
+        register_service(service);
+        let meta = r#"<meta http-equiv="refresh" content="3; URL = '/final?a=1&amp;b=2'">"#;
+        let base = serve(&[
+            ("/short", &found("{ip}/page")),
+            (
+                "/page",
+                &format!(
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{meta}",
+                    meta.len()
+                ),
+            ),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+        let expansion = super::super::expand(&format!("{base}/short"), &options).await.unwrap();
+        register_service(Service::new("localhost", Strategy::Generic));
+        assert_eq!(expansion.url, format!("{ip}/final?a=1&b=2"));
+        assert_eq!(expansion.stage, Stage::MetaRefresh);
+        assert_eq!(expansion.refresh_delay, Some(Duration::from_secs(3)));
+    }
+
+    #[tokio::test]
+    async fn test_async_stage_override() {
+        let _lock = LOCALHOST.lock().await;
+        let mut service = Service::new("localhost", Strategy::Generic);
+        service.stages = Some(vec![Stage::Canonical, Stage::Redirect]);
+        register_service(service);
+        let base = serve(&[
+            (
+                "/both",
+                &ok(r#"<meta http-equiv="refresh" content="0; URL=https://example.com/meta">
+                    <link rel="canonical" href="https://example.com/canonical">"#),
+            ),
+            ("/off", &found("{ip}/off")),
+            ("/off-canonical", &found("{ip}/both")),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+        let expansion = super::super::expand(&format!("{base}/both"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/canonical", Stage::Canonical));
+        // stages run in the overridden order
+        let expansion = super::super::expand(&format!("{base}/off-canonical"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/canonical", Stage::Canonical));
+        let expansion = super::super::expand(&format!("{base}/off"), &options).await.unwrap();
+        assert_eq!((expansion.url, expansion.stage), (format!("{ip}/off"), Stage::Redirect));
+
+        // without the redirect stage, the landing page is not a destination
+        let mut service = Service::new("localhost", Strategy::Generic);
+        service.stages = Some(vec![Stage::MetaRefresh]);
+        register_service(service);
+        assert!(super::super::expand(&format!("{base}/off"), &options).await.is_err());
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_curl_like() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Fallback));
+        // answers curl with a redirect, anything else with a page; every request is recorded
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0; 8192];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let response = if request.starts_with("GET /short ") && request.contains("\r\nUser-Agent: curl/") {
+                    found(&format!("http://127.0.0.1:{port}/dest"))
+                } else {
+                    ok("<p>hello browser</p>")
+                };
+                let _ = tx.send(request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        let options = Options::new().timeout(Duration::from_secs(5));
+        let expansion = super::super::expand(&format!("http://localhost:{port}/short"), &options).await.unwrap();
+        assert_eq!(expansion.url, format!("http://127.0.0.1:{port}/dest"));
+        assert_eq!(expansion.stage, Stage::Api);
+
+        let requests = rx.try_iter().collect::<Vec<_>>();
+        // reqwest first, then curl's exact request shape
+        assert_eq!(requests.len(), 3, "{requests:?}");
+        assert!(!requests[0].contains("curl/"));
+        assert_eq!(
+            requests[1],
+            format!("GET /short HTTP/1.1\r\nHost: localhost:{port}\r\nUser-Agent: curl/8.5.0\r\nAccept: */*\r\n\r\n")
+        );
+        assert!(requests[2].starts_with(&format!("GET /dest HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\n")));
+
+        // sub-second timeouts bound the whole fallback
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = silent.local_addr().unwrap().port();
+        let options = Options::new().timeout(Duration::from_millis(300));
+        let start = std::time::Instant::now();
+        assert!(super::super::expand(&format!("http://localhost:{port}/short"), &options).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(3));
+        drop(silent);
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_preview_first() {
+        let _lock = LOCALHOST.lock().await;
+        let mut service = Service::new("localhost", Strategy::Generic);
+        service.preview = Some(Preview::Suffix("+".into()));
+        register_service(service);
+        let base = serve(&[
+            ("/abc", &found("{ip}/clicked")),
+            ("/abc+", &ok(r#"<p>Goes to <a id="redirecturl" href="https://example.com/p">example.com</a></p>"#)),
+            ("/nothing", &found("{ip}/clicked")),
+            ("/nothing+", &ok("<p>link disabled</p>")),
+            ("/forward?shorturl=abc", &ok(r#"{"data": {"long_url": "https://example.com/json"}}"#)),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+        let preview_first = options.clone().preview_first(true);
+
+        let expansion = super::super::expand(&format!("{base}/abc"), &preview_first).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/p", Stage::Preview));
+        let hops = expansion.hops.iter().map(|h| h.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(hops, [format!("{base}/abc").as_str(), "https://example.com/p"]);
+        // the link itself is only followed without the option...
+        let expansion = super::super::expand(&format!("{base}/abc"), &options).await.unwrap();
+        assert_eq!((expansion.url, expansion.stage), (format!("{ip}/clicked"), Stage::Redirect));
+        // ...or when the preview shows no destination
+        let expansion = super::super::expand(&format!("{base}/nothing"), &preview_first).await.unwrap();
+        assert_eq!((expansion.url, expansion.stage), (format!("{ip}/clicked"), Stage::Redirect));
+
+        // the preview moves first even when the service lists it later
+        let mut service = Service::new("localhost", Strategy::Generic);
+        service.preview = Some(Preview::Suffix("+".into()));
+        service.stages = Some(vec![Stage::Redirect, Stage::Preview]);
+        register_service(service);
+        let expansion = super::super::expand(&format!("{base}/abc"), &preview_first).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/p", Stage::Preview));
+
+        let mut service = Service::new("localhost", Strategy::Generic);
+        service.preview = Some(Preview::Endpoint(format!("{ip}/forward?shorturl={{id}}").into()));
+        register_service(service);
+        let expansion = super::super::expand(&format!("{base}/abc"), &preview_first).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/json", Stage::Preview));
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_official_apis() {
+        let _lock = LOCALHOST.lock().await;
+        let (base, requests) = serve_recorded(&[
+            ("/abc", &found("{ip}/clicked")),
+            (
+                "/v4/bitlinks/localhost/abc",
+                &ok(r#"{"id": "localhost/abc", "long_url": "https://example.com/bitly", "title": "Bitly link",
+                        "created_at": "2024-05-01T10:00:00+0000"}"#),
+            ),
+            (
+                "/v1/links?domain.fullName=localhost&slashtag=abc",
+                &ok(r#"[{"slashtag": "abc", "destination": "https://example.com/rebrandly", "title": "Rebrandly link",
+                         "createdAt": "2024-05-02T10:00:00.000Z"}]"#),
+            ),
+            ("/links/info?domain=localhost&key=abc", &ok(r#"{"url": "https://example.com/dub", "title": null}"#)),
+            (
+                "/alias/localhost/abc",
+                &ok(r#"{"data": {"url": "https://example.com/tinyurl", "created_at": "2024-05-04T10:00:00+00:00"}}"#),
+            ),
+            (
+                "/huge/alias/localhost/abc",
+                &ok(&format!(r#"{{"data": {{"url": "https://example.com/tinyurl"}}, "pad": "{}"}}"#, "x".repeat(2 << 20))),
+            ),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+        for (provider, url, title, created_at) in [
+            (ApiProvider::Bitly, "https://example.com/bitly", Some("Bitly link"), Some("2024-05-01T10:00:00+0000")),
+            (
+                ApiProvider::Rebrandly,
+                "https://example.com/rebrandly",
+                Some("Rebrandly link"),
+                Some("2024-05-02T10:00:00.000Z"),
+            ),
+            (ApiProvider::Dub, "https://example.com/dub", None, None),
+            (ApiProvider::TinyUrl, "https://example.com/tinyurl", None, Some("2024-05-04T10:00:00+00:00")),
+        ] {
+            let mut service = Service::new("localhost", Strategy::Generic);
+            service.api = Some(provider);
+            register_service(service);
+            // no token: expanded as usual
+            let expansion = super::super::expand(&format!("{base}/abc"), &options).await.unwrap();
+            assert_eq!((expansion.url, expansion.metadata), (format!("{ip}/clicked"), None));
+
+            let with_token = options.clone().api_token(provider, "t0k3n").api_base_url(provider, &format!("{ip}/"));
+            let expansion = super::super::expand(&format!("{base}/abc"), &with_token).await.unwrap();
+            assert_eq!((expansion.url.as_str(), expansion.stage), (url, Stage::Api), "{provider:?}");
+            let metadata = expansion.metadata.unwrap();
+            assert_eq!(
+                (metadata.provider, metadata.title.as_deref(), metadata.created_at.as_deref()),
+                (provider, title, created_at)
+            );
+            let hops = expansion.hops.iter().map(|h| h.url.as_str()).collect::<Vec<_>>();
+            assert_eq!(hops, [format!("{base}/abc").as_str(), url]);
+
+            let api_request = requests.try_iter().last().unwrap();
+            let auth = match provider {
+                ApiProvider::Rebrandly => "\r\napikey: t0k3n\r\n",
+                _ => "\r\nauthorization: Bearer t0k3n\r\n",
+            };
+            assert!(api_request.contains(auth), "{api_request}");
+        }
+
+        // an API that doesn't know the link falls back to the usual expansion
+        let with_token = options
+            .clone()
+            .api_token(ApiProvider::TinyUrl, "t")
+            .api_base_url(ApiProvider::TinyUrl, &format!("{ip}/gone"));
+        let expansion = super::super::expand(&format!("{base}/abc"), &with_token).await.unwrap();
+        assert_eq!(
+            (expansion.url, expansion.stage, expansion.metadata),
+            (format!("{ip}/clicked"), Stage::Redirect, None)
+        );
+
+        // so does an answer past the body limit
+        let with_token = options
+            .clone()
+            .api_token(ApiProvider::TinyUrl, "t")
+            .api_base_url(ApiProvider::TinyUrl, &format!("{ip}/huge"));
+        let expansion = super::super::expand(&format!("{base}/abc"), &with_token).await.unwrap();
+        assert_eq!((expansion.url, expansion.stage), (format!("{ip}/clicked"), Stage::Redirect));
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_redirect_strategy() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Redirect));
+        let (base, requests) = serve_recorded(&[
+            ("/abc", &ok(r#"<script>location.href = "https://example.com/r";</script>"#)),
+            ("/none", &ok("<p>nothing</p>")),
+        ]);
+        let options = Options::new().timeout(Duration::from_secs(5));
+
+        // the page is requested once, for the resolver and the page stages
+        let expansion = super::super::expand(&format!("{base}/abc"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("https://example.com/r", Stage::Api));
+        assert_eq!(requests.try_iter().count(), 1);
+        assert!(matches!(super::super::expand(&format!("{base}/none"), &options).await, Err(Error::NoString)));
+        assert_eq!(requests.try_iter().count(), 1);
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_self_hosted() {
+        let _lock = LOCALHOST.lock().await;
+        let (base, requests) = serve_recorded(&[
+            ("/abc", &found("{ip}/clicked")),
+            ("/s/abc", &found("{ip}/clicked")),
+            (
+                "/s/yourls-api.php?action=expand&shorturl=abc&format=json",
+                &ok(r#"{"keyword": "abc", "message": "success", "longurl": "https://example.com/yourls"}"#),
+            ),
+            (
+                "/s/yourls-api.php?action=expand&shorturl=abc&format=json&signature=k3y",
+                &ok(r#"{"keyword": "abc", "longurl": "https://example.com/yourls"}"#),
+            ),
+            (
+                "/rest/v3/short-urls/abc?domain=localhost",
+                &ok(r#"{"shortCode": "abc", "longUrl": "https://example.com/shlink"}"#),
+            ),
+            (
+                "/api/v2/links?search=abc&limit=50",
+                &ok(r#"{"data": [{"address": "abcd", "target": "https://example.com/other"},
+                                 {"address": "abc", "target": "https://example.com/kutt"}]}"#),
+            ),
+            (
+                "/api/v2/action/lookup?key=k3y&url_ending=abc&response_type=json",
+                &ok(r#"{"action": "lookup", "result": {"long_url": "https://example.com/polr"}}"#),
+            ),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+        let with_key = options.clone().instance_key("localhost", "k3y");
+        for (strategy, path, url, key_header) in [
+            (Strategy::Yourls, "/s/abc", "https://example.com/yourls", None),
+            (Strategy::Shlink, "/abc", "https://example.com/shlink", Some("\r\nx-api-key: k3y\r\n")),
+            (Strategy::Kutt, "/abc", "https://example.com/kutt", Some("\r\nx-api-key: k3y\r\n")),
+            (Strategy::Polr, "/abc", "https://example.com/polr", None),
+        ] {
+            register_service(Service::new("localhost", strategy));
+            let expansion = super::super::expand(&format!("{base}{path}"), &with_key).await.unwrap();
+            assert_eq!((expansion.url.as_str(), expansion.stage), (url, Stage::Api), "{strategy:?}");
+            if let Some(header) = key_header {
+                assert!(requests.try_iter().any(|r| r.contains(header)), "{strategy:?}");
+            }
+            // without a key, only YOURLS has an API; the others follow the link
+            let expansion = super::super::expand(&format!("{base}{path}"), &options).await.unwrap();
+            let expected = match strategy {
+                Strategy::Yourls => (url.to_string(), Stage::Api),
+                _ => (format!("{ip}/clicked"), Stage::Redirect),
+            };
+            assert_eq!((expansion.url, expansion.stage), expected, "{strategy:?}");
+        }
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_detect_platforms() {
+        let _lock = LOCALHOST.lock().await;
+        let health = r#"{"status": "pass", "version": "4.1.0", "links": {"about": "https://shlink.io"}}"#;
+        let shlink = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/health+json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{health}",
+            health.len()
+        );
+        let private = r#"{"errorCode": 403, "message": "Please log in", "callback": ""}"#;
+        let yourls = format!(
+            "HTTP/1.1 403 Forbidden\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{private}",
+            private.len()
+        );
+        for (routes, expected) in [
+            (vec![("/rest/health", shlink.as_str())], Some(Strategy::Shlink)),
+            (vec![("/s/yourls-api.php?action=version&format=json", yourls.as_str())], Some(Strategy::Yourls)),
+            (
+                vec![("/", &ok(r#"<footer><a href="https://github.com/thedevs-network/kutt">Kutt</a></footer>"#))],
+                Some(Strategy::Kutt),
+            ),
+            (vec![("/", &ok("<footer>Powered by Polr 2</footer>"))], Some(Strategy::Polr)),
+            (vec![("/", &ok("<p>just a blog</p>"))], None),
+        ] {
+            let base = serve(&routes);
+            let url = url::Url::parse(&format!("{base}/s/abc")).unwrap();
+            let found = super::super::resolvers::detect::platform(&url, Some(Duration::from_secs(5))).await;
+            assert_eq!(found, expected, "{routes:?}");
+        }
+
+        // instances found are used for the expansion at hand, not registered
+        register_service(Service::new("localhost", Strategy::Generic));
+        let base = serve(&[("/", &ok("<footer>Powered by Polr 2</footer>"))]);
+        let options = Options::new().timeout(Duration::from_secs(5));
+        assert!(super::super::resolvers::detect::detect(&base, &options).await.is_none());
+        let (url, service) = super::super::resolvers::detect::detect(&format!("{base}/abc"), &options).await.unwrap();
+        assert_eq!((url, service.strategy), (format!("{base}/abc"), Strategy::Polr));
+        assert_eq!(which_service(&format!("{base}/abc")).unwrap().strategy, Strategy::Generic);
+    }
+
+    #[tokio::test]
+    async fn test_async_unwrap() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Generic));
+        let base = serve(&[("/abc", &found("{ip}/dest"))]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+
+        // a wrapped short link is expanded, the wrapper being the first hop
+        let short = format!("{base}/abc");
+        let wrapped = format!(
+            "https://nam02.safelinks.protection.outlook.com/?url={}&data=05",
+            percent_encoding::utf8_percent_encode(&short, percent_encoding::NON_ALPHANUMERIC)
+        );
+        let expansion = super::super::expand(&wrapped, &options).await.unwrap();
+        assert_eq!((expansion.service.as_str(), expansion.url.clone()), ("localhost", format!("{ip}/dest")));
+        let hops = expansion.hops.iter().map(|h| h.url.clone()).collect::<Vec<_>>();
+        assert_eq!(hops, [wrapped, short, format!("{ip}/dest")]);
+
+        // any other wrapped link is the destination itself, without a request
+        let wrapped = "https://secure-web.cisco.com/1AbC/https%3A%2F%2Fexample.com%2Fpath";
+        let expansion = super::super::expand(wrapped, &options).await.unwrap();
+        assert_eq!(
+            (expansion.service.as_str(), expansion.url.as_str(), expansion.stage),
+            ("secure-web.cisco.com", "https://example.com/path", Stage::Unwrap)
+        );
+        let hops = expansion.hops.iter().map(|h| h.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(hops, [wrapped, "https://example.com/path"]);
+        assert!(super::super::expand("https://example.com/path", &options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_redirectors() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Generic));
+        let base = serve(&[
+            // refreshes, whose targets aren't requested
+            (
+                "/abc",
+                "HTTP/1.1 200 OK\r\nRefresh: 0; URL={ip}/out?redirect={base}/def\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ),
+            (
+                "/def",
+                "HTTP/1.1 200 OK\r\nRefresh: 0; URL={ip}/end?to=https://example.org/end\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            ),
+            ("/login", &found("{ip}/login?redirect=https://example.org/home")),
+            ("/login?redirect=https://example.org/home", &ok("<p>sign in</p>")),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5));
+
+        // the destination is unwrapped, and the short link it carries expanded
+        let short = format!("{base}/abc");
+        let expansion = super::super::expand(&short, &options).await.unwrap();
+        assert_eq!(
+            (expansion.service.as_str(), expansion.url.as_str(), expansion.stage),
+            ("localhost", "https://example.org/end", Stage::Unwrap)
+        );
+        let hops = expansion.hops.iter().map(|h| h.url.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            hops,
+            [
+                short.clone(),
+                format!("{ip}/out?redirect={base}/def"),
+                format!("{base}/def"),
+                format!("{ip}/end?to=https://example.org/end"),
+                "https://example.org/end".into(),
+            ]
+        );
+
+        // a redirector around a short link is unwrapped before expanding
+        let wrapped = format!("https://l.facebook.com/l.php?u={base}/def&h=AT0");
+        let expansion = super::super::expand(&wrapped, &options).await.unwrap();
+        assert_eq!((expansion.service.as_str(), expansion.url.as_str()), ("localhost", "https://example.org/end"));
+        assert_eq!(expansion.hops[0].url, wrapped);
+
+        // without the heuristic, only the known redirectors are unwrapped
+        let expansion = super::super::expand(&short, &options.clone().unwrap_parameters(false)).await.unwrap();
+        assert_eq!(expansion.url, format!("{ip}/out?redirect={base}/def"));
+        assert_ne!(expansion.stage, Stage::Unwrap);
+
+        // a destination that served its own page keeps its parameters
+        let expansion = super::super::expand(&format!("{base}/login"), &options).await.unwrap();
+        assert_eq!(
+            (expansion.url.as_str(), expansion.stage),
+            (format!("{ip}/login?redirect=https://example.org/home").as_str(), Stage::Redirect)
+        );
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_offline() {
+        let options = Options::new().offline(true);
+
+        // deterministic links and wrappers need no request
+        let expansion = super::super::expand("https://youtu.be/dQw4w9WgXcQ", &options).await.unwrap();
+        assert_eq!(
+            (expansion.service.as_str(), expansion.url.as_str(), expansion.stage),
+            ("youtu.be", "https://www.youtube.com/watch?v=dQw4w9WgXcQ", Stage::Rewrite)
+        );
+        let hops = expansion.hops.iter().map(|h| h.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(hops, ["https://youtu.be/dQw4w9WgXcQ", "https://www.youtube.com/watch?v=dQw4w9WgXcQ"]);
+        let wrapped = "https://www.google.com/url?q=https://redd.it/1abcxyz";
+        let expansion = super::super::expand(wrapped, &options).await.unwrap();
+        assert_eq!(expansion.url, "https://www.reddit.com/comments/1abcxyz");
+
+        // the other links would need one
+        assert_eq!(super::super::expand("https://bit.ly/3alqLKi", &options).await.unwrap_err(), Error::Offline);
+        assert_eq!(super::super::expand("https://wa.me/message/ABC", &options).await.unwrap_err(), Error::Offline);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_async_expand_from_response() {
+        // captured pages need no runtime, and don't block the one they are called from
+        let body = r#"<script>location.href = "https://example.com/rt";</script>"#;
+        let expansion = expand_from_response("tinyurl.com", "https://tinyurl.com/abc", 200, &[], body).unwrap();
+        assert_eq!(expansion.url, "https://example.com/rt");
+    }
+
+    #[tokio::test]
+    async fn test_async_affiliates() {
+        let wrapped = "https://www.google.com/url?q=https%3A%2F%2Fwww.amazon.com%2Fdp%2FB08N5WRWNW%3Ftag%3Ddeals-20%26th%3D1";
+        let options = Options::new().offline(true);
+        let expansion = super::super::expand(wrapped, &options).await.unwrap();
+        assert_eq!(expansion.url, "https://www.amazon.com/dp/B08N5WRWNW?tag=deals-20&th=1");
+        let affiliates = expansion.affiliates.iter().map(|a| (a.program, a.tag.as_str())).collect::<Vec<_>>();
+        assert_eq!(affiliates, [(AffiliateProgram::Amazon, "deals-20")]);
+        assert_eq!(expansion.canonical, "https://www.amazon.com/dp/B08N5WRWNW");
+        let product = expansion.amazon.unwrap();
+        assert_eq!((product.marketplace.as_str(), product.asin.as_str()), ("amazon.com", "B08N5WRWNW"));
+
+        // stripped from the destination, still reported and kept in the hops
+        let expansion = super::super::expand(wrapped, &options.strip_affiliates(true)).await.unwrap();
+        assert_eq!(expansion.url, "https://www.amazon.com/dp/B08N5WRWNW?th=1");
+        assert_eq!(expansion.affiliates.len(), 1);
+        assert!(expansion.hops.last().unwrap().url.contains("tag=deals-20"));
+    }
+
+    #[tokio::test]
+    async fn test_async_deep_links() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Generic));
+        let intent = "intent://scan/#Intent;scheme=zxing;package=com.google.zxing.client.android;S.browser_fallback_url=http%3A%2F%2Fzxing.org;end";
+        let base = serve(&[("/abc", &found(intent)), ("/app", &found("intent://open#Intent;scheme=myapp;end"))]);
+        let options = Options::new().timeout(Duration::from_secs(5));
+
+        // an intent the redirect leads to: its web fallback is the destination
+        let expansion = super::super::expand(&format!("{base}/abc"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("http://zxing.org/", Stage::Unwrap));
+        let hops = expansion.hops.iter().map(|h| h.url.clone()).collect::<Vec<_>>();
+        assert_eq!(hops, [format!("{base}/abc"), intent.into(), "http://zxing.org/".into()]);
+        let android = expansion.deep_link.unwrap().android.unwrap();
+        assert_eq!(android.package.as_deref(), Some("com.google.zxing.client.android"));
+
+        // without one, the intent itself
+        let expansion = super::super::expand(&format!("{base}/app"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("intent://open#Intent;scheme=myapp;end", Stage::Redirect));
+        assert_eq!(expansion.deep_link.unwrap().web, None);
+
+        // long links are read offline
+        let firebase = "https://example.page.link/?link=https://example.com/item&apn=com.example.app";
+        let expansion = super::super::expand(firebase, &options.clone().offline(true)).await.unwrap();
+        assert_eq!((expansion.service.as_str(), expansion.url.as_str()), ("example.page.link", "https://example.com/item"));
+        assert_eq!(expansion.deep_link.unwrap().platform, DeepLinkPlatform::Firebase);
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_non_http_destinations() {
+        let _lock = LOCALHOST.lock().await;
+        let base = serve(&[
+            ("/tel", &found("tel:+44-20-7946-0958")),
+            ("/mail", &found("{ip}/mail2")),
+            ("/mail2", &found("mailto:help@example.com?subject=Hi")),
+            ("/store", &ok("<meta http-equiv=\"refresh\" content=\"0;url=market://details?id=com.example.app\">")),
+            ("/js", &found("javascript:alert(1)")),
+        ]);
+        let options = Options::new().timeout(Duration::from_secs(5));
+
+        // redirects and refreshes to non-HTTP targets end the expansion
+        for strategy in [Strategy::Generic, Strategy::Redirect, Strategy::Fallback] {
+            register_service(Service::new("localhost", strategy));
+            let expansion = super::super::expand(&format!("{base}/tel"), &options).await.unwrap();
+            assert_eq!(expansion.url, "tel:+44-20-7946-0958", "{strategy:?}");
+            assert_eq!(expansion.destination, Destination::Phone { number: "+442079460958".into() });
+        }
+        register_service(Service::new("localhost", Strategy::Generic));
+        let expansion = super::super::expand(&format!("{base}/mail"), &options).await.unwrap();
+        assert_eq!((expansion.url.as_str(), expansion.stage), ("mailto:help@example.com?subject=Hi", Stage::Redirect));
+        let Destination::Email { to, subject, .. } = expansion.destination else {
+            panic!("{:?}", expansion.destination);
+        };
+        assert_eq!((to, subject.as_deref()), (vec!["help@example.com".to_string()], Some("Hi")));
+        let expansion = super::super::expand(&format!("{base}/store"), &options).await.unwrap();
+        assert_eq!(
+            (expansion.destination, expansion.stage),
+            (Destination::AppStore { store: Store::GooglePlay, app_id: "com.example.app".into() }, Stage::MetaRefresh)
+        );
+        // other schemes stop the redirects without being a destination
+        let expansion = super::super::expand(&format!("{base}/js"), &options).await.unwrap();
+        assert_eq!((expansion.url, expansion.destination.is_web()), (format!("{base}/js"), true));
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
+    #[tokio::test]
+    async fn test_async_link_pages() {
+        let _lock = LOCALHOST.lock().await;
+        register_service(Service::new("localhost", Strategy::Generic));
+        let bio = ok(r#"<a data-testid="LinkButton" href="https://shop.example.com/">Shop</a>
+            <a data-testid="LinkButton" href="https://blog.example.com/">Blog</a>"#);
+        let plain = ok(r#"<a href="https://shop.example.com/">Shop</a>"#);
+        let large = ok(&format!(
+            r#"<a data-testid="LinkButton" href="https://shop.example.com/">Shop</a>{}
+            <a data-testid="LinkButton" href="https://late.example.com/">Late</a>"#,
+            " ".repeat(2 << 20)
+        ));
+        let base = serve(&[
+            ("/abc", &found("{ip}/bio")),
+            ("/bio", &bio),
+            ("/def", &found("{ip}/plain")),
+            ("/plain", &plain),
+            ("/ghi", &found("{ip}/large")),
+            ("/large", &large),
+        ]);
+        let ip = base.replace("localhost", "127.0.0.1");
+        let options = Options::new().timeout(Duration::from_secs(5)).link_pages(true);
+
+        // a link-in-bio page on a custom domain, recognised by its markup
+        let expansion = super::super::expand(&format!("{base}/abc"), &options).await.unwrap();
+        assert_eq!(expansion.url, format!("{ip}/bio"));
+        assert_eq!(expansion.canonical, expansion.url);
+        let links = expansion.links.iter().map(|l| (l.position, l.url.as_str(), l.label.as_deref())).collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [(0, "https://shop.example.com/", Some("Shop")), (1, "https://blog.example.com/", Some("Blog"))]
+        );
+
+        // only the start of a large page is read
+        let expansion = super::super::expand(&format!("{base}/ghi"), &options).await.unwrap();
+        let links = expansion.links.iter().map(|l| l.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(links, ["https://shop.example.com/"]);
+
+        // other pages, and expansions without the mode, have none
+        let expansion = super::super::expand(&format!("{base}/def"), &options).await.unwrap();
+        assert!(expansion.links.is_empty());
+        let options = Options::new().timeout(Duration::from_secs(5));
+        let expansion = super::super::expand(&format!("{base}/abc"), &options).await.unwrap();
+        assert!(expansion.links.is_empty());
+        register_service(Service::new("localhost", Strategy::Generic));
+    }
+
     #[tokio::test]
     async fn test_async_with_timeout() {
         let result = unshorten("https://bit.ly/3alqLKi", Some(Duration::from_secs(5))).await;
@@ -195,3 +1822,104 @@ mod error_tests {
         assert!(result.is_err());
     }
 }
+
+// Script sandbox tests
+#[cfg(feature = "js")]
+mod sandbox_tests {
+    use super::super::{run_scripts, SandboxLimits};
+    use super::*;
+
+    async fn navigations(html: &str, limits: &SandboxLimits) -> Vec<(String, JsSink)> {
+        run_scripts(html, "https://sho.rt/abc", limits)
+            .await
+            .into_iter()
+            .map(|n| (n.url, n.sink))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_navigations() {
+        let limits = SandboxLimits::default();
+        let html = r#"<script>var p = ["com", "example", "https://"]; location = p[2] + p[1] + "." + p[0] + "/x";</script>"#;
+        assert_eq!(navigations(html, &limits).await, [("https://example.com/x".into(), JsSink::Location)]);
+
+        // eval'd, base64-encoded, in a timer and relative to the page
+        let html = r#"<script>
+            eval(atob("d2luZG93LmxvY2F0aW9uLnJlcGxhY2UoIi9uZXh0IHBhZ2UiKQ=="));
+            setTimeout(function () { window.open("https://popup.example/" + location.hostname); }, 5000);
+            document.addEventListener("DOMContentLoaded", function () {
+                var a = document.createElement("a"); a.href = "https://example.com/click"; a.click();
+            });
+        </script>"#;
+        assert_eq!(
+            navigations(html, &limits).await,
+            [
+                ("https://sho.rt/next%20page".into(), JsSink::Replace),
+                ("https://popup.example/sho.rt".into(), JsSink::Open),
+                ("https://example.com/click".into(), JsSink::Location),
+            ]
+        );
+
+        // no network, external and non-JS scripts are skipped, errors don't stop the page
+        let html = r#"<script src="https://cdn.example/app.js"></script>
+            <script type="text/template">location = "/template"</script>
+            <script>fetch("http://127.0.0.1:1/"); new XMLHttpRequest().send(); undefinedCall();</script>
+            <body onload="top.location.href = '/loaded'"></body>"#;
+        assert_eq!(navigations(html, &limits).await, [("https://sho.rt/loaded".into(), JsSink::Location)]);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_limits() {
+        // a runaway loop hits the loop limit and the next script still runs
+        let html = r#"<script>while (true) {}</script><script>location.href = "/after";</script>"#;
+        let limits = SandboxLimits { loop_iterations: 10_000, ..Default::default() };
+        assert_eq!(navigations(html, &limits).await, [("https://sho.rt/after".into(), JsSink::Location)]);
+
+        // runaway recursion
+        let html = r#"<script>function f() { return f(); } f();</script><script>location = "/r";</script>"#;
+        assert_eq!(navigations(html, &limits).await, [("https://sho.rt/r".into(), JsSink::Location)]);
+
+        // past the deadline, nothing is reported
+        let html = r#"<script>for (var i = 0; i < 1e9; i++) {} location = "/late";</script>"#;
+        let limits = SandboxLimits {
+            time: Duration::from_millis(100),
+            loop_iterations: 50_000_000,
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+        assert!(navigations(html, &limits).await.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // the engine stops at the deadline, whatever each loop's limit
+        let html = r#"<script>
+            function spin() { for (var i = 0; i < 1e6; i++) {} }
+            for (var j = 0; j < 1e6; j++) spin();
+            location = "/late";
+        </script>"#;
+        let limits = SandboxLimits { time: Duration::from_millis(100), ..Default::default() };
+        let started = std::time::Instant::now();
+        assert!(navigations(html, &limits).await.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // the caller stops waiting for a callback the engine can't interrupt
+        let html = r#"<script>
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10].forEach(function () {
+                for (var j = 0; j < 1e3; j++) { for (var i = 0; i < 1e6; i++) {} }
+            });
+            location = "/late";
+        </script>"#;
+        let started = std::time::Instant::now();
+        assert!(navigations(html, &limits).await.is_empty());
+        assert!(super::super::sandbox::run_scripts_sync(html, "https://sho.rt/abc", &limits).is_empty());
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        // built-ins don't produce or walk huge strings and arrays
+        let html = r#"<script>location = "a".repeat(Math.pow(2, 30));</script>
+            <script>location = "b".padEnd(1e9);</script>
+            <script>location = new Array(1e9).join("c");</script>
+            <script>location = Array.from({ length: 1e9 }).length;</script>
+            <script>location = "/" + "d".repeat(3) + [1, 2].join("-");</script>"#;
+        let limits = SandboxLimits::default();
+        assert_eq!(navigations(html, &limits).await, [("https://sho.rt/ddd1-2".into(), JsSink::Location)]);
+    }
+}
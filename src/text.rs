@@ -0,0 +1,114 @@
+//! Short URL detection in free text.
+//!
+//! Chat messages, tickets and email bodies rarely contain clean URLs. This
+//! module finds the short links in such text, with:
+//!
+//! - URLs with or without a scheme (`https://bit.ly/x`, `bit.ly/x`)
+//! - trailing punctuation (`see bit.ly/x.` → `bit.ly/x`)
+//! - angle brackets (`<https://bit.ly/x>`)
+//! - markdown links (`[label](https://bit.ly/x)`)
+//!
+//! A candidate counts as a short link when its host belongs to a known
+//! shortener (see [`which_service`]) and it has a non-empty path, so that a bare
+//! mention of `bit.ly` in a sentence is not reported. Links rewritten by email
+//! security gateways count when they wrap a short link (see [`unwrap_link`](crate::unwrap_link)).
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::{services::which_service, unwrap::unwrap_chain, validate};
+
+/// Anything that looks like a host name (Unicode and emoji labels included),
+/// optionally with a scheme and a path.
+static RE_CANDIDATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)(?:https?://)?[\p{L}\p{N}\p{So}](?:[\p{L}\p{N}\p{So}-]*[\p{L}\p{N}\p{So}])?(?:\.[\p{L}\p{N}\p{So}](?:[\p{L}\p{N}\p{So}-]*[\p{L}\p{N}\p{So}])?)+(?::\d+)?(?:/[^\s<>\x22'`\[\]]*)?",
+    )
+    .expect("valid candidate regex")
+});
+
+/// A short URL found in a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Byte offset of the first character, `text[start..end]` is the link as written
+    pub start: usize,
+    /// Byte offset past the last character
+    pub end: usize,
+    /// The normalized URL (with a scheme) that is expanded, wrapper included
+    pub url: String,
+    /// Main domain of the shortener service (of the wrapped link, for a wrapper)
+    pub service: String,
+}
+
+pub fn find_short_urls(text: &str) -> Vec<Match> {
+    //! Finds every short URL in a text.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::find_short_urls;
+    //!
+    //! let text = "see <https://bit.ly/3alqLKi> or [this](surl.li/aap).";
+    //! let found = find_short_urls(text);
+    //! assert_eq!(found.len(), 2);
+    //! assert_eq!(&text[found[1].start..found[1].end], "surl.li/aap");
+    //! assert_eq!(found[1].url, "https://surl.li/aap");
+    //! ```
+    RE_CANDIDATE
+        .find_iter(text)
+        .filter(|m| {
+            // skip candidates glued to a word, e.g. the domain of an email address
+            text[..m.start()]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !(c.is_alphanumeric() || "@.-_/\\".contains(c)))
+        })
+        .filter_map(|m| {
+            let end = m.start() + trim_trailing(m.as_str()).len();
+            let candidate = &text[m.start()..end];
+            let (url, short) = match validate(candidate) {
+                Some(url) => (url.clone(), url),
+                None => (wrapper(candidate)?, validate(&unwrap_chain(candidate, true).pop()?)?),
+            };
+            let path = url::Url::parse(&short).ok()?.path().trim_matches('/').len();
+            (path > 0).then_some(())?;
+            let service = which_service(&short)?.domain;
+            Some(Match {
+                start: m.start(),
+                end,
+                url,
+                service,
+            })
+        })
+        .collect()
+}
+
+fn wrapper(candidate: &str) -> Option<String> {
+    //! A wrapped link candidate, normalized with a scheme.
+    let url = url::Url::parse(candidate)
+        .ok()
+        .filter(|u| u.has_host())
+        .or_else(|| url::Url::parse(&format!("https://{candidate}")).ok())?;
+    Some(url.into())
+}
+
+fn trim_trailing(candidate: &str) -> &str {
+    //! Strips sentence punctuation and unbalanced closing brackets from the
+    //! end of a candidate.
+    let mut s = candidate;
+    loop {
+        let Some(last) = s.chars().next_back() else {
+            return s;
+        };
+        let unbalanced = |open: char, close: char| s.matches(close).count() > s.matches(open).count();
+        let trim = match last {
+            '.' | ',' | ';' | ':' | '!' | '?' | '*' | '_' | '~' => true,
+            ')' => unbalanced('(', ')'),
+            ']' => unbalanced('[', ']'),
+            '}' => unbalanced('{', '}'),
+            _ => false,
+        };
+        if !trim {
+            return s;
+        }
+        s = &s[..s.len() - last.len_utf8()];
+    }
+}
@@ -0,0 +1,285 @@
+//! Offline unwrapping of links rewritten by email security gateways and
+//! redirectors.
+//!
+//! Mail filters rewrite every link of a message to go through their own
+//! scanner first, and many sites send outbound clicks through a redirector.
+//! Most of them carry the original link in the rewritten one, so it is
+//! recovered here without any request:
+//!
+//! - Proofpoint URL Defense v1 (`urldefense.proofpoint.com/v1/url?u=...`),
+//!   v2 (`/v2/url?u=https-3A__...`) and v3 (`urldefense.com/v3/__https://...__;...!!...$`)
+//! - Microsoft Safe Links (`*.safelinks.protection.outlook.com/?url=...`)
+//! - Barracuda Link Protection (`linkprotect.cudasvc.com/url?a=...`)
+//! - Cisco Secure Email (`secure-web.cisco.com/<token>/<percent-encoded URL>`)
+//! - known redirectors (`google.com/url?q=`, `l.facebook.com/l.php?u=`,
+//!   `youtube.com/redirect?q=`, `slack-redir.net/link?url=`, ...)
+//! - any link with a URL to another site in a `redirect`, `to`, `dest`, ... parameter (the
+//!   heuristic can be turned off with [`Options::unwrap_parameters`](crate::Options::unwrap_parameters))
+//!
+//! Mimecast links (`protect-eu.mimecast.com/s/...?domain=...`) only carry the
+//! domain of the original link: they are services, expanded by following them.
+//!
+//! [`expand`](crate::expand) unwraps links before looking for a shortener and
+//! again once expanded, and records every layer as a hop.
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use std::sync::LazyLock;
+use url::{ParseError, Url};
+
+use crate::{
+    decode::{decode_base64, decode_percent},
+    idn::host_matches,
+};
+
+static RE_PROOFPOINT_V3: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"/v3/__(.+?)__;([A-Za-z0-9_-]*)!").expect("valid proofpoint regex"));
+static RE_SINGLE_SLASH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^([a-z0-9+.-]+:/)([^/].+)").expect("valid scheme regex"));
+
+/// Run lengths of the Proofpoint v3 `**<c>` tokens, from 2.
+static V3_RUNS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Redirectors carrying the destination in a query parameter: domain (with
+/// its subdomains; `google.*` for the [`GOOGLE`] domains), path prefix and
+/// parameters.
+static REDIRECTORS: &[(&str, &str, &[&str])] = &[
+    ("google.*", "/url", &["q", "url"]),
+    ("l.facebook.com", "/l.php", &["u"]),
+    ("lm.facebook.com", "/l.php", &["u"]),
+    ("l.messenger.com", "/l.php", &["u"]),
+    ("l.instagram.com", "/", &["u"]),
+    ("youtube.com", "/redirect", &["q"]),
+    ("slack-redir.net", "/link", &["url"]),
+    ("t.umblr.com", "/redirect", &["z"]),
+    ("vk.com", "/away.php", &["to"]),
+    ("steamcommunity.com", "/linkfilter/", &["u", "url"]),
+    ("linkedin.com", "/redir/redirect", &["url"]),
+    ("duckduckgo.com", "/l/", &["uddg"]),
+    ("getpocket.com", "/redirect", &["url"]),
+    ("deref-gmx.net", "/mail/client/", &["redirectUrl"]),
+    ("deref-web.de", "/mail/client/", &["redirectUrl"]),
+];
+
+/// The top-level domains of the Google search domains, `google.<tld>`.
+static GOOGLE: &[&str] = &[
+    "com", "ad", "ae", "al", "am", "as", "at", "az", "ba", "be", "bf", "bg", "bi", "bj", "bs", "bt", "by", "ca",
+    "cat", "cd", "cf", "cg", "ch", "ci", "cl", "cm", "cn", "cv", "cz", "de", "dj", "dk", "dm", "dz", "ee", "es",
+    "fi", "fm", "fr", "ga", "ge", "gg", "gl", "gm", "gr", "gy", "hn", "hr", "ht", "hu", "ie", "im", "iq", "is",
+    "it", "je", "jo", "kg", "ki", "kz", "la", "li", "lk", "lt", "lu", "lv", "md", "me", "mg", "mk", "ml", "mn",
+    "mu", "mv", "mw", "ne", "nl", "no", "nr", "nu", "pl", "pn", "ps", "pt", "ro", "rs", "ru", "rw", "sc", "se",
+    "sh", "si", "sk", "sm", "sn", "so", "sr", "st", "td", "tg", "tl", "tm", "tn", "to", "tt", "vu", "ws",
+    "co.ao", "co.bw", "co.ck", "co.cr", "co.id", "co.il", "co.in", "co.jp", "co.ke", "co.kr", "co.ls", "co.ma",
+    "co.mz", "co.nz", "co.th", "co.tz", "co.ug", "co.uk", "co.uz", "co.ve", "co.vi", "co.za", "co.zm", "co.zw",
+    "com.af", "com.ag", "com.ar", "com.au", "com.bd", "com.bh", "com.bn", "com.bo", "com.br", "com.bz", "com.co",
+    "com.cu", "com.cy", "com.do", "com.ec", "com.eg", "com.et", "com.fj", "com.gh", "com.gi", "com.gt", "com.hk",
+    "com.jm", "com.kh", "com.kw", "com.lb", "com.ly", "com.mm", "com.mt", "com.mx", "com.my", "com.na", "com.ng",
+    "com.ni", "com.np", "com.om", "com.pa", "com.pe", "com.pg", "com.ph", "com.pk", "com.pr", "com.py", "com.qa",
+    "com.sa", "com.sb", "com.sg", "com.sl", "com.sv", "com.tj", "com.tr", "com.tw", "com.ua", "com.uy", "com.vc",
+    "com.vn",
+];
+
+/// Query parameters that usually hold the destination of a redirect, for the
+/// heuristic; compared case-insensitively.
+static PARAMETERS: &[&str] = &[
+    "redirect",
+    "redirect_url",
+    "redirecturl",
+    "redirect_to",
+    "redir",
+    "to",
+    "dest",
+    "destination",
+    "goto",
+    "target",
+    "out",
+];
+
+/// Most layers [`unwrap_chain`] removes.
+const MAX_LAYERS: usize = 8;
+
+/// A link wrapper that [`unwrap_link`] can remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Wrapper {
+    /// Proofpoint URL Defense, v1 encoding
+    ProofpointV1,
+    /// Proofpoint URL Defense, v2 encoding
+    ProofpointV2,
+    /// Proofpoint URL Defense, v3 encoding
+    ProofpointV3,
+    /// Microsoft Defender for Office 365 Safe Links
+    SafeLinks,
+    /// Barracuda Link Protection
+    Barracuda,
+    /// Cisco Secure Email URL rewriting
+    Cisco,
+    /// A known redirector (`google.com/url?q=...`, `l.facebook.com/l.php?u=...`, ...)
+    Redirector,
+    /// A URL in a `redirect`, `to`, `dest`, ... query parameter
+    Parameter,
+}
+
+impl Wrapper {
+    pub fn name(&self) -> &'static str {
+        //! A short name of the wrapper.
+        match self {
+            Self::ProofpointV1 => "proofpoint_v1",
+            Self::ProofpointV2 => "proofpoint_v2",
+            Self::ProofpointV3 => "proofpoint_v3",
+            Self::SafeLinks => "safelinks",
+            Self::Barracuda => "barracuda",
+            Self::Cisco => "cisco",
+            Self::Redirector => "redirector",
+            Self::Parameter => "parameter",
+        }
+    }
+}
+
+pub fn unwrap_link(url: &str) -> Option<(Wrapper, String)> {
+    //! Removes one wrapper or redirector from a link.
+    //!
+    //! # Arguments
+    //!
+    //! * `url` - The link, with or without a scheme
+    //!
+    //! # Returns
+    //!
+    //! Returns the wrapper and the `http(s)` link it carries, or `None` when
+    //! the link isn't wrapped or the wrapper can't be decoded.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{unwrap_link, Wrapper};
+    //!
+    //! let url = "https://urldefense.proofpoint.com/v2/url?u=https-3A__bit.ly_3alqLKi&d=DwMF&c=x";
+    //! assert_eq!(unwrap_link(url), Some((Wrapper::ProofpointV2, "https://bit.ly/3alqLKi".into())));
+    //!
+    //! let url = "https://www.google.com/url?sa=t&url=https%3A%2F%2Fexample.com%2F";
+    //! assert_eq!(unwrap_link(url), Some((Wrapper::Redirector, "https://example.com/".into())));
+    //! assert_eq!(unwrap_link("https://bit.ly/3alqLKi"), None);
+    //! ```
+    unwrap_once(url, true)
+}
+
+fn unwrap_once(url: &str, parameters: bool) -> Option<(Wrapper, String)> {
+    //! [`unwrap_link`], with or without the parameter heuristic.
+    let parsed = match Url::parse(url) {
+        Err(ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{url}")).ok()?,
+        parsed => parsed.ok()?,
+    };
+    let host = parsed.host_str()?.to_ascii_lowercase();
+    let param = |name: &str| parsed.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+    let (wrapper, inner) = match host.as_str() {
+        "urldefense.proofpoint.com" | "urldefense.com" if parsed.path().starts_with("/v1/") => {
+            (Wrapper::ProofpointV1, param("u")?)
+        }
+        "urldefense.proofpoint.com" | "urldefense.com" if parsed.path().starts_with("/v2/") => {
+            let u = param("u")?.replace('-', "%").replace('_', "/");
+            (Wrapper::ProofpointV2, percent_decode_str(&u).decode_utf8().ok()?.into_owned())
+        }
+        "urldefense.proofpoint.com" | "urldefense.com" if parsed.path().starts_with("/v3/") => {
+            (Wrapper::ProofpointV3, proofpoint_v3(url)?)
+        }
+        h if h == "safelinks.protection.outlook.com"
+            || h.ends_with(".safelinks.protection.outlook.com")
+            || h.ends_with(".safelinks.protection.office365.us") =>
+        {
+            (Wrapper::SafeLinks, param("url")?)
+        }
+        "linkprotect.cudasvc.com" => (Wrapper::Barracuda, param("a")?),
+        "secure-web.cisco.com" => {
+            // the first path segment is the token, the rest the link
+            let (_, encoded) = parsed.path().trim_start_matches('/').split_once('/')?;
+            (Wrapper::Cisco, percent_decode_str(encoded).decode_utf8().ok()?.into_owned())
+        }
+        _ => return redirector(&parsed, parameters),
+    };
+    web_url(&inner).map(|inner| (wrapper, inner))
+}
+
+pub(crate) fn unwrap_chain(url: &str, parameters: bool) -> Vec<String> {
+    //! The links carried by nested wrappers and redirectors, outermost first;
+    //! empty when the link isn't wrapped.
+    let mut chain: Vec<String> = vec![];
+    while chain.len() < MAX_LAYERS {
+        let current = chain.last().map_or(url, String::as_str);
+        match unwrap_once(current, parameters) {
+            Some((_, inner)) if inner != current => chain.push(inner),
+            _ => break,
+        }
+    }
+    chain
+}
+
+fn redirector(url: &Url, parameters: bool) -> Option<(Wrapper, String)> {
+    //! The destination in a query parameter: the known redirectors, then the
+    //! heuristic.
+    let host = url.host_str()?.to_ascii_lowercase();
+    let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).and_then(|(_, v)| web_url(&v));
+    let known = REDIRECTORS.iter().find(|(domain, path, _)| {
+        let domain_matches = match *domain {
+            "google.*" => is_google(&host),
+            domain => host_matches(&host, domain),
+        };
+        domain_matches && url.path().starts_with(path)
+    });
+    if let Some((_, _, names)) = known {
+        return names.iter().find_map(|name| param(name)).map(|inner| (Wrapper::Redirector, inner));
+    }
+    if !parameters {
+        return None;
+    }
+    // a link to the same site, such as a login page's return URL, is no redirect
+    let site = |host: &str| host.strip_prefix("www.").unwrap_or(host).to_string();
+    url.query_pairs()
+        .filter(|(k, _)| PARAMETERS.iter().any(|p| k.eq_ignore_ascii_case(p)))
+        .find_map(|(_, v)| web_url(&v).or_else(|| web_url(&decode_percent(&v))))
+        .filter(|inner| {
+            let inner_host = Url::parse(inner).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+            inner_host.is_some_and(|inner_host| site(&inner_host) != site(&host))
+        })
+        .map(|inner| (Wrapper::Parameter, inner))
+}
+
+fn is_google(host: &str) -> bool {
+    //! Whether a host is a Google search domain, such as `www.google.co.uk`.
+    let host = host.trim_end_matches('.');
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    host.strip_prefix("google.").is_some_and(|tld| GOOGLE.contains(&tld))
+}
+
+pub(crate) fn web_url(candidate: &str) -> Option<String> {
+    //! An absolute `http(s)` URL with a host, normalized.
+    let url = Url::parse(candidate.trim()).ok()?;
+    (matches!(url.scheme(), "http" | "https") && url.host_str().is_some()).then(|| url.into())
+}
+
+fn proofpoint_v3(url: &str) -> Option<String> {
+    //! Decodes a v3 link: the characters replaced with `*` (one) or `**<c>`
+    //! (a run) are base64 encoded after the `__;` marker.
+    let caps = RE_PROOFPOINT_V3.captures(url)?;
+    let mut link = caps[1].to_string();
+    if let Some(single) = RE_SINGLE_SLASH.captures(&link) {
+        link = format!("{}/{}", &single[1], &single[2]);
+    }
+    let encoded = match &caps[2] {
+        "" => vec![],
+        bytes => decode_base64(bytes)?.chars().collect(),
+    };
+    let mut replacements = encoded.into_iter();
+    let mut decoded = String::with_capacity(link.len());
+    let mut chars = link.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '*' {
+            decoded.push(c);
+        } else if chars.next_if_eq(&'*').is_some() {
+            let run = V3_RUNS.find(chars.next()?)? + 2;
+            for _ in 0..run {
+                decoded.push(replacements.next()?);
+            }
+        } else {
+            decoded.push(replacements.next()?);
+        }
+    }
+    Some(decoded)
+}
@@ -0,0 +1,142 @@
+//! adf.ly `ysmm` decoding.
+//!
+//! adf.ly and its family domains (atominik.com, fumacrom.com, intamema.com,
+//! j.gs, q.gs, ...) answer with an interstitial page instead of a redirect;
+//! the destination is scrambled in the `ysmm` variable of its script:
+//!
+//! - the characters at even positions, followed by the characters at odd
+//!   positions in reverse order, form a base64 string
+//! - current pages also XOR each pair of consecutive digits of that string,
+//!   and wrap the destination in 16 characters of noise on each side, often as
+//!   the `dest` parameter of an adf.ly URL
+//! - previous pages only prefix the destination with 2 characters of noise
+//!
+//! Pages are untrusted input: decoding never panics, and reports why it failed
+//! with a [`YsmmError`].
+use regex::Regex;
+use std::sync::LazyLock;
+use url::Url;
+
+use crate::decode::{decode_base64, decode_percent};
+
+static RE_YSMM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\bysmm\s*=\s*(?:'([^'\n]*)'|"([^"\n]*)")"#).expect("valid ysmm regex")
+});
+
+/// Noise around the destination in the current variant.
+const NOISE: usize = 16;
+/// Noise before the destination in the previous variant.
+const PREVIOUS_NOISE: usize = 2;
+
+/// Why a `ysmm` value couldn't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum YsmmError {
+    /// The page has no `ysmm` variable
+    #[error("no ysmm variable")]
+    Missing,
+    /// The value doesn't unscramble to base64 encoded text
+    #[error("ysmm is not base64 text")]
+    Encoding,
+    /// The decoded payload is shorter than its noise
+    #[error("ysmm payload too short")]
+    TooShort,
+    /// The payload holds no `http(s)` destination
+    #[error("no destination in ysmm")]
+    NoDestination,
+}
+
+pub fn find_ysmm(html: &str) -> Result<&str, YsmmError> {
+    //! The value of the `ysmm` variable of an adf.ly page.
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::{find_ysmm, YsmmError};
+    //!
+    //! assert_eq!(find_ysmm("<script>var ysmm = 'aHR0';</script>"), Ok("aHR0"));
+    //! assert_eq!(find_ysmm("<p>no ad today</p>"), Err(YsmmError::Missing));
+    //! ```
+    let caps = RE_YSMM.captures(html).ok_or(YsmmError::Missing)?;
+    let value = caps.get(1).or_else(|| caps.get(2)).ok_or(YsmmError::Missing)?.as_str().trim();
+    if value.is_empty() {
+        return Err(YsmmError::Missing);
+    }
+    Ok(value)
+}
+
+pub fn decode_ysmm(ysmm: &str) -> Result<String, YsmmError> {
+    //! Decodes a `ysmm` value into the destination URL.
+    //!
+    //! # Arguments
+    //!
+    //! * `ysmm` - The value of the `ysmm` variable (see [`find_ysmm`])
+    //!
+    //! # Returns
+    //!
+    //! Returns the `http(s)` destination, or the reason the current variant
+    //! couldn't be decoded when neither variant applies.
+    //!
+    //! # Behavior
+    //!
+    //! - The current variant is tried first, then the previous one
+    //! - A destination carried in a `dest` parameter is percent-decoded
+    //!
+    //! ## Example
+    //! ```rust
+    //! use urlexpand::decode_ysmm;
+    //!
+    //! let ysmm = "elTdBWoYdwH9RCwZcsz9o2vLL02VVm4bYuWU1Gwb";
+    //! assert_eq!(decode_ysmm(ysmm).unwrap(), "https://example.net/old/page");
+    //! assert!(decode_ysmm("!").is_err());
+    //! ```
+    let chars = ysmm.trim().chars().collect::<Vec<_>>();
+    if chars.is_empty() {
+        return Err(YsmmError::Missing);
+    }
+    // even positions, then odd positions backwards
+    let unscrambled = chars
+        .iter()
+        .step_by(2)
+        .chain(chars.iter().skip(1).step_by(2).rev())
+        .copied()
+        .collect::<Vec<_>>();
+    current(unscrambled.clone()).or_else(|e| previous(&unscrambled).map_err(|_| e))
+}
+
+fn current(mut data: Vec<char>) -> Result<String, YsmmError> {
+    //! Decodes the current variant: digit pairs XORed, 16 characters of noise
+    //! on each side.
+    let digits = data
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| Some((i, c.to_digit(10)?)))
+        .collect::<Vec<_>>();
+    for pair in digits.chunks_exact(2) {
+        let xor = pair[0].1 ^ pair[1].1;
+        if let Some(c) = char::from_digit(xor, 10) {
+            data[pair[0].0] = c;
+        }
+    }
+    let payload = decode_base64(&data.into_iter().collect::<String>()).ok_or(YsmmError::Encoding)?;
+    let end = payload.len().checked_sub(NOISE).filter(|&end| end > NOISE).ok_or(YsmmError::TooShort)?;
+    destination(payload.get(NOISE..end).ok_or(YsmmError::Encoding)?)
+}
+
+fn previous(data: &[char]) -> Result<String, YsmmError> {
+    //! Decodes the previous variant: 2 characters of noise first.
+    let payload = decode_base64(&data.iter().collect::<String>()).ok_or(YsmmError::Encoding)?;
+    destination(payload.get(PREVIOUS_NOISE..).filter(|s| !s.is_empty()).ok_or(YsmmError::TooShort)?)
+}
+
+fn destination(payload: &str) -> Result<String, YsmmError> {
+    //! The destination in a decoded payload: its `dest` parameter, or the
+    //! payload itself.
+    let url = match payload.split_once("dest=") {
+        Some((_, dest)) => decode_percent(dest.split('&').next().unwrap_or_default()),
+        None => payload.to_string(),
+    };
+    let url = url.trim();
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host_str().is_some() => Ok(url.into()),
+        _ => Err(YsmmError::NoDestination),
+    }
+}